@@ -1,11 +1,39 @@
 use async_trait::async_trait;
+use std::collections::HashSet;
 use std::error::Error;
-use reqwest;
-use crate::cex_api::CexApi;
+use std::time::Duration;
+use serde_json::Value;
+use crate::cex_api::{CexApi, ErrorMatcher, RateLimitBan, ServerError};
+use crate::config::{ApiCredentials, ErrorSubstrings};
+use crate::diff_book::DepthUpdate;
+use crate::order_book::{parse_levels, OrderBook};
 use crate::ticker::Ticker;
 
+/// The response substrings that mark an error response.
+const ERROR_SUBSTRINGS: [&str; 1] = [r#""code":-"#];
+
+/// The host of the production spot REST API, used unless a base URL is configured.
+pub const DEFAULT_BASE_URL: &str = "https://api.binance.com";
+
 /// Represents the Binance API for fetching order book data.
-pub struct BinanceApi;
+///
+/// All requests go through one shared `reqwest::Client`, so connections to the exchange are pooled
+/// across every ticker collected from it.
+pub struct BinanceApi {
+    client: reqwest::Client,
+    errors: ErrorMatcher,
+    /// The API key sent as `X-MBX-APIKEY`, if one is configured.
+    api_key: Option<String>,
+    /// The scheme and host of the spot REST endpoints, without a trailing slash.
+    base_url: String,
+}
+
+impl Default for BinanceApi {
+    /// Creates a `BinanceApi` with a default `reqwest::Client`.
+    fn default() -> Self {
+        BinanceApi::new(reqwest::Client::new())
+    }
+}
 
 #[async_trait]
 impl CexApi for BinanceApi {
@@ -17,6 +45,11 @@ impl CexApi for BinanceApi {
         "BINANCE"
     }
 
+    /// Returns the Binance symbol of a ticker, the base and quote concatenated, e.g. `BTCUSDT`.
+    fn format_symbol(&self, ticker: &Ticker) -> String {
+        format!("{}{}", ticker.base, ticker.quote)
+    }
+
     /// Asynchronously fetches the order book for a given ticker and depth from Binance.
     ///
     /// # Arguments
@@ -26,21 +59,166 @@ impl CexApi for BinanceApi {
     /// # Returns
     /// A `Result` which is either a string containing the order book data or an error.
     async fn get_order_book(&self, ticker: &Ticker, depth: u32) -> Result<String, Box<dyn Error>> {
-        let symbol = format!("{}{}", ticker.base, ticker.quote);
-        let response_text = reqwest::get(&format!(
-            "https://api.binance.com/api/v3/depth?symbol={}&limit={}",
-            symbol, depth
-        )).await?
-            .text()
-            .await?;
+        let symbol = self.format_symbol(ticker);
+        let mut request = self.client.get(format!(
+            "{}/api/v3/depth?symbol={}&limit={}",
+            self.base_url, symbol, depth
+        ));
+        if let Some(api_key) = &self.api_key {
+            request = request.header("X-MBX-APIKEY", api_key);
+        }
+        let response = request.send().await?;
+        BinanceApi::check_ban(&response)?;
+        ServerError::check(&response)?;
+        let response_text = response.text().await?;
 
-        if response_text.contains(r#""code":-"#) {
+        if self.errors.is_error(&response_text) {
             Err("Invalid symbol in response from Binance".into())
         } else {
             Ok(response_text)
         }
     }
 
+    /// Asynchronously fetches the mark and index prices of a USDⓈ-M perpetual from Binance futures.
+    ///
+    /// # Arguments
+    /// * `ticker` - A reference to a `Ticker` struct containing the base and quote currencies.
+    ///
+    /// # Returns
+    /// A `Result` which is either a string containing the premium index data or an error.
+    async fn get_mark_price(&self, ticker: &Ticker) -> Result<String, Box<dyn Error>> {
+        let symbol = self.format_symbol(ticker);
+        let response = self.client.get(format!(
+            "https://fapi.binance.com/fapi/v1/premiumIndex?symbol={}",
+            symbol
+        )).send().await?;
+        BinanceApi::check_ban(&response)?;
+        ServerError::check(&response)?;
+        let response_text = response.text().await?;
+
+        if self.errors.is_error(&response_text) {
+            Err("Invalid symbol in response from Binance futures".into())
+        } else {
+            Ok(response_text)
+        }
+    }
+
+    /// Returns the Binance USDⓈ-M futures `@forceOrder` stream of a contract.
+    ///
+    /// # Arguments
+    /// * `ticker` - A reference to a `Ticker` struct containing the base and quote currencies.
+    fn liquidation_stream_url(&self, ticker: &Ticker) -> Option<String> {
+        Some(format!(
+            "wss://fstream.binance.com/ws/{}@forceOrder",
+            self.format_symbol(ticker).to_lowercase()
+        ))
+    }
+
+    /// Returns the Binance spot `@depth<levels>@100ms` partial book depth stream of a symbol.
+    ///
+    /// The stream only offers 5, 10 or 20 levels, so the smallest of them covering `depth` is used,
+    /// or 20 for deeper requests.
+    ///
+    /// # Arguments
+    /// * `ticker` - A reference to a `Ticker` struct containing the base and quote currencies.
+    /// * `depth` - The requested depth of each snapshot.
+    fn depth_stream_url(&self, ticker: &Ticker, depth: u32) -> Option<String> {
+        let levels = [5, 10, 20].into_iter().find(|levels| *levels >= depth).unwrap_or(20);
+        Some(format!(
+            "wss://stream.binance.com:9443/ws/{}@depth{}@100ms",
+            self.format_symbol(ticker).to_lowercase(), levels
+        ))
+    }
+
+    /// Returns the Binance spot `@depth@100ms` diff depth stream of a symbol.
+    ///
+    /// # Arguments
+    /// * `ticker` - A reference to a `Ticker` struct containing the base and quote currencies.
+    fn diff_stream_url(&self, ticker: &Ticker) -> Option<String> {
+        Some(format!(
+            "wss://stream.binance.com:9443/ws/{}@depth@100ms",
+            self.format_symbol(ticker).to_lowercase()
+        ))
+    }
+
+    /// Parses a Binance `depthUpdate` event, e.g.
+    /// `{"e": "depthUpdate", "E": 1700000000123, "U": 157, "u": 160, "b": [["0.0024", "10"]], "a": []}`.
+    ///
+    /// # Arguments
+    /// * `raw` - The raw JSON text of the stream message.
+    ///
+    /// # Returns
+    /// A `Result` which is either the `DepthUpdate` or an error.
+    fn parse_depth_update(&self, raw: &str) -> Result<DepthUpdate, Box<dyn Error>> {
+        let json: Value = serde_json::from_str(raw)?;
+        let final_update_id = json["u"].as_u64().ok_or("Missing final update id in depth update")?;
+        Ok(DepthUpdate {
+            first_update_id: json["U"].as_u64().ok_or("Missing first update id in depth update")?,
+            final_update_id,
+            changes: OrderBook {
+                bids: parse_levels(&json["b"])?,
+                asks: parse_levels(&json["a"])?,
+                exchange_ts: json["E"].as_i64(),
+                sequence: Some(final_update_id),
+                requested_depth: None,
+            },
+        })
+    }
+
+    /// Returns the `lastUpdateId` of a Binance depth response.
+    ///
+    /// # Arguments
+    /// * `raw` - The raw JSON response text.
+    fn parse_snapshot_update_id(&self, raw: &str) -> Result<u64, Box<dyn Error>> {
+        let json: Value = serde_json::from_str(raw)?;
+        Ok(json["lastUpdateId"].as_u64().ok_or("Missing lastUpdateId in depth response")?)
+    }
+
+    /// Asynchronously checks the Binance system status endpoint for a maintenance window.
+    ///
+    /// # Returns
+    /// `true` if Binance reports status `1` (system maintenance), `false` otherwise or if the status is unavailable.
+    async fn is_in_maintenance(&self) -> bool {
+        let response = match self.client.get(format!("{}/sapi/v1/system/status", self.base_url)).send().await {
+            Ok(response) => response,
+            Err(_) => return false,
+        };
+        match response.json::<Value>().await {
+            Ok(status) => BinanceApi::is_maintenance_status(&status),
+            Err(_) => false,
+        }
+    }
+
+    /// Asynchronously fetches the symbols currently trading on Binance from `exchangeInfo`.
+    ///
+    /// # Returns
+    /// A `Result` which is either the trading symbols in "BASE_QUOTE" form or an error.
+    async fn load_symbols(&self) -> Result<Option<HashSet<String>>, Box<dyn Error>> {
+        let exchange_info: Value = self.client.get(format!("{}/api/v3/exchangeInfo", self.base_url)).send()
+            .await?
+            .json()
+            .await?;
+        Ok(Some(BinanceApi::parse_symbols(&exchange_info)?))
+    }
+
+    /// Parses a Binance depth response, whose `bids` and `asks` are arrays of `[price, qty]` strings.
+    ///
+    /// # Arguments
+    /// * `raw` - The raw JSON response text.
+    ///
+    /// # Returns
+    /// A `Result` which is either the normalized `OrderBook` or an error.
+    fn parse_order_book(&self, raw: &str) -> Result<OrderBook, Box<dyn Error>> {
+        let json: Value = serde_json::from_str(raw)?;
+        Ok(OrderBook {
+            bids: parse_levels(&json["bids"])?,
+            asks: parse_levels(&json["asks"])?,
+            exchange_ts: None,
+            sequence: json["lastUpdateId"].as_u64(),
+            requested_depth: None,
+        })
+    }
+
     /// Returns the interval at which the order book should be fetched.
     ///
     /// # Returns
@@ -48,25 +226,139 @@ impl CexApi for BinanceApi {
     fn get_order_book_interval(&self) -> u64 {
         1
     }
+
+    /// Returns Binance's request weight limit of 6000 per minute, spread evenly over each second.
+    fn rate_limit(&self) -> u32 {
+        100
+    }
+
+    /// Returns the weight of a `/api/v3/depth` request, which grows with the requested depth.
+    fn request_weight(&self, depth: u32) -> u32 {
+        match depth {
+            0..=50 => 1,
+            51..=100 => 5,
+            101..=500 => 25,
+            501..=1000 => 50,
+            _ => 250,
+        }
+    }
+}
+
+impl BinanceApi {
+    /// Creates a new `BinanceApi` sending its requests through `client`.
+    pub fn new(client: reqwest::Client) -> BinanceApi {
+        BinanceApi {
+            client,
+            errors: ErrorMatcher::new(&ERROR_SUBSTRINGS, None),
+            api_key: None,
+            base_url: DEFAULT_BASE_URL.to_string(),
+        }
+    }
+
+    /// Returns the API sending its spot REST requests to `base_url`, if set, e.g. `https://testnet.binance.vision`.
+    ///
+    /// Futures and stream endpoints keep their production hosts.
+    pub fn with_base_url(mut self, base_url: Option<&str>) -> BinanceApi {
+        self.base_url = base_url.unwrap_or(DEFAULT_BASE_URL).trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Returns the API sending the key of `credentials`, if any, with its order book requests.
+    ///
+    /// Market data needs no signature, so the secret is not used.
+    pub fn with_credentials(mut self, credentials: Option<&ApiCredentials>) -> BinanceApi {
+        self.api_key = credentials.map(|credentials| credentials.api_key.clone());
+        self
+    }
+
+    /// Returns the API with its error-detection substrings extended or replaced by `overrides`.
+    pub fn with_error_substrings(mut self, overrides: Option<&ErrorSubstrings>) -> BinanceApi {
+        self.errors = ErrorMatcher::new(&ERROR_SUBSTRINGS, overrides);
+        self
+    }
+
+    /// Returns whether a Binance system status response (`{"status": 0, "msg": "normal"}`) reports maintenance.
+    fn is_maintenance_status(status: &Value) -> bool {
+        status["status"].as_i64() == Some(1)
+    }
+
+    /// Returns a `RateLimitBan` error if Binance answered with HTTP 418, its IP ban for repeated 429s.
+    fn check_ban(response: &reqwest::Response) -> Result<(), RateLimitBan> {
+        let retry_after = response.headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|value| value.to_str().ok());
+        match BinanceApi::ban_from_status(response.status().as_u16(), retry_after) {
+            Some(ban) => Err(ban),
+            None => Ok(()),
+        }
+    }
+
+    /// Interprets a response status and `Retry-After` header (in seconds) as a rate limit ban.
+    fn ban_from_status(status: u16, retry_after: Option<&str>) -> Option<RateLimitBan> {
+        if status != 418 {
+            return None;
+        }
+        let retry_after = retry_after
+            .and_then(|seconds| seconds.trim().parse::<u64>().ok())
+            .map(Duration::from_secs);
+        Some(RateLimitBan { retry_after })
+    }
+
+    /// Extracts the symbols with status `TRADING` from an `exchangeInfo` response, in "BASE_QUOTE" form.
+    fn parse_symbols(exchange_info: &Value) -> Result<HashSet<String>, Box<dyn Error>> {
+        let symbols = exchange_info["symbols"].as_array().ok_or("Missing symbols in exchangeInfo response")?;
+        Ok(symbols
+            .iter()
+            .filter(|symbol| symbol["status"] == "TRADING")
+            .filter_map(|symbol| {
+                let base = symbol["baseAsset"].as_str()?;
+                let quote = symbol["quoteAsset"].as_str()?;
+                Some(format!("{}_{}", base, quote))
+            })
+            .collect())
+    }
 }
 
 // Unit tests for the BinanceApi implementation
 #[cfg(test)]
 mod tests {
     use super::*;
-    use serde_json::Value;
+    use wiremock::matchers::{method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
 
     #[test]
     fn test_binance_api_name() {
-        assert_eq!(BinanceApi.name(), "BINANCE");
+        assert_eq!(BinanceApi::default().name(), "BINANCE");
     }
 
-    #[tokio::test]
-    async fn test_get_order_book() {
-        // Mock HTTP requests setup would go here
+    #[test]
+    fn test_format_symbol() {
+        let ticker = Ticker::new("BTC_USDT").unwrap();
+        assert_eq!(BinanceApi::default().format_symbol(&ticker), "BTCUSDT");
+    }
+
+    /// Returns a `BinanceApi` sending its requests to `server`, bypassing any proxy set in the environment.
+    fn mock_api(server: &MockServer) -> BinanceApi {
+        let client = reqwest::Client::builder().no_proxy().build().unwrap();
+        BinanceApi::new(client).with_base_url(Some(&server.uri()))
+    }
+
+    /// Mounts a response to the depth request of `BTCUSDT` with 10 levels.
+    async fn mock_depth(server: &MockServer, response: ResponseTemplate) {
+        Mock::given(method("GET"))
+            .and(path("/api/v3/depth"))
+            .and(query_param("symbol", "BTCUSDT"))
+            .and(query_param("limit", "10"))
+            .respond_with(response)
+            .mount(server)
+            .await;
+    }
 
+    #[tokio::test]
+    #[ignore = "hits the live Binance API"]
+    async fn test_get_order_book_live() {
         let ticker = Ticker::new("BTC_USDT").unwrap();
-        let result = BinanceApi.get_order_book(&ticker, 10).await;
+        let result = BinanceApi::default().get_order_book(&ticker, 10).await;
 
         assert!(result.is_ok());
         if let Ok(response_text) = result {
@@ -76,8 +368,128 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_get_order_book() {
+        let server = MockServer::start().await;
+        let body = r#"{"lastUpdateId":1,"bids":[["42000.10","0.50"]],"asks":[["42000.20","0.25"]]}"#;
+        mock_depth(&server, ResponseTemplate::new(200).set_body_string(body)).await;
+
+        let ticker = Ticker::new("BTC_USDT").unwrap();
+        assert_eq!(mock_api(&server).get_order_book(&ticker, 10).await.unwrap(), body);
+        assert_eq!(BinanceApi::default().with_base_url(None).base_url, DEFAULT_BASE_URL);
+    }
+
+    #[tokio::test]
+    async fn test_get_order_book_error_response() {
+        let server = MockServer::start().await;
+        let body = r#"{"code":-1121,"msg":"Invalid symbol."}"#;
+        mock_depth(&server, ResponseTemplate::new(400).set_body_string(body)).await;
+
+        let ticker = Ticker::new("BTC_USDT").unwrap();
+        let error = mock_api(&server).get_order_book(&ticker, 10).await.unwrap_err();
+        assert_eq!(error.to_string(), "Invalid symbol in response from Binance");
+    }
+
+    #[tokio::test]
+    async fn test_get_order_book_server_error() {
+        let server = MockServer::start().await;
+        mock_depth(&server, ResponseTemplate::new(503).set_body_string("<html>Service Unavailable</html>")).await;
+
+        let ticker = Ticker::new("BTC_USDT").unwrap();
+        let error = mock_api(&server).get_order_book(&ticker, 10).await.unwrap_err();
+        assert_eq!(error.downcast_ref::<ServerError>(), Some(&ServerError { status: 503 }));
+    }
+
+    #[test]
+    fn test_parse_order_book() {
+        let raw = r#"{"lastUpdateId":1,"bids":[["42000.10","0.50"],["42000.00","1.00"]],"asks":[["42000.20","0.25"]]}"#;
+        let book = BinanceApi::default().parse_order_book(raw).unwrap();
+
+        assert_eq!(book.bids, vec![(42000.10, 0.50), (42000.00, 1.00)]);
+        assert_eq!(book.asks, vec![(42000.20, 0.25)]);
+        assert_eq!(book.exchange_ts, None);
+        assert_eq!(book.sequence, Some(1));
+    }
+
+    #[test]
+    fn test_is_maintenance_status() {
+        let normal: Value = serde_json::from_str(r#"{"status":0,"msg":"normal"}"#).unwrap();
+        let maintenance: Value = serde_json::from_str(r#"{"status":1,"msg":"system_maintenance"}"#).unwrap();
+        assert!(!BinanceApi::is_maintenance_status(&normal));
+        assert!(BinanceApi::is_maintenance_status(&maintenance));
+    }
+
+    #[test]
+    fn test_parse_symbols_keeps_trading_symbols() {
+        let exchange_info: Value = serde_json::from_str(r#"{"symbols":[
+            {"symbol":"BTCUSDT","status":"TRADING","baseAsset":"BTC","quoteAsset":"USDT"},
+            {"symbol":"LUNAUSDT","status":"BREAK","baseAsset":"LUNA","quoteAsset":"USDT"}
+        ]}"#).unwrap();
+        let symbols = BinanceApi::parse_symbols(&exchange_info).unwrap();
+        assert_eq!(symbols, HashSet::from(["BTC_USDT".to_string()]));
+        assert!(BinanceApi::parse_symbols(&Value::Null).is_err());
+    }
+
+    #[test]
+    fn test_ban_from_status() {
+        assert_eq!(BinanceApi::ban_from_status(200, None), None);
+        assert_eq!(BinanceApi::ban_from_status(429, Some("30")), None);
+        assert_eq!(
+            BinanceApi::ban_from_status(418, Some("120")),
+            Some(RateLimitBan { retry_after: Some(Duration::from_secs(120)) })
+        );
+        assert_eq!(BinanceApi::ban_from_status(418, None), Some(RateLimitBan { retry_after: None }));
+    }
+
+    #[test]
+    fn test_depth_stream_url() {
+        let api = BinanceApi::default();
+        let ticker = Ticker::new("BTC_USDT").unwrap();
+        assert_eq!(
+            api.depth_stream_url(&ticker, 10).unwrap(),
+            "wss://stream.binance.com:9443/ws/btcusdt@depth10@100ms"
+        );
+        assert!(api.depth_stream_url(&ticker, 3).unwrap().ends_with("@depth5@100ms"));
+        assert!(api.depth_stream_url(&ticker, 100).unwrap().ends_with("@depth20@100ms"));
+    }
+
+    #[test]
+    fn test_parse_depth_update_and_snapshot_id() {
+        let api = BinanceApi::default();
+        let update = api.parse_depth_update(
+            r#"{"e":"depthUpdate","E":1700000000123,"s":"BNBBTC","U":157,"u":160,"b":[["0.0024","10"]],"a":[["0.0026","0"]]}"#
+        ).unwrap();
+        assert_eq!(update.first_update_id, 157);
+        assert_eq!(update.final_update_id, 160);
+        assert_eq!(update.changes.bids, vec![(0.0024, 10.0)]);
+        assert_eq!(update.changes.asks, vec![(0.0026, 0.0)]);
+        assert_eq!(update.changes.exchange_ts, Some(1700000000123));
+        assert_eq!(update.changes.sequence, Some(160));
+        assert!(api.parse_depth_update(r#"{"result":null,"id":1}"#).is_err());
+
+        assert_eq!(api.parse_snapshot_update_id(r#"{"lastUpdateId":1027024,"bids":[],"asks":[]}"#).unwrap(), 1027024);
+    }
+
+    #[test]
+    fn test_liquidation_stream_url() {
+        let ticker = Ticker::new("BTC_USDT").unwrap();
+        assert_eq!(
+            BinanceApi::default().liquidation_stream_url(&ticker).as_deref(),
+            Some("wss://fstream.binance.com/ws/btcusdt@forceOrder")
+        );
+    }
+
+    #[test]
+    fn test_request_weight_grows_with_depth() {
+        let api = BinanceApi::default();
+        assert_eq!(api.request_weight(10), 1);
+        assert_eq!(api.request_weight(100), 5);
+        assert_eq!(api.request_weight(1000), 50);
+        assert_eq!(api.request_weight(5000), 250);
+    }
+
     #[test]
     fn test_get_order_book_interval() {
-        assert_eq!(BinanceApi.get_order_book_interval(), 1);
+        assert_eq!(BinanceApi::default().get_order_book_interval(), 1);
     }
 }
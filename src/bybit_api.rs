@@ -0,0 +1,267 @@
+use async_trait::async_trait;
+use std::error::Error;
+use serde_json::Value;
+use crate::cex_api::{CexApi, ErrorMatcher, ServerError};
+use crate::config::ErrorSubstrings;
+use crate::order_book::{parse_levels, OrderBook};
+use crate::ticker::Ticker;
+
+/// The response substrings that mark an error response. Bybit errors are detected from the
+/// `retCode` field of the response, so none are built in; the `error_substrings` config can add some.
+const ERROR_SUBSTRINGS: [&str; 0] = [];
+
+/// The host of the production REST API, used unless a base URL is configured.
+pub const DEFAULT_BASE_URL: &str = "https://api.bybit.com";
+
+/// The largest `limit` of a spot order book request.
+pub const MAX_SPOT_DEPTH: u32 = 200;
+
+/// Represents the Bybit spot API for fetching order book data.
+///
+/// All requests go through one shared `reqwest::Client`, so connections to the exchange are pooled
+/// across every ticker collected from it.
+pub struct BybitApi {
+    client: reqwest::Client,
+    errors: ErrorMatcher,
+    /// The scheme and host of the REST endpoints, without a trailing slash.
+    base_url: String,
+}
+
+impl Default for BybitApi {
+    /// Creates an `BybitApi` with a default `reqwest::Client`.
+    fn default() -> Self {
+        BybitApi::new(reqwest::Client::new())
+    }
+}
+
+#[async_trait]
+impl CexApi for BybitApi {
+    /// Returns the name of the exchange.
+    ///
+    /// # Returns
+    /// A static string slice representing the name of the exchange.
+    fn name(&self) -> &'static str {
+        "BYBIT"
+    }
+
+    /// Returns the Bybit symbol of a ticker, e.g. `BTCUSDT`.
+    fn format_symbol(&self, ticker: &Ticker) -> String {
+        format!("{}{}", ticker.base, ticker.quote)
+    }
+
+    /// Asynchronously fetches the spot order book for a given ticker and depth from Bybit.
+    ///
+    /// The depth is capped at the spot maximum of 200 levels. A response whose `retCode` is not `0` is an error.
+    ///
+    /// # Arguments
+    /// * `ticker` - A reference to a `Ticker` struct containing the base and quote currencies.
+    /// * `depth` - The depth of the order book to fetch.
+    ///
+    /// # Returns
+    /// A `Result` which is either a string containing the order book data or an error.
+    async fn get_order_book(&self, ticker: &Ticker, depth: u32) -> Result<String, Box<dyn Error>> {
+        let response = self.client.get(format!(
+            "{}/v5/market/orderbook?category=spot&symbol={}&limit={}",
+            self.base_url, self.format_symbol(ticker), depth.min(MAX_SPOT_DEPTH)
+        )).send().await?;
+        ServerError::check(&response)?;
+        let response_text = response.text().await?;
+
+        if self.errors.is_error(&response_text) {
+            return Err("Error in response from Bybit".into());
+        }
+        let json: Value = serde_json::from_str(&response_text)?;
+        BybitApi::check_code(&json)?;
+        Ok(response_text)
+    }
+
+    /// Parses a Bybit order book response, e.g.
+    /// `{"retCode": 0, "retMsg": "OK", "result": {"s": "BTCUSDT", "a": [["42000.2", "0.25"]], "b": [...], "ts": 1700000000123, "u": 18521288}}`.
+    ///
+    /// The `ts` of the book becomes its `exchange_ts` and its update id `u` its `sequence`.
+    ///
+    /// # Arguments
+    /// * `raw` - The raw JSON response text.
+    ///
+    /// # Returns
+    /// A `Result` which is either the normalized `OrderBook` or an error.
+    fn parse_order_book(&self, raw: &str) -> Result<OrderBook, Box<dyn Error>> {
+        let json: Value = serde_json::from_str(raw)?;
+        BybitApi::check_code(&json)?;
+        let book = json["result"].as_object().ok_or("Missing result in Bybit order book response")?;
+        Ok(OrderBook {
+            bids: parse_levels(&book["b"])?,
+            asks: parse_levels(&book["a"])?,
+            exchange_ts: book["ts"].as_i64(),
+            sequence: book["u"].as_u64(),
+            requested_depth: None,
+        })
+    }
+
+    /// Asynchronously fetches the mark and index prices of a USDT perpetual from Bybit's linear tickers, e.g.
+    /// `{"retCode": 0, "retMsg": "OK", "result": {"category": "linear", "list": [{"symbol": "BTCUSDT", "markPrice": "42000.5", "indexPrice": "42001.2", ...}]}}`.
+    ///
+    /// # Arguments
+    /// * `ticker` - A reference to a `Ticker` struct containing the base and quote currencies.
+    ///
+    /// # Returns
+    /// A `Result` which is either a string containing the ticker data or an error.
+    async fn get_mark_price(&self, ticker: &Ticker) -> Result<String, Box<dyn Error>> {
+        let response = self.client.get(format!(
+            "{}/v5/market/tickers?category=linear&symbol={}",
+            self.base_url, self.format_symbol(ticker)
+        )).send().await?;
+        ServerError::check(&response)?;
+        let response_text = response.text().await?;
+
+        let json: Value = serde_json::from_str(&response_text)?;
+        BybitApi::check_code(&json)?;
+        Ok(response_text)
+    }
+
+    /// Returns the interval at which the order book should be fetched.
+    ///
+    /// # Returns
+    /// A `u64` representing the interval in seconds.
+    fn get_order_book_interval(&self) -> u64 {
+        1
+    }
+
+    /// Returns Bybit's public limit of 600 requests per 5 seconds.
+    fn rate_limit(&self) -> u32 {
+        120
+    }
+}
+
+impl BybitApi {
+    /// Creates a new `BybitApi` sending its requests through `client`.
+    pub fn new(client: reqwest::Client) -> BybitApi {
+        BybitApi {
+            client,
+            errors: ErrorMatcher::new(&ERROR_SUBSTRINGS, None),
+            base_url: DEFAULT_BASE_URL.to_string(),
+        }
+    }
+
+    /// Returns the API sending its requests to `base_url`, if set, e.g. a local mock server.
+    pub fn with_base_url(mut self, base_url: Option<&str>) -> BybitApi {
+        self.base_url = base_url.unwrap_or(DEFAULT_BASE_URL).trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Returns the API with its error-detection substrings extended or replaced by `overrides`.
+    pub fn with_error_substrings(mut self, overrides: Option<&ErrorSubstrings>) -> BybitApi {
+        self.errors = ErrorMatcher::new(&ERROR_SUBSTRINGS, overrides);
+        self
+    }
+
+    /// Checks the `retCode` of a Bybit response, which is `0` on success.
+    ///
+    /// # Errors
+    ///
+    /// Returns the code and `retMsg` of the response if the code is anything else or missing.
+    fn check_code(json: &Value) -> Result<(), Box<dyn Error>> {
+        match json["retCode"].as_i64() {
+            Some(0) => Ok(()),
+            code => Err(format!(
+                "Bybit returned code {}: {}",
+                code.map_or("none".to_string(), |code| code.to_string()), json["retMsg"].as_str().unwrap_or("")
+            ).into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    /// Returns a `BybitApi` sending its requests to `server`, bypassing any proxy set in the environment.
+    fn mock_api(server: &MockServer) -> BybitApi {
+        let client = reqwest::Client::builder().no_proxy().build().unwrap();
+        BybitApi::new(client).with_base_url(Some(&server.uri()))
+    }
+
+    /// Mounts a response to the spot order book request of `BTCUSDT` with `limit` levels.
+    async fn mock_orderbook(server: &MockServer, limit: &str, body: &str) {
+        Mock::given(method("GET"))
+            .and(path("/v5/market/orderbook"))
+            .and(query_param("category", "spot"))
+            .and(query_param("symbol", "BTCUSDT"))
+            .and(query_param("limit", limit))
+            .respond_with(ResponseTemplate::new(200).set_body_string(body))
+            .mount(server)
+            .await;
+    }
+
+    #[test]
+    fn test_bybit_api_name() {
+        assert_eq!(BybitApi::default().name(), "BYBIT");
+    }
+
+    #[test]
+    fn test_format_symbol() {
+        let ticker = Ticker::new("BTC_USDT").unwrap();
+        assert_eq!(BybitApi::default().format_symbol(&ticker), "BTCUSDT");
+    }
+
+    #[tokio::test]
+    async fn test_get_order_book() {
+        let server = MockServer::start().await;
+        let body = r#"{"retCode":0,"retMsg":"OK","result":{"s":"BTCUSDT","a":[["42000.2","0.25"]],"b":[["42000.1","1.5"]],"ts":1700000000123,"u":18521288,"seq":7961638724},"time":1700000000130}"#;
+        mock_orderbook(&server, "10", body).await;
+
+        let ticker = Ticker::new("BTC_USDT").unwrap();
+        let response = mock_api(&server).get_order_book(&ticker, 10).await.unwrap();
+        assert_eq!(response, body);
+        let book = BybitApi::default().parse_order_book(&response).unwrap();
+        assert_eq!(book.bids, vec![(42000.1, 1.5)]);
+        assert_eq!(book.asks, vec![(42000.2, 0.25)]);
+        assert_eq!(book.exchange_ts, Some(1700000000123));
+        assert_eq!(book.sequence, Some(18521288));
+    }
+
+    #[tokio::test]
+    async fn test_get_order_book_clamps_depth() {
+        let server = MockServer::start().await;
+        let body = r#"{"retCode":0,"retMsg":"OK","result":{"s":"BTCUSDT","a":[],"b":[],"ts":1700000000123,"u":1}}"#;
+        mock_orderbook(&server, "200", body).await;
+
+        let ticker = Ticker::new("BTC_USDT").unwrap();
+        assert_eq!(mock_api(&server).get_order_book(&ticker, 1000).await.unwrap(), body);
+    }
+
+    #[tokio::test]
+    async fn test_get_order_book_error_code() {
+        let server = MockServer::start().await;
+        mock_orderbook(&server, "10", r#"{"retCode":10001,"retMsg":"Not supported symbols","result":{}}"#).await;
+
+        let ticker = Ticker::new("BTC_USDT").unwrap();
+        let error = mock_api(&server).get_order_book(&ticker, 10).await.unwrap_err();
+        assert_eq!(error.to_string(), "Bybit returned code 10001: Not supported symbols");
+    }
+
+    #[tokio::test]
+    async fn test_get_mark_price() {
+        let server = MockServer::start().await;
+        let body = r#"{"retCode":0,"retMsg":"OK","result":{"category":"linear","list":[{"symbol":"BTCUSDT","markPrice":"42000.5","indexPrice":"42001.2"}]},"time":1700000000130}"#;
+        Mock::given(method("GET"))
+            .and(path("/v5/market/tickers"))
+            .and(query_param("category", "linear"))
+            .and(query_param("symbol", "BTCUSDT"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(body))
+            .mount(&server)
+            .await;
+
+        let ticker = Ticker::new("BTC_USDT").unwrap();
+        assert_eq!(mock_api(&server).get_mark_price(&ticker).await.unwrap(), body);
+        assert!(mock_api(&server).get_mark_price(&Ticker::new("ETH_USDT").unwrap()).await.is_err());
+    }
+
+    #[test]
+    fn test_parse_order_book_without_result() {
+        assert!(BybitApi::default().parse_order_book(r#"{"retCode":0,"retMsg":"OK"}"#).is_err());
+        assert!(BybitApi::default().parse_order_book(r#"{"retMsg":"OK","result":{}}"#).is_err());
+    }
+}
@@ -1,15 +1,162 @@
 use async_trait::async_trait;
+use std::collections::HashSet;
 use std::error::Error;
+use std::fmt;
+use std::time::Duration;
+#[cfg(feature = "bitkub")]
+use hmac::{Hmac, Mac};
+#[cfg(feature = "bitkub")]
+use sha2::Sha256;
+use crate::config::ErrorSubstrings;
+use crate::diff_book::DepthUpdate;
+use crate::order_book::OrderBook;
 use crate::ticker::Ticker;
 
+/// The error returned when an exchange has banned the client for exceeding its rate limits.
+///
+/// Workers that receive it stop fetching from the exchange until the ban is over.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimitBan {
+    /// How long the exchange asked the client to wait, if it said so.
+    pub retry_after: Option<Duration>,
+}
+
+impl fmt::Display for RateLimitBan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.retry_after {
+            Some(retry_after) => write!(f, "Rate limit ban, retry after {} s", retry_after.as_secs()),
+            None => write!(f, "Rate limit ban"),
+        }
+    }
+}
+
+impl Error for RateLimitBan {}
+
+/// The number of characters of a malformed response kept in its error.
+const MALFORMED_PREVIEW_CHARS: usize = 120;
+
+/// The error returned when an exchange response is not valid JSON, such as an HTML error page or a truncated body.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MalformedResponse {
+    /// The start of the response, for the log.
+    pub preview: String,
+}
+
+impl MalformedResponse {
+    /// Checks that `response_text` parses as JSON, so it can be embedded into a record as is.
+    ///
+    /// # Returns
+    ///
+    /// The unchanged response, or a `MalformedResponse` with a preview of it.
+    pub fn check(response_text: String) -> Result<String, MalformedResponse> {
+        match serde_json::from_str::<serde_json::Value>(&response_text) {
+            Ok(_) => Ok(response_text),
+            Err(_) => Err(MalformedResponse { preview: response_text.chars().take(MALFORMED_PREVIEW_CHARS).collect() }),
+        }
+    }
+}
+
+impl fmt::Display for MalformedResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Malformed JSON response: {:?}", self.preview)
+    }
+}
+
+impl Error for MalformedResponse {}
+
+/// The error returned when an exchange answers with a 5xx status, whatever the body holds.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerError {
+    /// The HTTP status code of the response.
+    pub status: u16,
+}
+
+impl ServerError {
+    /// Checks that `response` does not have a server error status.
+    ///
+    /// # Returns
+    ///
+    /// `Ok` for any status below 500, or a `ServerError` carrying the status.
+    pub fn check(response: &reqwest::Response) -> Result<(), ServerError> {
+        let status = response.status();
+        if status.is_server_error() {
+            return Err(ServerError { status: status.as_u16() });
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Server error: HTTP {}", self.status)
+    }
+}
+
+impl Error for ServerError {}
+
+/// Signs `message` with HMAC-SHA256 under `secret`, as exchanges with signed endpoints require.
+///
+/// # Returns
+///
+/// The signature as lowercase hex.
+#[cfg(feature = "bitkub")]
+pub fn hmac_sha256_hex(secret: &str, message: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(message.as_bytes());
+    mac.finalize().into_bytes().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Detects error responses by the substrings they contain.
+///
+/// Each API starts from its built-in substrings, which the `error_substrings` config can extend or
+/// replace so a changed error shape can be handled without a new release. This is a stopgap
+/// superseded by structured error detection.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ErrorMatcher {
+    substrings: Vec<String>,
+}
+
+impl ErrorMatcher {
+    /// Creates an `ErrorMatcher` from built-in substrings and optional config overrides.
+    ///
+    /// # Arguments
+    ///
+    /// * `defaults` - The built-in error substrings of the exchange.
+    /// * `overrides` - The substrings configured for the exchange, if any.
+    pub fn new(defaults: &[&str], overrides: Option<&ErrorSubstrings>) -> ErrorMatcher {
+        let mut substrings: Vec<String> = match overrides {
+            Some(overrides) if overrides.replace => Vec::new(),
+            _ => defaults.iter().map(|substring| substring.to_string()).collect(),
+        };
+        if let Some(overrides) = overrides {
+            substrings.extend(overrides.substrings.iter().cloned());
+        }
+        ErrorMatcher { substrings }
+    }
+
+    /// Returns whether `response_text` contains any of the error substrings.
+    pub fn is_error(&self, response_text: &str) -> bool {
+        self.substrings.iter().any(|substring| response_text.contains(substring.as_str()))
+    }
+}
+
 /// `CexApi` is a trait defining the common interface for interacting with different cryptocurrency exchanges (CEX).
 /// It provides methods for fetching order book data and other exchange-specific information.
 #[async_trait]
-pub trait CexApi {
+pub trait CexApi: Send + Sync {
     /// Returns the name of the cryptocurrency exchange.
     /// This is typically a static string representing the exchange, like "BINANCE" or "BITKUB".
     fn name(&self) -> &'static str;
 
+    /// Returns the exchange's own symbol of a ticker, as used in its request URLs and streams,
+    /// e.g. `BTCUSDT` for the `BTC_USDT` ticker on Binance.
+    ///
+    /// The canonical `BASE_QUOTE` form of `Ticker::to_string` is kept for data directories and logs.
+    /// Defaults to that canonical form.
+    fn format_symbol(&self, ticker: &Ticker) -> String {
+        ticker.to_string()
+    }
+
     /// Asynchronously fetches the order book for a given symbol up to a specified depth.
     ///
     /// # Arguments
@@ -21,7 +168,186 @@ pub trait CexApi {
     /// or an `Err` with an error message boxed as a `dyn Error` if the fetch fails.
     async fn get_order_book(&self, symbol: &Ticker, depth: u32) -> Result<String, Box<dyn Error>>;
 
+    /// Returns the maximum number of symbols `get_order_books` accepts in a single request.
+    /// Exchanges without a multi-symbol order book endpoint keep the default of 1.
+    fn max_batch_size(&self) -> usize {
+        1
+    }
+
+    /// Asynchronously fetches the order books of several symbols in a single request.
+    ///
+    /// # Arguments
+    /// * `symbols` - The `Ticker`s to fetch, at most `max_batch_size()` of them.
+    /// * `depth` - The depth of each order book to fetch.
+    ///
+    /// # Returns
+    /// A `Result` which is `Ok` with one raw JSON response per requested symbol, in the same order,
+    /// or `None` for a symbol missing from the exchange's response. Exchanges without batch support
+    /// keep the default implementation, which returns an error.
+    async fn get_order_books(&self, symbols: &[Ticker], depth: u32) -> Result<Vec<Option<String>>, Box<dyn Error>> {
+        let _ = (symbols, depth);
+        Err(format!("{} does not support batched order book requests", self.name()).into())
+    }
+
+    /// Parses a raw order book response returned by `get_order_book` into a normalized `OrderBook`.
+    ///
+    /// # Arguments
+    /// * `raw` - The raw JSON response text.
+    ///
+    /// # Returns
+    /// A `Result` which is `Ok` with the normalized `OrderBook`, or an `Err` if the response
+    /// does not have the exchange's expected shape.
+    fn parse_order_book(&self, raw: &str) -> Result<OrderBook, Box<dyn Error>>;
+
+    /// Asynchronously fetches the mark and index prices of a derivatives contract.
+    ///
+    /// Exchanges without derivatives keep the default implementation, which returns an error.
+    ///
+    /// # Arguments
+    /// * `symbol` - A `Ticker` representing the contract's trading pair.
+    ///
+    /// # Returns
+    /// A `Result` which is `Ok` with the raw JSON response, or an `Err` if the fetch fails or is unsupported.
+    async fn get_mark_price(&self, symbol: &Ticker) -> Result<String, Box<dyn Error>> {
+        Err(format!("{} does not support mark prices for {}", self.name(), symbol).into())
+    }
+
+    /// Returns the WebSocket URL streaming the liquidation events of a derivatives contract.
+    ///
+    /// Each text message received on the stream is stored as one liquidation record.
+    /// Exchanges without a liquidation stream keep the default implementation, which returns `None`.
+    ///
+    /// # Arguments
+    /// * `symbol` - A `Ticker` representing the contract's trading pair.
+    fn liquidation_stream_url(&self, symbol: &Ticker) -> Option<String> {
+        let _ = symbol;
+        None
+    }
+
+    /// Returns the WebSocket URL streaming full order book snapshots of a symbol.
+    ///
+    /// Each text message received on the stream must be a response `parse_order_book` understands,
+    /// so it is recorded exactly like a polled one. Exchanges without such a stream keep the
+    /// default implementation, which returns `None`.
+    ///
+    /// # Arguments
+    /// * `symbol` - A `Ticker` representing the trading pair.
+    /// * `depth` - The requested depth of each snapshot.
+    fn depth_stream_url(&self, symbol: &Ticker, depth: u32) -> Option<String> {
+        let _ = (symbol, depth);
+        None
+    }
+
+    /// Returns the WebSocket URL of the diff depth stream of a symbol, used to maintain a local order book.
+    ///
+    /// Exchanges without a sequenced diff stream keep the default implementation, which returns `None`.
+    ///
+    /// # Arguments
+    /// * `symbol` - A `Ticker` representing the trading pair.
+    fn diff_stream_url(&self, symbol: &Ticker) -> Option<String> {
+        let _ = symbol;
+        None
+    }
+
+    /// Parses a message of the diff depth stream into a `DepthUpdate`.
+    ///
+    /// # Arguments
+    /// * `raw` - The raw JSON text of the stream message.
+    fn parse_depth_update(&self, raw: &str) -> Result<DepthUpdate, Box<dyn Error>> {
+        let _ = raw;
+        Err(format!("{} has no diff depth stream", self.name()).into())
+    }
+
+    /// Returns the id of the last book change included in an order book response of `get_order_book`.
+    ///
+    /// # Arguments
+    /// * `raw` - The raw JSON response text.
+    fn parse_snapshot_update_id(&self, raw: &str) -> Result<u64, Box<dyn Error>> {
+        let _ = raw;
+        Err(format!("{} has no diff depth stream", self.name()).into())
+    }
+
+    /// Asynchronously checks whether the exchange reports that it is under maintenance.
+    ///
+    /// Called after a failed fetch to tell maintenance windows apart from other errors.
+    /// Exchanges without a status endpoint keep the default implementation, which returns `false`.
+    async fn is_in_maintenance(&self) -> bool {
+        false
+    }
+
+    /// Asynchronously fetches the list of symbols the exchange currently trades.
+    ///
+    /// Called once per exchange before its workers are started; the result is used to reject
+    /// unknown tickers and to resolve symbols given without a separator (e.g. `BTCUSDT`).
+    /// Exchanges without a symbol list endpoint keep the default implementation, which returns `None`.
+    ///
+    /// # Returns
+    /// A `Result` which is `Ok` with the valid symbols in "BASE_QUOTE" form, `Ok(None)` if the
+    /// exchange does not provide a symbol list, or an `Err` if the fetch fails.
+    async fn load_symbols(&self) -> Result<Option<HashSet<String>>, Box<dyn Error>> {
+        Ok(None)
+    }
+
     /// Returns the interval in seconds at which the order book should be fetched.
     /// This can be used to rate limit the requests to the exchange's API.
     fn get_order_book_interval(&self) -> u64;
+
+    /// Returns the request weight the exchange allows per second, shared by all workers of the exchange.
+    /// Exchanges without a documented limit keep the default of 0, which disables rate limiting.
+    fn rate_limit(&self) -> u32 {
+        0
+    }
+
+    /// Returns the weight an order book request of `depth` levels counts against `rate_limit`.
+    /// Exchanges counting requests rather than weight keep the default of 1.
+    fn request_weight(&self, depth: u32) -> u32 {
+        let _ = depth;
+        1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "bitkub")]
+    #[test]
+    fn test_hmac_sha256_hex() {
+        // RFC 4231 test case 2
+        assert_eq!(
+            hmac_sha256_hex("Jefe", "what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn test_malformed_response() {
+        assert_eq!(MalformedResponse::check(r#"{"bids":[]}"#.to_string()), Ok(r#"{"bids":[]}"#.to_string()));
+        assert!(MalformedResponse::check(r#"{"bids":[[1.0,"#.to_string()).is_err());
+        let page = format!("<html>{}</html>", "x".repeat(500));
+        let error = MalformedResponse::check(page).unwrap_err();
+        assert_eq!(error.preview.chars().count(), MALFORMED_PREVIEW_CHARS);
+        assert!(error.preview.starts_with("<html>"));
+    }
+
+    #[test]
+    fn test_error_matcher_overrides() {
+        let defaults = ErrorMatcher::new(&[r#""code":-"#], None);
+        assert!(defaults.is_error(r#"{"code":-1121,"msg":"Invalid symbol."}"#));
+        assert!(!defaults.is_error(r#"{"bids":[]}"#));
+
+        let extended = ErrorMatcher::new(&[r#""code":-"#], Some(&ErrorSubstrings {
+            substrings: vec!["Service unavailable".to_string()],
+            replace: false,
+        }));
+        assert!(extended.is_error(r#"{"code":-1121}"#));
+        assert!(extended.is_error("Service unavailable"));
+
+        let replaced = ErrorMatcher::new(&[r#""code":-"#], Some(&ErrorSubstrings {
+            substrings: vec!["Service unavailable".to_string()],
+            replace: true,
+        }));
+        assert!(!replaced.is_error(r#"{"code":-1121}"#));
+        assert!(replaced.is_error("Service unavailable"));
+    }
 }
@@ -0,0 +1,150 @@
+use std::time::{Duration, Instant};
+use crate::cex_api::CexApi;
+use crate::ticker::Ticker;
+
+/// The outcome of checking one ticker, e.g. for `--check`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CheckResult {
+    /// The name of the exchange.
+    pub exchange: String,
+    /// The ticker symbol as configured.
+    pub symbol: String,
+    /// How long the order book request took, or `None` if it was not sent.
+    pub latency: Option<Duration>,
+    /// Why the check failed, or `None` if it passed.
+    pub error: Option<String>,
+}
+
+impl CheckResult {
+    /// Returns a failed check of `symbol` whose request was not sent.
+    pub fn failed(exchange: &str, symbol: &str, error: impl Into<String>) -> CheckResult {
+        CheckResult { exchange: exchange.to_string(), symbol: symbol.to_string(), latency: None, error: Some(error.into()) }
+    }
+
+    /// Returns whether the check passed.
+    pub fn passed(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Fetches one order book of `symbol` and parses it, without recording it.
+///
+/// The check fails if the symbol is not in "BASE_QUOTE" form, the request fails, or the response
+/// does not parse into a book with at least one level. Requests are sent once, without retries.
+///
+/// # Arguments
+///
+/// * `api` - The exchange API to fetch from.
+/// * `symbol` - The ticker symbol in "BASE_QUOTE" form.
+/// * `depth` - The depth of the order book to fetch.
+pub async fn check_ticker(api: &dyn CexApi, symbol: &str, depth: u32) -> CheckResult {
+    let Some(ticker) = Ticker::new(symbol) else {
+        return CheckResult::failed(api.name(), symbol, "Invalid symbol format");
+    };
+    let started = Instant::now();
+    let response = api.get_order_book(&ticker, depth).await;
+    let latency = started.elapsed();
+    let error = match response {
+        Ok(raw) => match api.parse_order_book(&raw) {
+            Ok(book) if book.is_empty() => Some("Empty order book".to_string()),
+            Ok(_) => None,
+            Err(e) => Some(format!("Cannot parse the order book: {}", e)),
+        },
+        Err(e) => Some(e.to_string()),
+    };
+    CheckResult { exchange: api.name().to_string(), symbol: symbol.to_string(), latency: Some(latency), error }
+}
+
+/// Formats check results as a table with a header and one aligned row per ticker, e.g.
+///
+/// ```text
+/// EXCHANGE  SYMBOL    RESULT  LATENCY  ERROR
+/// BINANCE   BTC_USDT  PASS    85 ms
+/// ```
+pub fn format_table(results: &[CheckResult]) -> String {
+    let rows: Vec<[String; 5]> = results.iter().map(|result| [
+        result.exchange.clone(),
+        result.symbol.clone(),
+        if result.passed() { "PASS" } else { "FAIL" }.to_string(),
+        result.latency.map_or("-".to_string(), |latency| format!("{} ms", latency.as_millis())),
+        result.error.clone().unwrap_or_default(),
+    ]).collect();
+    let header = ["EXCHANGE", "SYMBOL", "RESULT", "LATENCY", "ERROR"].map(String::from);
+    let mut widths = header.clone().map(|column| column.len());
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    let mut table = String::new();
+    for row in std::iter::once(&header).chain(&rows) {
+        let cells: Vec<String> = row.iter().zip(widths).map(|(cell, width)| format!("{:<width$}", cell)).collect();
+        table.push_str(cells.join("  ").trim_end());
+        table.push('\n');
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+    use crate::order_book::OrderBook;
+
+    /// An exchange returning a one-level book for BTC_USDT, an empty one for ETH_USDT and an
+    /// error otherwise.
+    struct StubApi;
+
+    #[async_trait::async_trait]
+    impl CexApi for StubApi {
+        fn name(&self) -> &'static str {
+            "STUB"
+        }
+
+        async fn get_order_book(&self, ticker: &Ticker, _depth: u32) -> Result<String, Box<dyn Error>> {
+            match ticker.to_string().as_str() {
+                "BTC_USDT" => Ok("full".to_string()),
+                "ETH_USDT" => Ok("empty".to_string()),
+                _ => Err("Invalid symbol".into()),
+            }
+        }
+
+        fn parse_order_book(&self, raw: &str) -> Result<OrderBook, Box<dyn Error>> {
+            let levels = if raw == "full" { vec![(1.0, 1.0)] } else { Vec::new() };
+            Ok(OrderBook { bids: levels.clone(), asks: levels, ..Default::default() })
+        }
+
+        fn get_order_book_interval(&self) -> u64 {
+            1
+        }
+    }
+
+    #[tokio::test]
+    async fn test_check_ticker() {
+        let passed = check_ticker(&StubApi, "BTC_USDT", 10).await;
+        assert!(passed.passed());
+        assert!(passed.latency.is_some());
+
+        let errors: Vec<Option<String>> = futures_util::future::join_all(
+            ["ETH_USDT", "DOGE_USDT", "BTC_"].map(|symbol| check_ticker(&StubApi, symbol, 10))
+        ).await.into_iter().map(|result| result.error).collect();
+        assert_eq!(errors, vec![
+            Some("Empty order book".to_string()),
+            Some("Invalid symbol".to_string()),
+            Some("Invalid symbol format".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn test_format_table() {
+        let results = [
+            CheckResult { latency: Some(Duration::from_millis(85)), error: None, ..CheckResult::failed("BINANCE", "BTC_USDT", "") },
+            CheckResult::failed("OKX", "BTC_", "Invalid symbol format"),
+        ];
+        assert_eq!(format_table(&results), "\
+EXCHANGE  SYMBOL    RESULT  LATENCY  ERROR
+BINANCE   BTC_USDT  PASS    85 ms
+OKX       BTC_      FAIL    -        Invalid symbol format
+");
+    }
+}
@@ -0,0 +1,69 @@
+use std::sync::atomic::{AtomicI64, Ordering};
+use chrono::prelude::Utc;
+
+/// A source of the current time, so that time-dependent logic can be tested without waiting.
+pub trait Clock: Send + Sync {
+    /// Returns the current unix time in milliseconds.
+    fn now_millis(&self) -> i64;
+}
+
+/// The wall clock of the system.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_millis(&self) -> i64 {
+        Utc::now().timestamp_millis()
+    }
+}
+
+/// A clock that only moves when told to, for deterministic tests.
+#[derive(Debug, Default)]
+pub struct MockClock {
+    millis: AtomicI64,
+}
+
+impl MockClock {
+    /// Creates a clock standing at `millis`.
+    pub fn new(millis: i64) -> MockClock {
+        MockClock { millis: AtomicI64::new(millis) }
+    }
+
+    /// Moves the clock to `millis`.
+    pub fn set(&self, millis: i64) {
+        self.millis.store(millis, Ordering::SeqCst);
+    }
+
+    /// Moves the clock forward by `millis`.
+    pub fn advance(&self, millis: i64) {
+        self.millis.fetch_add(millis, Ordering::SeqCst);
+    }
+}
+
+impl Clock for MockClock {
+    fn now_millis(&self) -> i64 {
+        self.millis.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mock_clock_moves_only_when_told() {
+        let clock = MockClock::new(1_000);
+        assert_eq!(clock.now_millis(), 1_000);
+        clock.advance(500);
+        assert_eq!(clock.now_millis(), 1_500);
+        clock.set(42);
+        assert_eq!(clock.now_millis(), 42);
+    }
+
+    #[test]
+    fn test_system_clock_is_current() {
+        let before = Utc::now().timestamp_millis();
+        let now = SystemClock.now_millis();
+        assert!(now >= before && now <= Utc::now().timestamp_millis());
+    }
+}
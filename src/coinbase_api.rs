@@ -0,0 +1,185 @@
+use async_trait::async_trait;
+use std::error::Error;
+use serde_json::Value;
+use crate::cex_api::{CexApi, ErrorMatcher, ServerError};
+use crate::config::{ErrorSubstrings, HttpConfig};
+use crate::order_book::{parse_levels, OrderBook};
+use crate::ticker::Ticker;
+
+/// The response substrings that mark an error response.
+const ERROR_SUBSTRINGS: [&str; 1] = [r#""message":"NotFound""#];
+
+/// The host of the production REST API, used unless a base URL is configured.
+pub const DEFAULT_BASE_URL: &str = "https://api.exchange.coinbase.com";
+
+/// Represents the Coinbase Exchange API for fetching order book data.
+///
+/// All requests go through one shared `reqwest::Client`, so connections to the exchange are pooled
+/// across every ticker collected from it. Coinbase rejects requests without a `User-Agent`, so the
+/// client must set one, as `HttpConfig::build_client` does.
+pub struct CoinbaseApi {
+    client: reqwest::Client,
+    errors: ErrorMatcher,
+    /// The scheme and host of the REST endpoints, without a trailing slash.
+    base_url: String,
+}
+
+impl Default for CoinbaseApi {
+    /// Creates a `CoinbaseApi` with the client of a default `HttpConfig`, which sets the `User-Agent`.
+    fn default() -> Self {
+        CoinbaseApi::new(HttpConfig::default().build_client().unwrap_or_default())
+    }
+}
+
+#[async_trait]
+impl CexApi for CoinbaseApi {
+    /// Returns the name of the exchange.
+    ///
+    /// # Returns
+    /// A static string slice representing the name of the exchange.
+    fn name(&self) -> &'static str {
+        "COINBASE"
+    }
+
+    /// Returns the Coinbase product id symbol of a ticker, e.g. `BTC-USD`.
+    fn format_symbol(&self, ticker: &Ticker) -> String {
+        format!("{}-{}", ticker.base, ticker.quote)
+    }
+
+    /// Asynchronously fetches the level 2 order book of a product from Coinbase.
+    ///
+    /// Coinbase has no depth parameter and returns the whole aggregated book, so set
+    /// `store_depth` to keep records small.
+    ///
+    /// # Arguments
+    /// * `ticker` - A reference to a `Ticker` struct containing the base and quote currencies.
+    /// * `depth` - The depth of the order book to fetch; unused by Coinbase.
+    ///
+    /// # Returns
+    /// A `Result` which is either a string containing the order book data or an error.
+    async fn get_order_book(&self, ticker: &Ticker, depth: u32) -> Result<String, Box<dyn Error>> {
+        let _ = depth;
+        let response = self.client.get(format!(
+            "{}/products/{}/book?level=2",
+            self.base_url, self.format_symbol(ticker)
+        )).send().await?;
+        ServerError::check(&response)?;
+        let response_text = response.text().await?;
+
+        if self.errors.is_error(&response_text) {
+            Err("Product not found in response from Coinbase".into())
+        } else {
+            Ok(response_text)
+        }
+    }
+
+    /// Parses a Coinbase level 2 book, whose levels are `[price, size, num_orders]` with string numbers.
+    ///
+    /// # Arguments
+    /// * `raw` - The raw JSON response text.
+    ///
+    /// # Returns
+    /// A `Result` which is either the normalized `OrderBook` or an error.
+    fn parse_order_book(&self, raw: &str) -> Result<OrderBook, Box<dyn Error>> {
+        let json: Value = serde_json::from_str(raw)?;
+        Ok(OrderBook {
+            bids: parse_levels(&json["bids"])?,
+            asks: parse_levels(&json["asks"])?,
+            exchange_ts: None,
+            sequence: json["sequence"].as_u64(),
+            requested_depth: None,
+        })
+    }
+
+    /// Returns the interval at which the order book should be fetched.
+    ///
+    /// # Returns
+    /// A `u64` representing the interval in seconds.
+    fn get_order_book_interval(&self) -> u64 {
+        1
+    }
+
+    /// Returns Coinbase's limit of 10 public requests per second.
+    fn rate_limit(&self) -> u32 {
+        10
+    }
+}
+
+impl CoinbaseApi {
+    /// Creates a new `CoinbaseApi` sending its requests through `client`.
+    pub fn new(client: reqwest::Client) -> CoinbaseApi {
+        CoinbaseApi {
+            client,
+            errors: ErrorMatcher::new(&ERROR_SUBSTRINGS, None),
+            base_url: DEFAULT_BASE_URL.to_string(),
+        }
+    }
+
+    /// Returns the API sending its requests to `base_url`, if set, e.g. the Coinbase sandbox.
+    pub fn with_base_url(mut self, base_url: Option<&str>) -> CoinbaseApi {
+        self.base_url = base_url.unwrap_or(DEFAULT_BASE_URL).trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Returns the API with its error-detection substrings extended or replaced by `overrides`.
+    pub fn with_error_substrings(mut self, overrides: Option<&ErrorSubstrings>) -> CoinbaseApi {
+        self.errors = ErrorMatcher::new(&ERROR_SUBSTRINGS, overrides);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[test]
+    fn test_coinbase_api_name() {
+        assert_eq!(CoinbaseApi::default().name(), "COINBASE");
+    }
+
+    #[tokio::test]
+    async fn test_get_order_book() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/products/BTC-USD/book"))
+            .and(query_param("level", "2"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"bids":[],"asks":[],"sequence":1}"#))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/products/ETH-USD/book"))
+            .respond_with(ResponseTemplate::new(500).set_body_string(r#"{"message":"Internal server error"}"#))
+            .mount(&server)
+            .await;
+
+        let client = reqwest::Client::builder().no_proxy().build().unwrap();
+        let api = CoinbaseApi::new(client).with_base_url(Some(&server.uri()));
+        let response = api.get_order_book(&Ticker::new("BTC_USD").unwrap(), 50).await.unwrap();
+        assert_eq!(response, r#"{"bids":[],"asks":[],"sequence":1}"#);
+        let error = api.get_order_book(&Ticker::new("ETH_USD").unwrap(), 50).await.unwrap_err();
+        assert_eq!(error.downcast_ref::<ServerError>(), Some(&ServerError { status: 500 }));
+    }
+
+    #[test]
+    fn test_format_symbol() {
+        let ticker = Ticker::new("BTC_USD").unwrap();
+        assert_eq!(CoinbaseApi::default().format_symbol(&ticker), "BTC-USD");
+    }
+
+    #[test]
+    fn test_parse_order_book() {
+        let raw = r#"{"bids":[["42000.01","0.5",3]],"asks":[["42000.02","1.25",1]],"sequence":1,"time":"2024-01-15T00:00:00Z"}"#;
+        let book = CoinbaseApi::default().parse_order_book(raw).unwrap();
+        assert_eq!(book.bids, vec![(42000.01, 0.5)]);
+        assert_eq!(book.asks, vec![(42000.02, 1.25)]);
+        assert_eq!(book.sequence, Some(1));
+    }
+
+    #[test]
+    fn test_not_found_is_error() {
+        assert!(CoinbaseApi::default().errors.is_error(r#"{"message":"NotFound"}"#));
+        assert!(!CoinbaseApi::default().errors.is_error(r#"{"bids":[],"asks":[]}"#));
+    }
+}
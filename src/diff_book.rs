@@ -0,0 +1,143 @@
+use crate::order_book::OrderBook;
+
+/// One event of an exchange's diff depth stream.
+///
+/// Binance numbers every book change; an event carries the changes from `first_update_id`
+/// to `final_update_id` (`U` and `u` in its messages) as absolute quantities, where `0` removes a level.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DepthUpdate {
+    pub first_update_id: u64,
+    pub final_update_id: u64,
+    /// The changed levels, in the shape `OrderBook::apply` expects.
+    pub changes: OrderBook,
+}
+
+/// The outcome of applying a `DepthUpdate` to a `LocalBook`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UpdateResult {
+    /// The update was applied and the book changed.
+    Applied,
+    /// The update only holds changes the book already includes.
+    Ignored,
+    /// Updates between the book and this one were missed; the book must be resynced from a new snapshot.
+    Gap,
+}
+
+/// An order book maintained from a REST snapshot and the diff depth stream.
+///
+/// This follows Binance's documented procedure: buffer the stream, fetch a snapshot with its
+/// `lastUpdateId`, drop buffered events it already includes, then apply every later event in order.
+/// Any gap in the update ids means events were lost and the book has to be rebuilt.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LocalBook {
+    book: OrderBook,
+    last_update_id: u64,
+}
+
+impl LocalBook {
+    /// Builds the local book from a snapshot and the events buffered while it was fetched.
+    ///
+    /// # Arguments
+    ///
+    /// * `snapshot` - The REST order book snapshot.
+    /// * `last_update_id` - The `lastUpdateId` of the snapshot.
+    /// * `buffered` - The stream events received since the stream was opened, in order.
+    ///
+    /// # Returns
+    ///
+    /// The synced `LocalBook`, or `None` if the snapshot is older than the first buffered event
+    /// or the buffered events have a gap, in which case a new snapshot is needed.
+    pub fn from_snapshot(snapshot: OrderBook, last_update_id: u64, buffered: &[DepthUpdate]) -> Option<LocalBook> {
+        if let Some(first) = buffered.first() {
+            if first.first_update_id > last_update_id + 1 {
+                return None;
+            }
+        }
+        let mut local = LocalBook { book: snapshot, last_update_id };
+        for update in buffered {
+            if local.apply(update) == UpdateResult::Gap {
+                return None;
+            }
+        }
+        Some(local)
+    }
+
+    /// Applies the next stream event to the book.
+    pub fn apply(&mut self, update: &DepthUpdate) -> UpdateResult {
+        if update.final_update_id <= self.last_update_id {
+            return UpdateResult::Ignored;
+        }
+        if update.first_update_id > self.last_update_id + 1 {
+            return UpdateResult::Gap;
+        }
+        self.book.apply(&update.changes);
+        self.last_update_id = update.final_update_id;
+        UpdateResult::Applied
+    }
+
+    /// Returns the maintained order book.
+    pub fn book(&self) -> &OrderBook {
+        &self.book
+    }
+
+    /// Returns the id of the last book change included in the book.
+    pub fn last_update_id(&self) -> u64 {
+        self.last_update_id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update(first_update_id: u64, final_update_id: u64, bids: Vec<(f64, f64)>) -> DepthUpdate {
+        DepthUpdate {
+            first_update_id,
+            final_update_id,
+            changes: OrderBook {
+                bids,
+                asks: vec![],
+                exchange_ts: Some(final_update_id as i64),
+                sequence: None,
+                requested_depth: None,
+            },
+        }
+    }
+
+    fn snapshot() -> OrderBook {
+        OrderBook {
+            bids: vec![(2.0, 1.0), (1.0, 1.0)],
+            asks: vec![(3.0, 1.0)],
+            exchange_ts: None,
+            sequence: None,
+            requested_depth: None,
+        }
+    }
+
+    #[test]
+    fn test_from_snapshot_drops_included_events() {
+        let buffered = vec![update(90, 100, vec![(9.0, 9.0)]), update(95, 105, vec![(2.0, 5.0)]), update(106, 107, vec![(1.0, 0.0)])];
+        let local = LocalBook::from_snapshot(snapshot(), 100, &buffered).unwrap();
+        assert_eq!(local.last_update_id(), 107);
+        assert_eq!(local.book().bids, vec![(2.0, 5.0)]);
+        assert_eq!(local.book().asks, vec![(3.0, 1.0)]);
+    }
+
+    #[test]
+    fn test_from_snapshot_rejects_stale_snapshot() {
+        let buffered = vec![update(102, 105, vec![(2.0, 5.0)])];
+        assert_eq!(LocalBook::from_snapshot(snapshot(), 100, &buffered), None);
+        let gapped = vec![update(101, 101, vec![]), update(103, 104, vec![])];
+        assert_eq!(LocalBook::from_snapshot(snapshot(), 100, &gapped), None);
+    }
+
+    #[test]
+    fn test_apply_detects_gap() {
+        let mut local = LocalBook::from_snapshot(snapshot(), 100, &[]).unwrap();
+        assert_eq!(local.apply(&update(99, 100, vec![(9.0, 9.0)])), UpdateResult::Ignored);
+        assert_eq!(local.apply(&update(101, 102, vec![(2.5, 1.0)])), UpdateResult::Applied);
+        assert_eq!(local.book().bids, vec![(2.5, 1.0), (2.0, 1.0), (1.0, 1.0)]);
+        assert_eq!(local.apply(&update(104, 105, vec![])), UpdateResult::Gap);
+        assert_eq!(local.last_update_id(), 102);
+    }
+}
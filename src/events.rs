@@ -0,0 +1,127 @@
+use chrono::prelude::Utc;
+use serde::Serialize;
+use std::error::Error;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::thread;
+use std::time::Duration;
+use tracing::warn;
+
+/// A significant occurrence during collection, reported to external systems.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// A worker started collecting a ticker.
+    WorkerStarted { exchange: String, symbol: String },
+    /// A worker stopped collecting a ticker.
+    WorkerStopped { exchange: String, symbol: String },
+    /// A worker kept panicking and was given up after `restarts` restarts.
+    WorkerFailed { exchange: String, symbol: String, restarts: u32 },
+    /// A symbol has not written a record for `silent_secs` seconds, though its worker is running.
+    WorkerStalled { exchange: String, symbol: String, silent_secs: u64 },
+    /// An exchange banned the client for exceeding its rate limits; all its workers pause.
+    RateLimitBan { exchange: String, cooldown_secs: u64 },
+    /// An exchange reported a maintenance window.
+    MaintenanceStarted { exchange: String },
+    /// An exchange recovered from a maintenance window.
+    MaintenanceEnded { exchange: String },
+}
+
+/// The number of events queued for delivery before new events are dropped.
+const EVENT_QUEUE_CAPACITY: usize = 256;
+
+/// A cloneable handle for emitting events.
+///
+/// Emitting never blocks: without a configured webhook events are discarded, and when the
+/// delivery queue is full the event is dropped and logged.
+#[derive(Clone, Default)]
+pub struct Events {
+    sender: Option<SyncSender<Event>>,
+}
+
+impl Events {
+    /// Queues an event for delivery.
+    pub fn emit(&self, event: Event) {
+        if let Some(sender) = &self.sender {
+            match sender.try_send(event) {
+                Ok(()) => {}
+                Err(TrySendError::Full(event)) => warn!("Webhook queue full, dropping event {:?}", event),
+                Err(TrySendError::Disconnected(_)) => {}
+            }
+        }
+    }
+}
+
+/// Starts a background thread that POSTs emitted events to a webhook as JSON.
+///
+/// Each request body is the event with a `"time"` field, e.g.
+/// `{"event": "worker_started", "exchange": "BINANCE", "symbol": "BTC_USDT", "time": 1700000000}`.
+/// Requests are spaced at least `min_interval` apart; failed requests are logged and not retried.
+///
+/// # Arguments
+///
+/// * `url` - The webhook URL.
+/// * `min_interval` - The minimum time between two webhook requests.
+///
+/// # Returns
+///
+/// The `Events` handle feeding the webhook.
+///
+/// # Errors
+///
+/// Returns an error if the HTTP client or its runtime cannot be created.
+pub fn spawn_webhook(url: &str, min_interval: Duration) -> Result<Events, Box<dyn Error>> {
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+    let client = reqwest::Client::builder().timeout(Duration::from_secs(5)).build()?;
+    let (sender, receiver) = sync_channel(EVENT_QUEUE_CAPACITY);
+    let url = url.to_string();
+    thread::spawn(move || deliver(url, receiver, min_interval, runtime, client));
+    Ok(Events { sender: Some(sender) })
+}
+
+/// Delivers queued events to the webhook until every `Events` handle is dropped.
+fn deliver(
+    url: String,
+    receiver: Receiver<Event>,
+    min_interval: Duration,
+    runtime: tokio::runtime::Runtime,
+    client: reqwest::Client,
+) {
+    while let Ok(event) = receiver.recv() {
+        let payload = payload(&event, Utc::now().timestamp());
+        let result = runtime.block_on(client.post(&url).json(&payload).send());
+        match result {
+            Ok(response) if !response.status().is_success() => {
+                warn!("Webhook returned {} for {:?}", response.status(), event);
+            }
+            Ok(_) => {}
+            Err(e) => warn!("Failed to deliver webhook event {:?}: {}", event, e),
+        }
+        thread::sleep(min_interval);
+    }
+}
+
+/// Builds the JSON body posted for an event.
+fn payload(event: &Event, timestamp: i64) -> serde_json::Value {
+    let mut payload = serde_json::to_value(event).expect("Event is serializable");
+    payload["time"] = timestamp.into();
+    payload
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_payload_contains_event_and_time() {
+        let event = Event::WorkerStarted { exchange: "BINANCE".to_string(), symbol: "BTC_USDT".to_string() };
+        assert_eq!(payload(&event, 5), json!({
+            "event": "worker_started", "exchange": "BINANCE", "symbol": "BTC_USDT", "time": 5
+        }));
+    }
+
+    #[test]
+    fn test_emit_without_webhook_is_noop() {
+        Events::default().emit(Event::MaintenanceStarted { exchange: "BINANCE".to_string() });
+    }
+}
@@ -0,0 +1,159 @@
+use std::sync::Arc;
+use crate::cex_api::CexApi;
+use crate::config::{Config, ConfigError, ExchangeConfig, KNOWN_EXCHANGES};
+#[cfg(feature = "binance")]
+use crate::binance_api::BinanceApi;
+#[cfg(feature = "bitkub")]
+use crate::bitkub_api::BitkubApi;
+#[cfg(feature = "coinbase")]
+use crate::coinbase_api::CoinbaseApi;
+#[cfg(feature = "kraken")]
+use crate::kraken_api::KrakenApi;
+#[cfg(feature = "okx")]
+use crate::okx_api::OkxApi;
+#[cfg(feature = "bybit")]
+use crate::bybit_api::BybitApi;
+
+/// Builds the API of the exchange called `name`, e.g. `"BINANCE"`, with its configured base URL,
+/// error substrings and credentials.
+///
+/// This is the one place mapping exchange names to their implementations, so supporting a new
+/// exchange takes one arm here and its name in `KNOWN_EXCHANGES`.
+///
+/// # Arguments
+///
+/// * `name` - The name of the exchange as written in the config file.
+/// * `client` - The HTTP client the API sends its requests through.
+/// * `config` - The loaded configuration.
+///
+/// # Returns
+///
+/// The API, or `None` if the exchange is unknown or not compiled into this build. Unknown names
+/// are rejected by `Config::validate` before collection starts.
+#[cfg_attr(
+    not(any(
+        feature = "binance", feature = "bitkub", feature = "coinbase", feature = "kraken", feature = "okx",
+        feature = "bybit"
+    )),
+    allow(unused_variables)
+)]
+pub fn api_for(name: &str, client: reqwest::Client, config: &Config) -> Option<Arc<dyn CexApi>> {
+    let overrides = config.error_substrings.get(name);
+    let base_url = config.base_urls.get(name).map(String::as_str);
+    match name {
+        #[cfg(feature = "binance")]
+        "BINANCE" => Some(Arc::new(
+            BinanceApi::new(client)
+                .with_base_url(base_url)
+                .with_error_substrings(overrides)
+                .with_credentials(config.credentials.get(name)),
+        )),
+        #[cfg(feature = "bitkub")]
+        "BITKUB" => Some(Arc::new(
+            BitkubApi::new(client)
+                .with_base_url(base_url)
+                .with_error_substrings(overrides)
+                .with_credentials(config.credentials.get(name)),
+        )),
+        #[cfg(feature = "coinbase")]
+        "COINBASE" => Some(Arc::new(CoinbaseApi::new(client).with_base_url(base_url).with_error_substrings(overrides))),
+        #[cfg(feature = "kraken")]
+        "KRAKEN" => Some(Arc::new(KrakenApi::new(client).with_base_url(base_url).with_error_substrings(overrides))),
+        #[cfg(feature = "okx")]
+        "OKX" => Some(Arc::new(OkxApi::new(client).with_base_url(base_url).with_error_substrings(overrides))),
+        #[cfg(feature = "bybit")]
+        "BYBIT" => Some(Arc::new(BybitApi::new(client).with_base_url(base_url).with_error_substrings(overrides))),
+        _ => None,
+    }
+}
+
+/// The configured exchanges with their APIs, as built by `build_apis`.
+pub type ExchangeApis = Vec<(ExchangeConfig, Arc<dyn CexApi>)>;
+
+/// Builds the API of every exchange in `config`, each with its own HTTP client.
+///
+/// Nothing is started, so a configuration can be checked in full before any worker is touched.
+///
+/// # Returns
+///
+/// Each exchange with its API, in the order of `config.exchanges()`.
+///
+/// # Errors
+///
+/// Returns a `ConfigError` for the first exchange whose client cannot be built or which is
+/// unknown or not compiled into this build.
+pub fn build_apis(config: &Config) -> Result<ExchangeApis, ConfigError> {
+    let mut apis = Vec::new();
+    for exchange in config.exchanges() {
+        // Each exchange gets its own client, so its connection pool serves only that exchange's host
+        let client = config.http.build_client().map_err(|e| ConfigError::HttpClient {
+            exchange: exchange.cex.clone(),
+            reason: e.to_string(),
+        })?;
+        let api = api_for(&exchange.cex, client, config).ok_or_else(|| match KNOWN_EXCHANGES.contains(&exchange.cex.as_str()) {
+            true => ConfigError::ExchangeNotCompiled(exchange.cex.clone()),
+            false => ConfigError::UnknownExchange(exchange.cex.clone()),
+        })?;
+        apis.push((exchange, api));
+    }
+    Ok(apis)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_api_for() {
+        let config = Config::default();
+        for name in KNOWN_EXCHANGES {
+            if let Some(api) = api_for(name, reqwest::Client::new(), &config) {
+                assert_eq!(api.name(), name);
+            }
+        }
+        #[cfg(feature = "binance")]
+        assert!(api_for("BINANCE", reqwest::Client::new(), &config).is_some());
+        assert!(api_for("NOPE", reqwest::Client::new(), &config).is_none());
+        assert!(api_for("binance", reqwest::Client::new(), &config).is_none());
+    }
+
+    #[cfg(feature = "binance")]
+    #[test]
+    fn test_build_apis_fails_on_any_exchange() {
+        let config: Config = serde_json::from_str(r#"{"exchanges": [
+            {"cex": "BINANCE", "tickers": ["BTC_USDT"]},
+            {"cex": "NOPE", "tickers": ["BTC_USDT"]}
+        ]}"#).unwrap();
+        assert_eq!(build_apis(&config).err(), Some(ConfigError::UnknownExchange("NOPE".to_string())));
+
+        let config: Config = serde_json::from_str(r#"{"cex": "BINANCE", "tickers": ["BTC_USDT"]}"#).unwrap();
+        let apis = build_apis(&config).unwrap();
+        assert_eq!(apis.iter().map(|(exchange, api)| (exchange.cex.as_str(), api.name())).collect::<Vec<_>>(), vec![("BINANCE", "BINANCE")]);
+    }
+
+    #[cfg(feature = "coinbase")]
+    #[tokio::test]
+    async fn test_configured_user_agent_reaches_coinbase() {
+        use crate::ticker::Ticker;
+        use wiremock::matchers::{header, method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/products/BTC-USD/book"))
+            .and(header("user-agent", "my-collector/1.0"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"bids":[],"asks":[],"sequence":1}"#))
+            .mount(&server)
+            .await;
+
+        let config: Config = serde_json::from_str(&format!(r#"{{
+            "cex": "COINBASE",
+            "tickers": ["BTC_USD"],
+            "http": {{"user_agent": "my-collector/1.0"}},
+            "base_urls": {{"COINBASE": "{}"}}
+        }}"#, server.uri())).unwrap();
+        let (_, api) = build_apis(&config).unwrap().remove(0);
+        let response = api.get_order_book(&Ticker::new("BTC_USD").unwrap(), 50).await.unwrap();
+        assert_eq!(response, r#"{"bids":[],"asks":[],"sequence":1}"#);
+    }
+}
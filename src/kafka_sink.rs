@@ -0,0 +1,85 @@
+use rdkafka::config::ClientConfig;
+use rdkafka::error::{KafkaError, KafkaResult, RDKafkaErrorCode};
+use rdkafka::producer::{BaseRecord, DeliveryResult, Producer, ProducerContext, ThreadedProducer};
+use rdkafka::ClientContext;
+use std::error::Error;
+use crate::config::KafkaConfig;
+use crate::sink::Sink;
+use tracing::warn;
+
+/// How long a replay waits for room in a full producer queue before trying again.
+const QUEUE_FULL_BACKOFF: std::time::Duration = std::time::Duration::from_millis(10);
+
+/// How long dropping the sink waits for the queued records to be delivered.
+const FLUSH_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Publishes records to a Kafka topic, keyed by ticker symbol.
+///
+/// Messages are queued in the producer's bounded in-memory queue and delivered by its background
+/// thread. When the queue is full the record is dropped and the error returned. A delivery that
+/// fails later, in the background, is logged.
+pub struct KafkaSink {
+    producer: ThreadedProducer<LoggingContext>,
+    topic: String,
+}
+
+impl KafkaSink {
+    /// Creates a producer connected to the configured brokers.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `KafkaError` if the producer cannot be created.
+    pub fn new(config: &KafkaConfig) -> KafkaResult<KafkaSink> {
+        let producer = ClientConfig::new()
+            .set("bootstrap.servers", &config.brokers)
+            .set("queue.buffering.max.messages", config.queue_capacity.to_string())
+            .create_with_context(LoggingContext)?;
+        Ok(KafkaSink { producer, topic: config.topic.clone() })
+    }
+}
+
+impl Sink for KafkaSink {
+    fn publish(&self, _exchange: &str, symbol: &str, record: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let message = BaseRecord::to(&self.topic).key(symbol).payload(record);
+        self.producer.send(message).map_err(|(e, _)| format!("Kafka: {}", e).into())
+    }
+
+    /// Queues a replayed record, waiting while the producer's queue is full instead of dropping the record.
+    fn replay(&self, _exchange: &str, symbol: &str, record: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut message = BaseRecord::to(&self.topic).key(symbol).payload(record);
+        loop {
+            match self.producer.send(message) {
+                Ok(()) => return Ok(()),
+                Err((KafkaError::MessageProduction(RDKafkaErrorCode::QueueFull), returned)) => {
+                    message = returned;
+                    std::thread::sleep(QUEUE_FULL_BACKOFF);
+                }
+                Err((e, _)) => return Err(format!("Kafka: {}", e).into()),
+            }
+        }
+    }
+}
+
+impl Drop for KafkaSink {
+    fn drop(&mut self) {
+        // The producer discards its queue when dropped, so deliver what is left first
+        if let Err(e) = self.producer.flush(FLUSH_TIMEOUT) {
+            warn!("Failed to deliver the queued Kafka records: {}", e);
+        }
+    }
+}
+
+/// A producer context that logs failed deliveries.
+struct LoggingContext;
+
+impl ClientContext for LoggingContext {}
+
+impl ProducerContext for LoggingContext {
+    type DeliveryOpaque = ();
+
+    fn delivery(&self, delivery_result: &DeliveryResult<'_>, _delivery_opaque: Self::DeliveryOpaque) {
+        if let Err((e, _)) = delivery_result {
+            warn!("Failed to deliver Kafka record: {}", e);
+        }
+    }
+}
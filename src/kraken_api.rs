@@ -0,0 +1,316 @@
+use async_trait::async_trait;
+use std::error::Error;
+use std::fmt;
+use serde_json::Value;
+use crate::cex_api::{CexApi, ErrorMatcher, ServerError};
+use crate::config::ErrorSubstrings;
+use crate::order_book::{parse_levels, OrderBook};
+use crate::ticker::Ticker;
+
+/// The response substrings that mark an error response. Kraken errors are detected from the
+/// `error` array of the response, so none are built in; the `error_substrings` config can add some.
+const ERROR_SUBSTRINGS: [&str; 0] = [];
+
+/// The host of the production REST API, used unless a base URL is configured.
+pub const DEFAULT_BASE_URL: &str = "https://api.kraken.com";
+
+/// The number of levels per side covered by a Kraken book checksum.
+const CHECKSUM_LEVELS: usize = 10;
+
+/// The error returned when a Kraken book page does not match the checksum it carries.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChecksumMismatch {
+    /// The checksum sent by Kraken.
+    pub expected: u32,
+    /// The checksum of the received levels.
+    pub actual: u32,
+}
+
+impl fmt::Display for ChecksumMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Order book checksum mismatch: expected {}, got {}", self.expected, self.actual)
+    }
+}
+
+impl Error for ChecksumMismatch {}
+
+/// Represents the Kraken spot API for fetching order book data.
+///
+/// All requests go through one shared `reqwest::Client`, so connections to the exchange are pooled
+/// across every ticker collected from it.
+pub struct KrakenApi {
+    client: reqwest::Client,
+    errors: ErrorMatcher,
+    /// The scheme and host of the REST endpoints, without a trailing slash.
+    base_url: String,
+}
+
+impl Default for KrakenApi {
+    /// Creates a `KrakenApi` with a default `reqwest::Client`.
+    fn default() -> Self {
+        KrakenApi::new(reqwest::Client::new())
+    }
+}
+
+#[async_trait]
+impl CexApi for KrakenApi {
+    /// Returns the name of the exchange.
+    ///
+    /// # Returns
+    /// A static string slice representing the name of the exchange.
+    fn name(&self) -> &'static str {
+        "KRAKEN"
+    }
+
+    /// Returns the Kraken pair symbol of a ticker, the base and quote concatenated, e.g. `BTCUSD`.
+    fn format_symbol(&self, ticker: &Ticker) -> String {
+        format!("{}{}", ticker.base, ticker.quote)
+    }
+
+    /// Asynchronously fetches the order book for a given ticker and depth from Kraken.
+    ///
+    /// A response whose `error` array is not empty is an error, as is a book page that carries a
+    /// checksum its levels do not match, so corrupt pages are never recorded.
+    ///
+    /// # Arguments
+    /// * `ticker` - A reference to a `Ticker` struct containing the base and quote currencies.
+    /// * `depth` - The depth of the order book to fetch.
+    ///
+    /// # Returns
+    /// A `Result` which is either a string containing the order book data or an error.
+    async fn get_order_book(&self, ticker: &Ticker, depth: u32) -> Result<String, Box<dyn Error>> {
+        let response = self.client.get(format!(
+            "{}/0/public/Depth?pair={}&count={}",
+            self.base_url, self.format_symbol(ticker), depth
+        )).send().await?;
+        ServerError::check(&response)?;
+        let response_text = response.text().await?;
+
+        if self.errors.is_error(&response_text) {
+            return Err("Error in response from Kraken".into());
+        }
+        let json: Value = serde_json::from_str(&response_text)?;
+        if let Some(errors) = KrakenApi::error_messages(&json) {
+            return Err(format!("Kraken returned errors: {}", errors).into());
+        }
+        KrakenApi::verify_checksum(KrakenApi::pair_book(&json)?)?;
+        Ok(response_text)
+    }
+
+    /// Parses a Kraken depth response, e.g.
+    /// `{"error": [], "result": {"XXBTZUSD": {"asks": [["42000.2", "0.250", 1700000000]], "bids": [...]}}}`.
+    ///
+    /// A book page that carries a checksum its levels do not match is rejected with a
+    /// `ChecksumMismatch`, so recorded pages are verified again when they are replayed or checked.
+    ///
+    /// # Arguments
+    /// * `raw` - The raw JSON response text.
+    ///
+    /// # Returns
+    /// A `Result` which is either the normalized `OrderBook` or an error.
+    fn parse_order_book(&self, raw: &str) -> Result<OrderBook, Box<dyn Error>> {
+        let json: Value = serde_json::from_str(raw)?;
+        let book = KrakenApi::pair_book(&json)?;
+        KrakenApi::verify_checksum(book)?;
+        Ok(OrderBook {
+            bids: parse_levels(&book["bids"])?,
+            asks: parse_levels(&book["asks"])?,
+            exchange_ts: None,
+            sequence: None,
+            requested_depth: None,
+        })
+    }
+
+    /// Returns the interval at which the order book should be fetched.
+    ///
+    /// # Returns
+    /// A `u64` representing the interval in seconds.
+    fn get_order_book_interval(&self) -> u64 {
+        1
+    }
+
+    /// Returns Kraken's public endpoint limit of about one request per second.
+    fn rate_limit(&self) -> u32 {
+        1
+    }
+}
+
+impl KrakenApi {
+    /// Creates a new `KrakenApi` sending its requests through `client`.
+    pub fn new(client: reqwest::Client) -> KrakenApi {
+        KrakenApi {
+            client,
+            errors: ErrorMatcher::new(&ERROR_SUBSTRINGS, None),
+            base_url: DEFAULT_BASE_URL.to_string(),
+        }
+    }
+
+    /// Returns the API sending its requests to `base_url`, if set, e.g. a local mock server.
+    pub fn with_base_url(mut self, base_url: Option<&str>) -> KrakenApi {
+        self.base_url = base_url.unwrap_or(DEFAULT_BASE_URL).trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Returns the API with its error-detection substrings extended or replaced by `overrides`.
+    pub fn with_error_substrings(mut self, overrides: Option<&ErrorSubstrings>) -> KrakenApi {
+        self.errors = ErrorMatcher::new(&ERROR_SUBSTRINGS, overrides);
+        self
+    }
+
+    /// Returns the messages of the top-level `error` array joined with `, `, or `None` if it is empty.
+    fn error_messages(json: &Value) -> Option<String> {
+        let errors = json["error"].as_array().filter(|errors| !errors.is_empty())?;
+        Some(errors.iter().map(|error| error.as_str().unwrap_or("unknown error")).collect::<Vec<_>>().join(", "))
+    }
+
+    /// Returns the book of the single pair under `result`, whose key is Kraken's pair name, e.g. `XXBTZUSD`.
+    fn pair_book(json: &Value) -> Result<&Value, Box<dyn Error>> {
+        let result = json["result"].as_object().ok_or("Missing result in Kraken depth response")?;
+        Ok(result.values().next().ok_or("Missing pair in Kraken depth response")?)
+    }
+
+    /// Checks a book page against its `checksum`, if it carries one.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `ChecksumMismatch` if the levels do not match the checksum.
+    fn verify_checksum(book: &Value) -> Result<(), Box<dyn Error>> {
+        let expected = match &book["checksum"] {
+            Value::Null => return Ok(()),
+            Value::String(checksum) => checksum.parse::<u32>()?,
+            checksum => checksum.as_u64().and_then(|checksum| u32::try_from(checksum).ok())
+                .ok_or("Invalid checksum in Kraken depth response")?,
+        };
+        let actual = KrakenApi::checksum(book)?;
+        if actual != expected {
+            return Err(ChecksumMismatch { expected, actual }.into());
+        }
+        Ok(())
+    }
+
+    /// Computes Kraken's CRC32 checksum of a book page.
+    ///
+    /// The checksummed text is the price and volume of the top 10 asks, then of the top 10 bids,
+    /// each as sent without its decimal point and leading zeros, e.g. `"0.05005"` becomes `5005`.
+    fn checksum(book: &Value) -> Result<u32, Box<dyn Error>> {
+        let mut text = String::new();
+        for side in ["asks", "bids"] {
+            let levels = book[side].as_array().ok_or("Order book levels are not an array")?;
+            for level in levels.iter().take(CHECKSUM_LEVELS) {
+                for index in 0..2 {
+                    let value = level[index].as_str().ok_or("Order book level values are not strings")?;
+                    text.push_str(value.replace('.', "").trim_start_matches('0'));
+                }
+            }
+        }
+        Ok(crc32fast::hash(text.as_bytes()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use wiremock::matchers::{method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    /// Returns a `KrakenApi` sending its requests to `server`, bypassing any proxy set in the environment.
+    fn mock_api(server: &MockServer) -> KrakenApi {
+        let client = reqwest::Client::builder().no_proxy().build().unwrap();
+        KrakenApi::new(client).with_base_url(Some(&server.uri()))
+    }
+
+    /// Mounts a response to the depth request of `BTCUSD` with 10 levels.
+    async fn mock_depth(server: &MockServer, body: &str) {
+        Mock::given(method("GET"))
+            .and(path("/0/public/Depth"))
+            .and(query_param("pair", "BTCUSD"))
+            .and(query_param("count", "10"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(body))
+            .mount(server)
+            .await;
+    }
+
+    #[test]
+    fn test_kraken_api_name() {
+        assert_eq!(KrakenApi::default().name(), "KRAKEN");
+    }
+
+    #[test]
+    fn test_format_symbol() {
+        let ticker = Ticker::new("BTC_USD").unwrap();
+        assert_eq!(KrakenApi::default().format_symbol(&ticker), "BTCUSD");
+    }
+
+    #[tokio::test]
+    async fn test_get_order_book() {
+        let server = MockServer::start().await;
+        let body = r#"{"error":[],"result":{"XXBTZUSD":{"asks":[["42000.2","0.250",1700000000]],"bids":[["42000.1","1.500",1700000000]]}}}"#;
+        mock_depth(&server, body).await;
+
+        let ticker = Ticker::new("BTC_USD").unwrap();
+        let response = mock_api(&server).get_order_book(&ticker, 10).await.unwrap();
+        assert_eq!(response, body);
+        let book = KrakenApi::default().parse_order_book(&response).unwrap();
+        assert_eq!(book.bids, vec![(42000.1, 1.5)]);
+        assert_eq!(book.asks, vec![(42000.2, 0.25)]);
+    }
+
+    #[tokio::test]
+    async fn test_get_order_book_error_array() {
+        let server = MockServer::start().await;
+        mock_depth(&server, r#"{"error":["EQuery:Unknown asset pair"]}"#).await;
+
+        let ticker = Ticker::new("BTC_USD").unwrap();
+        let error = mock_api(&server).get_order_book(&ticker, 10).await.unwrap_err();
+        assert_eq!(error.to_string(), "Kraken returned errors: EQuery:Unknown asset pair");
+    }
+
+    #[tokio::test]
+    async fn test_get_order_book_rejects_checksum_mismatch() {
+        let server = MockServer::start().await;
+        mock_depth(&server, r#"{"error":[],"result":{"XXBTZUSD":{"asks":[["42000.2","0.250",1]],"bids":[],"checksum":1}}}"#).await;
+
+        let ticker = Ticker::new("BTC_USD").unwrap();
+        let error = mock_api(&server).get_order_book(&ticker, 10).await.unwrap_err();
+        assert!(error.downcast_ref::<ChecksumMismatch>().is_some());
+    }
+
+    #[test]
+    fn test_parse_order_book_verifies_checksum() {
+        let expected = crc32fast::hash(b"42000225042000115");
+        let page = |checksum: u32| format!(
+            r#"{{"error":[],"result":{{"XXBTZUSD":{{"asks":[["42000.2","0.250",1]],"bids":[["42000.1","1.5",1]],"checksum":{}}}}}}}"#,
+            checksum
+        );
+        let book = KrakenApi::default().parse_order_book(&page(expected)).unwrap();
+        assert_eq!(book.bids, vec![(42000.1, 1.5)]);
+
+        let error = KrakenApi::default().parse_order_book(&page(expected + 1)).unwrap_err();
+        assert_eq!(error.downcast_ref::<ChecksumMismatch>(), Some(&ChecksumMismatch { expected: expected + 1, actual: expected }));
+    }
+
+    #[test]
+    fn test_checksum() {
+        let book = json!({
+            "asks": [["0.05005", "0.00000500", 1], ["0.05010", "0.00001000", 2]],
+            "bids": [["0.05000", "0.00000100", 3]],
+        });
+        let expected = crc32fast::hash(b"5005500501010005000100");
+        assert_eq!(KrakenApi::checksum(&book).unwrap(), expected);
+
+        let mut verified = book.clone();
+        verified["checksum"] = json!(expected);
+        assert!(KrakenApi::verify_checksum(&verified).is_ok());
+        verified["checksum"] = json!(expected.to_string());
+        assert!(KrakenApi::verify_checksum(&verified).is_ok());
+        assert!(KrakenApi::verify_checksum(&book).is_ok());
+
+        // Only the top 10 levels of each side are covered
+        let mut deep = book.clone();
+        let levels: Vec<Value> = (1..=12).map(|i| json!([format!("{}.0", i), "1.0", 0])).collect();
+        deep["asks"] = json!(levels);
+        let top: String = (1..=10).map(|i| format!("{}010", i)).collect();
+        assert_eq!(KrakenApi::checksum(&deep).unwrap(), crc32fast::hash(format!("{}5000100", top).as_bytes()));
+    }
+}
@@ -0,0 +1,72 @@
+//! Collects order book snapshots from centralized exchanges (CEXs) into data files and sinks.
+//!
+//! The `cex-orderbook-collector-rs` binary runs collection as a service. To embed it into another
+//! service instead, create an `OrderBookCollector`, give it a `Config`, and start the tickers of each
+//! exchange with that exchange's `CexApi`. Each exchange struct is behind the Cargo feature of
+//! its name in lowercase.
+//!
+//! Only the types needed to configure, run and extend collection are public: the collector,
+//! the configuration, the exchange APIs and the `CexApi` trait with the types its methods use,
+//! and the sinks, events and metrics the collector reports to. The workers, recorders and file
+//! storage are internal.
+
+mod cex_api;
+mod check;
+mod clock;
+#[cfg(feature = "binance")]
+mod binance_api;
+#[cfg(feature = "bitkub")]
+mod bitkub_api;
+#[cfg(feature = "coinbase")]
+mod coinbase_api;
+#[cfg(feature = "kraken")]
+mod kraken_api;
+#[cfg(feature = "okx")]
+mod okx_api;
+#[cfg(feature = "bybit")]
+mod bybit_api;
+mod exchanges;
+mod ticker;
+mod schedule;
+mod storage;
+mod order_book;
+mod diff_book;
+mod recorder;
+mod worker;
+mod rate_limit;
+mod replay;
+mod orderbook_collector;
+#[cfg(feature = "kafka")]
+mod kafka_sink;
+#[cfg(feature = "sqlite")]
+mod sqlite_sink;
+pub mod config;
+pub mod events;
+pub mod metrics;
+pub mod sink;
+pub mod socket_export;
+
+pub use cex_api::{CexApi, ErrorMatcher, MalformedResponse, RateLimitBan, ServerError};
+pub use check::{format_table, CheckResult};
+pub use clock::{Clock, MockClock, SystemClock};
+pub use config::{Config, ConfigError, ExchangeConfig, TickerConfig, KNOWN_EXCHANGES};
+pub use diff_book::DepthUpdate;
+pub use exchanges::{api_for, build_apis, ExchangeApis};
+pub use order_book::{parse_levels, OrderBook, Snapshot};
+pub use orderbook_collector::OrderBookCollector;
+pub use replay::{replay, ticker_of_dir, ReplayStats};
+pub use storage::{check_writable, decode_line};
+pub use ticker::Ticker;
+
+#[cfg(feature = "binance")]
+pub use binance_api::BinanceApi;
+#[cfg(feature = "bitkub")]
+pub use bitkub_api::BitkubApi;
+#[cfg(feature = "coinbase")]
+pub use coinbase_api::CoinbaseApi;
+#[cfg(feature = "kraken")]
+pub use kraken_api::KrakenApi;
+#[cfg(feature = "okx")]
+pub use okx_api::OkxApi;
+#[cfg(feature = "bybit")]
+pub use bybit_api::BybitApi;
@@ -1,61 +1,404 @@
-// Module imports
-mod config;
-mod cex_api;
-mod binance_api;
-mod bitkub_api;
-mod ticker;
-mod orderbook_collector;
-
-// Use statements to bring types into scope
-use orderbook_collector::OrderBookCollector;
-use binance_api::BinanceApi;
-use bitkub_api::BitkubApi;
-use crate::config::Config;
+use cex_orderbook_collector_rs::{
+    api_for, check_writable, decode_line, events, format_table, replay, sink, socket_export, ticker_of_dir, CexApi,
+    CheckResult, Config, ExchangeConfig, OrderBookCollector, KNOWN_EXCHANGES,
+};
+#[cfg(feature = "metrics")]
+use cex_orderbook_collector_rs::metrics;
+use std::fs;
 use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::Duration;
 use notify::{Watcher, RecursiveMode};
+use tracing::{error, info, warn};
+
+/// Loads the configuration from `config_path` and validates it.
+///
+/// # Errors
+///
+/// Returns a message describing why the file cannot be read, parsed or collected from.
+fn load_valid_config(config_path: &str) -> Result<Config, String> {
+    let config = Config::load(config_path).map_err(|e| e.to_string())?;
+    config.validate().map_err(|e| e.to_string())?;
+    Ok(config)
+}
+
+/// Checks at startup that the data files can be written to the configured output directory.
+///
+/// # Errors
+///
+/// Returns a message naming the directory and why it cannot be written.
+fn check_output_dir(config: &Config) -> Result<(), String> {
+    check_writable(&config.output_dir)
+        .map_err(|e| format!("output directory {} is not writable: {}", config.output_dir, e))
+}
 
 /// Updates the tasks in the OrderBookCollector based on the current configuration.
-/// It loads the configuration and starts collecting order books for the specified tickers.
-fn update_tasks_based_on_config(collector: &mut OrderBookCollector) {
-    match Config::load() {
-        Ok(config) => {
-            println!("CEX: {}", config.cex);
-            // Start tasks based on the specified CEX in the configuration
-            if config.cex == "BINANCE" {
-                collector.start_multiple(&config.tickers, BinanceApi.into());
-            } else if config.cex == "BITKUB" {
-                collector.start_multiple(&config.tickers, BitkubApi.into());
-            } else {
-                eprintln!("Unsupported CEX: {}", config.cex);
-                return;
+/// It loads the configuration from `config_path` and starts collecting order books for the specified tickers
+/// of every configured exchange, stopping the workers of exchanges no longer configured.
+/// A configuration that cannot be loaded or applied in full is logged and leaves the running workers untouched,
+/// and a configuration equal to the applied one is ignored.
+fn update_tasks_based_on_config(collector: &mut OrderBookCollector, config_path: &str) {
+    let result = load_valid_config(config_path).and_then(|config| {
+        if &config == collector.config() {
+            info!("Configuration unchanged, keeping the running workers");
+            return Ok(());
+        }
+        collector.reload(config).map_err(|e| e.to_string())
+    });
+    if let Err(e) = result {
+        warn!("Failed to load config, keeping the running workers: {}", e);
+    }
+}
+
+/// How long the configuration file must stay unchanged after a change before it is reloaded.
+const RELOAD_DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Waits for the next burst of events on `receiver`, e.g. the write and rename of a single save.
+///
+/// # Returns
+///
+/// The number of events of the burst once no further event arrived within `window`,
+/// or `None` if the sender is gone.
+fn next_burst<T>(receiver: &Receiver<T>, window: Duration) -> Option<usize> {
+    receiver.recv().ok()?;
+    let mut events = 1;
+    loop {
+        match receiver.recv_timeout(window) {
+            Ok(_) => events += 1,
+            Err(RecvTimeoutError::Timeout) => return Some(events),
+            Err(RecvTimeoutError::Disconnected) => return None,
+        }
+    }
+}
+
+/// Builds the API of one configured exchange through `api_for`.
+///
+/// # Returns
+///
+/// The API, or `None` if the exchange is unsupported or not compiled into this binary, which is logged.
+fn build_api(exchange: &ExchangeConfig, client: reqwest::Client, config: &Config) -> Option<Arc<dyn CexApi>> {
+    let api = api_for(&exchange.cex, client, config);
+    if api.is_none() {
+        let cex = exchange.cex.as_str();
+        if KNOWN_EXCHANGES.contains(&cex) {
+            error!("CEX {} is not compiled into this binary; rebuild with `--features {}`", cex, cex.to_lowercase());
+        } else {
+            error!("Unsupported CEX: {}", cex);
+        }
+    }
+    api
+}
+
+/// Fetches one order book of every configured ticker, records it and returns, for `--once`.
+///
+/// With `stdout`, the records are printed to stdout instead of being written to the data files
+/// or the configured sinks. Warmup is skipped, so each fetched book is recorded.
+///
+/// # Returns
+///
+/// `true` if every ticker was fetched.
+fn collect_once(config: Config, stdout: bool) -> bool {
+    let mut config = Config { warmup_snapshots: 0, ..config };
+    let mut collector = OrderBookCollector::new();
+    if stdout {
+        config.write_files = false;
+        collector.set_sinks(vec![Arc::new(sink::StdoutSink)]);
+    } else {
+        collector.set_sinks(sink::from_config(&config));
+    }
+    let config = Arc::new(config);
+    collector.set_config(config.clone());
+
+    let mut failed = 0;
+    for exchange in config.exchanges() {
+        let api = config.http.build_client()
+            .map_err(|e| error!("Failed to create HTTP client for {}: {}", exchange.cex, e))
+            .ok()
+            .and_then(|client| build_api(&exchange, client, &config));
+        failed += match api {
+            Some(api) => collector.collect_once(&exchange.tickers, api),
+            None => exchange.tickers.len(),
+        };
+    }
+    if failed > 0 {
+        error!("{} tickers could not be fetched", failed);
+    }
+    failed == 0
+}
+
+/// Fetches and parses one order book of every configured ticker and prints a table of the results, for `--check`.
+///
+/// Nothing is recorded and the collection loop is not started.
+///
+/// # Returns
+///
+/// `true` if every check passed.
+fn check_all(config: Config) -> bool {
+    let mut collector = OrderBookCollector::new();
+    let config = Arc::new(config);
+    collector.set_config(config.clone());
+
+    let mut results = Vec::new();
+    for exchange in config.exchanges() {
+        let api = config.http.build_client()
+            .map_err(|e| error!("Failed to create HTTP client for {}: {}", exchange.cex, e))
+            .ok()
+            .and_then(|client| build_api(&exchange, client, &config));
+        match api {
+            Some(api) => results.extend(collector.check(&exchange.tickers, api)),
+            None => results.extend(exchange.tickers.iter().map(|ticker| {
+                CheckResult::failed(&exchange.cex, &ticker.symbol, "Exchange not available")
+            })),
+        }
+    }
+    print!("{}", format_table(&results));
+    results.iter().all(CheckResult::passed)
+}
+
+/// Re-publishes the data files of the ticker directory `dir` to the configured sinks, for `--replay`.
+///
+/// The exchange and symbol are the last two components of `dir`, e.g. `data/BINANCE/BTC_USDT`.
+/// Raw responses are checked with the exchange's parser if the exchange is compiled in.
+///
+/// # Returns
+///
+/// `true` if the directory was replayed.
+fn replay_dir(config: Config, dir: &str) -> bool {
+    let Some((exchange, symbol)) = ticker_of_dir(Path::new(dir)) else {
+        error!("Cannot tell the exchange and symbol of {}; pass a directory like data/BINANCE/BTC_USDT", dir);
+        return false;
+    };
+    let sinks = sink::from_config(&config);
+    if sinks.is_empty() {
+        error!("No sink is configured to replay {} to", dir);
+        return false;
+    }
+    let api = config.http.build_client().ok().and_then(|client| api_for(&exchange, client, &config));
+    if api.is_none() {
+        warn!("Replaying {} without checking its responses: {} is not available", dir, exchange);
+    }
+    let result = replay(Path::new(dir), &exchange, &symbol, api.as_deref(), &sinks);
+    // Dropping the sinks flushes the records they still hold, e.g. the SQLite writer's queue
+    drop(sinks);
+    match result {
+        Ok(stats) => {
+            info!(
+                "Replayed {} records from {} files of {}, skipped {}, failed to publish {}",
+                stats.records, stats.files, dir, stats.skipped, stats.failed
+            );
+            true
+        }
+        Err(e) => {
+            error!("Failed to replay {}: {}", dir, e);
+            false
+        }
+    }
+}
+
+/// Prints the records of the given data files to stdout, decompressing compressed lines.
+fn decode_files(paths: &[String]) {
+    for path in paths {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) => {
+                warn!("Cannot read {}: {}", path, e);
+                continue;
+            }
+        };
+        for line in contents.lines() {
+            match decode_line(line) {
+                Ok(record) => println!("{}", record),
+                Err(e) => warn!("Cannot decode line in {}: {}", path, e),
             }
         }
-        Err(e) => eprintln!("Failed to load config: {}", e),
+    }
+}
+
+/// How often the watchdog looks for stalled workers.
+const WATCHDOG_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Starts a thread that reports, and optionally restarts, stalled workers of the collector every `WATCHDOG_INTERVAL`.
+fn spawn_watchdog(collector: Arc<Mutex<OrderBookCollector>>) {
+    std::thread::spawn(move || loop {
+        std::thread::sleep(WATCHDOG_INTERVAL);
+        collector.lock().unwrap().check_stalled();
+    });
+}
+
+/// Starts a thread that restarts all workers of the collector whenever the process receives SIGUSR1.
+fn spawn_restart_signal_handler(collector: Arc<Mutex<OrderBookCollector>>) {
+    std::thread::spawn(move || {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("Unable to create Tokio runtime");
+        runtime.block_on(async move {
+            use tokio::signal::unix::{signal, SignalKind};
+            let mut signals = match signal(SignalKind::user_defined1()) {
+                Ok(signals) => signals,
+                Err(e) => {
+                    error!("Failed to listen for SIGUSR1: {}", e);
+                    return;
+                }
+            };
+            while signals.recv().await.is_some() {
+                info!("Received SIGUSR1");
+                collector.lock().unwrap().restart_all();
+            }
+        });
+    });
+}
+
+/// Initializes the log subscriber writing to stderr.
+///
+/// The level filter is read from `RUST_LOG` and defaults to `info`. With `LOG_FORMAT=json`,
+/// each event is logged as one JSON object per line, e.g. for a log collector.
+fn init_logging() {
+    let filter = tracing_subscriber::EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info"));
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr);
+    if std::env::var("LOG_FORMAT").is_ok_and(|format| format == "json") {
+        builder.json().init();
+    } else {
+        builder.init();
     }
 }
 
 fn main() {
+    init_logging();
+
+    // `decode <file>...` prints the records of data files instead of collecting
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("decode") {
+        decode_files(&args[1..]);
+        return;
+    }
+
+    // The configuration file comes from `--config <path>`, `COLLECTOR_CONFIG` or defaults to config.json
+    let config_path = Config::resolve_path(&args);
+    info!("Using configuration file {}", config_path);
+
+    // `--once` fetches a single snapshot of every ticker and exits, printing it with `--stdout`
+    if args.iter().any(|arg| arg == "--once") {
+        let stdout = args.iter().any(|arg| arg == "--stdout");
+        let config = load_valid_config(&config_path)
+            .and_then(|config| if stdout { Ok(config) } else { check_output_dir(&config).map(|_| config) });
+        match config {
+            Ok(config) => std::process::exit(if collect_once(config, stdout) { 0 } else { 1 }),
+            Err(e) => {
+                error!("Invalid configuration {}: {}", config_path, e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // `--check` fetches and parses one book of every ticker, prints the results and exits
+    if args.iter().any(|arg| arg == "--check") {
+        match load_valid_config(&config_path) {
+            Ok(config) => std::process::exit(if check_all(config) { 0 } else { 1 }),
+            Err(e) => {
+                error!("Invalid configuration {}: {}", config_path, e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // `--replay <dir>` re-publishes a ticker's collected files to the configured sinks and exits
+    if let Some(position) = args.iter().position(|arg| arg == "--replay") {
+        let Some(dir) = args.get(position + 1) else {
+            error!("--replay needs a ticker directory, e.g. --replay data/BINANCE/BTC_USDT");
+            std::process::exit(1);
+        };
+        match load_valid_config(&config_path) {
+            Ok(config) => std::process::exit(if replay_dir(config, dir) { 0 } else { 1 }),
+            Err(e) => {
+                error!("Invalid configuration {}: {}", config_path, e);
+                std::process::exit(1);
+            }
+        }
+    }
+
     // Initialize the OrderBookCollector
     let mut collector = OrderBookCollector::new();
 
-    // Load and apply the initial configuration
-    update_tasks_based_on_config(&mut collector);
+    // An invalid initial configuration is fatal, rather than leaving the service running idle
+    let config = match load_valid_config(&config_path) {
+        Ok(config) => config,
+        Err(e) => {
+            error!("Invalid configuration {}: {}", config_path, e);
+            std::process::exit(1);
+        }
+    };
+
+    // So is an output directory that cannot be written, rather than every worker failing to save
+    if let Err(e) = check_output_dir(&config) {
+        error!("Cannot start collecting: {}", e);
+        std::process::exit(1);
+    }
+
+    // Start streaming snapshots on the unix socket if one is configured
+    if let Some(path) = &config.unix_socket {
+        if let Err(e) = socket_export::spawn(path, collector.snapshot_sender()) {
+            error!("Failed to bind unix socket {}: {}", path, e);
+        }
+    }
+
+    // Serve the Prometheus metrics if a port is configured; collection runs on without them
+    if let Some(port) = config.metrics_port {
+        #[cfg(feature = "metrics")]
+        if let Err(e) = metrics::spawn(port, collector.metrics()) {
+            error!("Failed to serve metrics on port {}: {}", port, e);
+        }
+        #[cfg(not(feature = "metrics"))]
+        warn!("metrics_port {} is set but the metrics feature is not enabled", port);
+    }
+
+    // Report collection events to the webhook if one is configured
+    if let Some(url) = &config.webhook_url {
+        let min_interval = std::time::Duration::from_millis(config.webhook_min_interval_ms);
+        match events::spawn_webhook(url, min_interval) {
+            Ok(events) => collector.set_events(events),
+            Err(e) => error!("Failed to start webhook {}: {}", url, e),
+        }
+    }
+
+    // Publish records to the configured sinks, e.g. Kafka
+    collector.set_sinks(sink::from_config(&config));
+
+    // Apply the initial configuration; one that cannot be applied in full is fatal too
+    if let Err(e) = collector.reload(config) {
+        error!("Cannot start collecting: {}", e);
+        std::process::exit(1);
+    }
+    let collector = Arc::new(Mutex::new(collector));
+
+    // Restart every worker on SIGUSR1, e.g. after a transient exchange outage
+    spawn_restart_signal_handler(collector.clone());
+
+    // Report symbols that stopped writing records, e.g. because every fetch fails
+    spawn_watchdog(collector.clone());
 
     // Set up a filesystem watcher to monitor changes in the configuration file
+    let (changes, changed) = mpsc::channel();
     let mut watcher = notify::recommended_watcher(move |res| {
         match res {
             Ok(event) => {
-                println!("Change detected: {:?}", event);
-                // Reload the configuration and update tasks upon any change
-                update_tasks_based_on_config(&mut collector);
+                info!("Change detected: {:?}", event);
+                let _ = changes.send(event);
             }
-            Err(e) => println!("watch error: {:?}", e),
+            Err(e) => warn!("watch error: {:?}", e),
         }
     }).unwrap();
 
     // Watch the configuration file for changes
-    watcher.watch(Path::new(Config::path()), RecursiveMode::NonRecursive).unwrap();
+    watcher.watch(Path::new(&config_path), RecursiveMode::NonRecursive).unwrap();
 
-    // Keep the main thread alive to continuously monitor for changes
-    loop {}
+    // Reload once the file has settled, so a save firing several events, or still being written, reloads once
+    while let Some(events) = next_burst(&changed, RELOAD_DEBOUNCE) {
+        info!("Reloading configuration after {} change events", events);
+        update_tasks_based_on_config(&mut collector.lock().unwrap(), &config_path);
+    }
 }
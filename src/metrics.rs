@@ -0,0 +1,186 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+
+/// The collection counters and gauges served on the Prometheus `/metrics` endpoint.
+///
+/// The workers count their written snapshots and failed fetches, and the collector keeps the
+/// number of collected symbols of each exchange up to date. Counting is cheap, so it happens
+/// whether or not the endpoint is enabled.
+#[derive(Clone, Default)]
+pub struct Metrics {
+    state: Arc<Mutex<MetricsState>>,
+}
+
+#[derive(Default)]
+struct MetricsState {
+    /// The snapshots written per exchange and symbol.
+    snapshots_written: BTreeMap<(String, String), u64>,
+    /// The failed or timed-out fetches per exchange and symbol.
+    fetch_errors: BTreeMap<(String, String), u64>,
+    /// The stalls reported by the watchdog per exchange and symbol.
+    worker_stalls: BTreeMap<(String, String), u64>,
+    /// The number of symbols collected per exchange.
+    active_symbols: BTreeMap<String, usize>,
+}
+
+impl Metrics {
+    /// Counts a snapshot of `symbol` written by a worker.
+    pub fn snapshot_written(&self, exchange: &str, symbol: &str) {
+        let mut state = self.state.lock().unwrap();
+        *state.snapshots_written.entry((exchange.to_string(), symbol.to_string())).or_insert(0) += 1;
+    }
+
+    /// Counts a failed or timed-out fetch of `symbol`.
+    pub fn fetch_error(&self, exchange: &str, symbol: &str) {
+        let mut state = self.state.lock().unwrap();
+        *state.fetch_errors.entry((exchange.to_string(), symbol.to_string())).or_insert(0) += 1;
+    }
+
+    /// Counts a stall of `symbol` reported by the watchdog.
+    pub fn worker_stalled(&self, exchange: &str, symbol: &str) {
+        let mut state = self.state.lock().unwrap();
+        *state.worker_stalls.entry((exchange.to_string(), symbol.to_string())).or_insert(0) += 1;
+    }
+
+    /// Replaces the number of symbols collected per exchange, e.g. after workers were started or stopped.
+    ///
+    /// Exchanges missing from `active_symbols` report 0 if they were collected before.
+    pub fn set_active_symbols(&self, active_symbols: &HashMap<String, usize>) {
+        let mut state = self.state.lock().unwrap();
+        for count in state.active_symbols.values_mut() {
+            *count = 0;
+        }
+        for (exchange, count) in active_symbols {
+            state.active_symbols.insert(exchange.clone(), *count);
+        }
+    }
+
+    /// Renders the metrics in the Prometheus text exposition format.
+    #[cfg_attr(not(feature = "metrics"), allow(dead_code))]
+    pub fn render(&self) -> String {
+        let state = self.state.lock().unwrap();
+        let mut text = String::new();
+        render_symbol_counter(
+            &mut text,
+            "snapshots_written_total",
+            "Order book snapshots written.",
+            &state.snapshots_written,
+        );
+        render_symbol_counter(
+            &mut text,
+            "fetch_errors_total",
+            "Order book fetches that failed or timed out.",
+            &state.fetch_errors,
+        );
+        render_symbol_counter(
+            &mut text,
+            "worker_stalls_total",
+            "Times a symbol went without writing a record for too long.",
+            &state.worker_stalls,
+        );
+        let _ = writeln!(text, "# HELP active_symbols Symbols collected.");
+        let _ = writeln!(text, "# TYPE active_symbols gauge");
+        for (exchange, count) in &state.active_symbols {
+            let _ = writeln!(text, "active_symbols{{exchange=\"{}\"}} {}", escape_label(exchange), count);
+        }
+        text
+    }
+}
+
+/// Appends a counter labelled by exchange and symbol to `text`.
+#[cfg_attr(not(feature = "metrics"), allow(dead_code))]
+fn render_symbol_counter(text: &mut String, name: &str, help: &str, values: &BTreeMap<(String, String), u64>) {
+    let _ = writeln!(text, "# HELP {} {}", name, help);
+    let _ = writeln!(text, "# TYPE {} counter", name);
+    for ((exchange, symbol), value) in values {
+        let _ = writeln!(
+            text, "{}{{exchange=\"{}\",symbol=\"{}\"}} {}", name, escape_label(exchange), escape_label(symbol), value
+        );
+    }
+}
+
+/// Escapes a label value for the text exposition format.
+#[cfg_attr(not(feature = "metrics"), allow(dead_code))]
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Starts an HTTP server serving `metrics` on `/metrics` at `0.0.0.0:<port>`.
+///
+/// The port is bound before returning, so a port in use is reported to the caller; the server
+/// then runs on its own thread and runtime, apart from the collection workers.
+///
+/// # Errors
+///
+/// Returns an `io::Error` if the port cannot be bound or the server's runtime cannot be created.
+#[cfg(feature = "metrics")]
+pub fn spawn(port: u16, metrics: Metrics) -> std::io::Result<std::thread::JoinHandle<()>> {
+    use axum::routing::get;
+
+    let listener = std::net::TcpListener::bind(("0.0.0.0", port))?;
+    listener.set_nonblocking(true)?;
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+    let listener = {
+        let _guard = runtime.enter();
+        tokio::net::TcpListener::from_std(listener)?
+    };
+    tracing::info!("Serving metrics on port {}", port);
+
+    Ok(std::thread::spawn(move || {
+        runtime.block_on(async move {
+            let app = axum::Router::new().route("/metrics", get(move || async move {
+                ([("content-type", "text/plain; version=0.0.4")], metrics.render())
+            }));
+            if let Err(e) = axum::serve(listener, app).await {
+                tracing::error!("Metrics server failed: {}", e);
+            }
+        });
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::default();
+        metrics.snapshot_written("BINANCE", "BTC_USDT");
+        metrics.snapshot_written("BINANCE", "BTC_USDT");
+        metrics.fetch_error("BITKUB", "BTC_THB");
+        metrics.worker_stalled("BITKUB", "BTC_THB");
+        metrics.set_active_symbols(&HashMap::from([("BINANCE".to_string(), 2), ("BITKUB".to_string(), 1)]));
+        metrics.set_active_symbols(&HashMap::from([("BINANCE".to_string(), 1)]));
+
+        assert_eq!(metrics.render(), "\
+# HELP snapshots_written_total Order book snapshots written.
+# TYPE snapshots_written_total counter
+snapshots_written_total{exchange=\"BINANCE\",symbol=\"BTC_USDT\"} 2
+# HELP fetch_errors_total Order book fetches that failed or timed out.
+# TYPE fetch_errors_total counter
+fetch_errors_total{exchange=\"BITKUB\",symbol=\"BTC_THB\"} 1
+# HELP worker_stalls_total Times a symbol went without writing a record for too long.
+# TYPE worker_stalls_total counter
+worker_stalls_total{exchange=\"BITKUB\",symbol=\"BTC_THB\"} 1
+# HELP active_symbols Symbols collected.
+# TYPE active_symbols gauge
+active_symbols{exchange=\"BINANCE\"} 1
+active_symbols{exchange=\"BITKUB\"} 0
+");
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn test_serves_metrics() {
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let metrics = Metrics::default();
+        metrics.snapshot_written("BINANCE", "BTC_USDT");
+        spawn(port, metrics).unwrap();
+
+        let client = reqwest::Client::builder().no_proxy().build().unwrap();
+        let body = client.get(format!("http://127.0.0.1:{}/metrics", port)).send().await.unwrap()
+            .text().await.unwrap();
+        assert!(body.contains("snapshots_written_total{exchange=\"BINANCE\",symbol=\"BTC_USDT\"} 1"), "{}", body);
+    }
+}
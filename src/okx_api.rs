@@ -0,0 +1,273 @@
+use async_trait::async_trait;
+use std::error::Error;
+use serde_json::Value;
+use crate::cex_api::{CexApi, ErrorMatcher, ServerError};
+use crate::config::ErrorSubstrings;
+use crate::order_book::{parse_levels, OrderBook};
+use crate::ticker::Ticker;
+
+/// The response substrings that mark an error response. OKX errors are detected from the
+/// `code` field of the response, so none are built in; the `error_substrings` config can add some.
+const ERROR_SUBSTRINGS: [&str; 0] = [];
+
+/// The host of the production REST API, used unless a base URL is configured.
+pub const DEFAULT_BASE_URL: &str = "https://www.okx.com";
+
+/// Represents the OKX spot API for fetching order book data.
+///
+/// All requests go through one shared `reqwest::Client`, so connections to the exchange are pooled
+/// across every ticker collected from it.
+pub struct OkxApi {
+    client: reqwest::Client,
+    errors: ErrorMatcher,
+    /// The scheme and host of the REST endpoints, without a trailing slash.
+    base_url: String,
+}
+
+impl Default for OkxApi {
+    /// Creates an `OkxApi` with a default `reqwest::Client`.
+    fn default() -> Self {
+        OkxApi::new(reqwest::Client::new())
+    }
+}
+
+#[async_trait]
+impl CexApi for OkxApi {
+    /// Returns the name of the exchange.
+    ///
+    /// # Returns
+    /// A static string slice representing the name of the exchange.
+    fn name(&self) -> &'static str {
+        "OKX"
+    }
+
+    /// Returns the OKX instrument id of a ticker, e.g. `BTC-USDT`.
+    fn format_symbol(&self, ticker: &Ticker) -> String {
+        format!("{}-{}", ticker.base, ticker.quote)
+    }
+
+    /// Asynchronously fetches the order book for a given ticker and depth from OKX.
+    ///
+    /// A response whose `code` is not `"0"` is an error.
+    ///
+    /// # Arguments
+    /// * `ticker` - A reference to a `Ticker` struct containing the base and quote currencies.
+    /// * `depth` - The depth of the order book to fetch.
+    ///
+    /// # Returns
+    /// A `Result` which is either a string containing the order book data or an error.
+    async fn get_order_book(&self, ticker: &Ticker, depth: u32) -> Result<String, Box<dyn Error>> {
+        let response = self.client.get(format!(
+            "{}/api/v5/market/books?instId={}&sz={}",
+            self.base_url, self.format_symbol(ticker), depth
+        )).send().await?;
+        ServerError::check(&response)?;
+        let response_text = response.text().await?;
+
+        if self.errors.is_error(&response_text) {
+            return Err("Error in response from OKX".into());
+        }
+        let json: Value = serde_json::from_str(&response_text)?;
+        OkxApi::check_code(&json)?;
+        Ok(response_text)
+    }
+
+    /// Parses an OKX books response, e.g.
+    /// `{"code": "0", "msg": "", "data": [{"asks": [["42000.2", "0.25", "0", "3"]], "bids": [...], "ts": "1700000000123"}]}`.
+    ///
+    /// Each level is `[price, quantity, deprecated, num_orders]`, of which only the price and
+    /// quantity are kept. The `ts` of the book becomes its `exchange_ts`.
+    ///
+    /// # Arguments
+    /// * `raw` - The raw JSON response text.
+    ///
+    /// # Returns
+    /// A `Result` which is either the normalized `OrderBook` or an error.
+    fn parse_order_book(&self, raw: &str) -> Result<OrderBook, Box<dyn Error>> {
+        let json: Value = serde_json::from_str(raw)?;
+        OkxApi::check_code(&json)?;
+        let book = json["data"].get(0).ok_or("Missing data in OKX books response")?;
+        Ok(OrderBook {
+            bids: parse_levels(&book["bids"])?,
+            asks: parse_levels(&book["asks"])?,
+            exchange_ts: book["ts"].as_str().and_then(|ts| ts.parse().ok()),
+            sequence: None,
+            requested_depth: None,
+        })
+    }
+
+    /// Asynchronously fetches the mark price of a USDT perpetual swap and the index price of its pair from OKX.
+    ///
+    /// OKX serves them from two endpoints, so both responses are combined into one, e.g.
+    /// `{"mark": {"code": "0", "data": [{"instId": "BTC-USDT-SWAP", "markPx": "42000.5", ...}]}, "index": {"code": "0", "data": [{"instId": "BTC-USDT", "idxPx": "42001.2", ...}]}}`.
+    ///
+    /// # Arguments
+    /// * `ticker` - A reference to a `Ticker` struct containing the base and quote currencies.
+    ///
+    /// # Returns
+    /// A `Result` which is either a string containing the combined price data or an error.
+    async fn get_mark_price(&self, ticker: &Ticker) -> Result<String, Box<dyn Error>> {
+        let symbol = self.format_symbol(ticker);
+        let mark = self.get_checked(&format!("/api/v5/public/mark-price?instType=SWAP&instId={}-SWAP", symbol)).await?;
+        let index = self.get_checked(&format!("/api/v5/market/index-tickers?instId={}", symbol)).await?;
+        Ok(format!(r#"{{"mark": {}, "index": {}}}"#, mark.trim_end(), index.trim_end()))
+    }
+
+    /// Returns the interval at which the order book should be fetched.
+    ///
+    /// # Returns
+    /// A `u64` representing the interval in seconds.
+    fn get_order_book_interval(&self) -> u64 {
+        1
+    }
+
+    /// Returns OKX's public order book limit of 40 requests per 2 seconds.
+    fn rate_limit(&self) -> u32 {
+        20
+    }
+}
+
+impl OkxApi {
+    /// Creates a new `OkxApi` sending its requests through `client`.
+    pub fn new(client: reqwest::Client) -> OkxApi {
+        OkxApi {
+            client,
+            errors: ErrorMatcher::new(&ERROR_SUBSTRINGS, None),
+            base_url: DEFAULT_BASE_URL.to_string(),
+        }
+    }
+
+    /// Returns the API sending its requests to `base_url`, if set, e.g. a local mock server.
+    pub fn with_base_url(mut self, base_url: Option<&str>) -> OkxApi {
+        self.base_url = base_url.unwrap_or(DEFAULT_BASE_URL).trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Returns the API with its error-detection substrings extended or replaced by `overrides`.
+    pub fn with_error_substrings(mut self, overrides: Option<&ErrorSubstrings>) -> OkxApi {
+        self.errors = ErrorMatcher::new(&ERROR_SUBSTRINGS, overrides);
+        self
+    }
+
+    /// Fetches a public endpoint, e.g. `/api/v5/public/mark-price?...`, and checks the `code` of its response.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails, the response is not JSON or its code is not `"0"`.
+    async fn get_checked(&self, path_and_query: &str) -> Result<String, Box<dyn Error>> {
+        let response = self.client.get(format!("{}{}", self.base_url, path_and_query)).send().await?;
+        ServerError::check(&response)?;
+        let response_text = response.text().await?;
+
+        let json: Value = serde_json::from_str(&response_text)?;
+        OkxApi::check_code(&json)?;
+        Ok(response_text)
+    }
+
+    /// Checks the `code` of an OKX response, which is `"0"` on success.
+    ///
+    /// # Errors
+    ///
+    /// Returns the code and `msg` of the response if the code is anything else or missing.
+    fn check_code(json: &Value) -> Result<(), Box<dyn Error>> {
+        match json["code"].as_str() {
+            Some("0") => Ok(()),
+            code => Err(format!(
+                "OKX returned code {}: {}",
+                code.unwrap_or("none"), json["msg"].as_str().unwrap_or("")
+            ).into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    /// Returns an `OkxApi` sending its requests to `server`, bypassing any proxy set in the environment.
+    fn mock_api(server: &MockServer) -> OkxApi {
+        let client = reqwest::Client::builder().no_proxy().build().unwrap();
+        OkxApi::new(client).with_base_url(Some(&server.uri()))
+    }
+
+    /// Mounts a response to the books request of `BTC-USDT` with 10 levels.
+    async fn mock_books(server: &MockServer, body: &str) {
+        Mock::given(method("GET"))
+            .and(path("/api/v5/market/books"))
+            .and(query_param("instId", "BTC-USDT"))
+            .and(query_param("sz", "10"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(body))
+            .mount(server)
+            .await;
+    }
+
+    #[test]
+    fn test_okx_api_name() {
+        assert_eq!(OkxApi::default().name(), "OKX");
+    }
+
+    #[test]
+    fn test_format_symbol() {
+        let ticker = Ticker::new("BTC_USDT").unwrap();
+        assert_eq!(OkxApi::default().format_symbol(&ticker), "BTC-USDT");
+    }
+
+    #[tokio::test]
+    async fn test_get_order_book() {
+        let server = MockServer::start().await;
+        let body = r#"{"code":"0","msg":"","data":[{"asks":[["42000.2","0.25","0","3"]],"bids":[["42000.1","1.5","0","7"]],"ts":"1700000000123"}]}"#;
+        mock_books(&server, body).await;
+
+        let ticker = Ticker::new("BTC_USDT").unwrap();
+        let response = mock_api(&server).get_order_book(&ticker, 10).await.unwrap();
+        assert_eq!(response, body);
+        let book = OkxApi::default().parse_order_book(&response).unwrap();
+        assert_eq!(book.bids, vec![(42000.1, 1.5)]);
+        assert_eq!(book.asks, vec![(42000.2, 0.25)]);
+        assert_eq!(book.exchange_ts, Some(1700000000123));
+    }
+
+    #[tokio::test]
+    async fn test_get_order_book_error_code() {
+        let server = MockServer::start().await;
+        mock_books(&server, r#"{"code":"51001","msg":"Instrument ID does not exist.","data":[]}"#).await;
+
+        let ticker = Ticker::new("BTC_USDT").unwrap();
+        let error = mock_api(&server).get_order_book(&ticker, 10).await.unwrap_err();
+        assert_eq!(error.to_string(), "OKX returned code 51001: Instrument ID does not exist.");
+    }
+
+    #[tokio::test]
+    async fn test_get_mark_price() {
+        let server = MockServer::start().await;
+        let mark = r#"{"code":"0","msg":"","data":[{"instType":"SWAP","instId":"BTC-USDT-SWAP","markPx":"42000.5","ts":"1700000000123"}]}"#;
+        let index = r#"{"code":"0","msg":"","data":[{"instId":"BTC-USDT","idxPx":"42001.2","ts":"1700000000100"}]}"#;
+        Mock::given(method("GET"))
+            .and(path("/api/v5/public/mark-price"))
+            .and(query_param("instType", "SWAP"))
+            .and(query_param("instId", "BTC-USDT-SWAP"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(mark))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v5/market/index-tickers"))
+            .and(query_param("instId", "BTC-USDT"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(index))
+            .mount(&server)
+            .await;
+
+        let ticker = Ticker::new("BTC_USDT").unwrap();
+        let response = mock_api(&server).get_mark_price(&ticker).await.unwrap();
+        let json: Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(json["mark"]["data"][0]["markPx"], "42000.5");
+        assert_eq!(json["index"]["data"][0]["idxPx"], "42001.2");
+    }
+
+    #[test]
+    fn test_parse_order_book_without_data() {
+        assert!(OkxApi::default().parse_order_book(r#"{"code":"0","msg":"","data":[]}"#).is_err());
+        assert!(OkxApi::default().parse_order_book(r#"{"msg":"","data":[]}"#).is_err());
+    }
+}
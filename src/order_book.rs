@@ -0,0 +1,447 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::error::Error;
+
+/// A normalized order book snapshot, independent of the exchange's response format.
+///
+/// Each level is a `(price, quantity)` pair. Bids are ordered from the highest price and
+/// asks from the lowest, as returned by the exchanges.
+#[derive(Debug, Clone, PartialEq, Default, Deserialize, Serialize)]
+pub struct OrderBook {
+    pub bids: Vec<(f64, f64)>,
+    pub asks: Vec<(f64, f64)>,
+    /// The exchange-provided timestamp of the snapshot in milliseconds, if available.
+    pub exchange_ts: Option<i64>,
+    /// The exchange's sequence number or update id of the snapshot, if it provides one.
+    ///
+    /// Omitted from the serialized book when absent, so books of exchanges without one are unchanged.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sequence: Option<u64>,
+    /// The depth requested from the exchange, noted only when a side has fewer levels, see `with_requested_depth`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub requested_depth: Option<u32>,
+}
+
+impl OrderBook {
+    /// Returns a copy of the order book keeping only the best `depth` levels on each side.
+    pub fn truncated(&self, depth: usize) -> OrderBook {
+        OrderBook {
+            bids: self.bids.iter().take(depth).cloned().collect(),
+            asks: self.asks.iter().take(depth).cloned().collect(),
+            exchange_ts: self.exchange_ts,
+            sequence: self.sequence,
+            requested_depth: self.requested_depth,
+        }
+    }
+
+    /// Returns the book with `requested_depth` noted if either side has fewer levels than that.
+    ///
+    /// Thin markets legitimately return short books, so they are kept, but the note lets consumers
+    /// tell a short book from a full one.
+    pub fn with_requested_depth(mut self, requested_depth: u32) -> OrderBook {
+        if self.is_short(requested_depth) {
+            self.requested_depth = Some(requested_depth);
+        }
+        self
+    }
+
+    /// Returns whether either side has fewer than `requested_depth` levels.
+    pub fn is_short(&self, requested_depth: u32) -> bool {
+        self.bids.len() < requested_depth as usize || self.asks.len() < requested_depth as usize
+    }
+
+    /// Returns whether both sides are empty, which makes the book an empty snapshot rather than a thin one.
+    pub fn is_empty(&self) -> bool {
+        self.bids.is_empty() && self.asks.is_empty()
+    }
+
+    /// Returns whether `other` has the same bid and ask levels, ignoring the exchange timestamp and sequence.
+    pub fn same_levels(&self, other: &OrderBook) -> bool {
+        self.bids == other.bids && self.asks == other.asks
+    }
+
+    /// Returns a copy without zero-quantity levels, keeping at most `depth` levels on each side.
+    ///
+    /// Some exchanges pad thin books to the requested depth with zero-quantity levels.
+    pub fn without_padding(&self, depth: usize) -> OrderBook {
+        let keep = |levels: &[(f64, f64)]| levels.iter()
+            .filter(|(_, quantity)| *quantity != 0.0)
+            .take(depth)
+            .cloned()
+            .collect();
+        OrderBook {
+            bids: keep(&self.bids),
+            asks: keep(&self.asks),
+            exchange_ts: self.exchange_ts,
+            sequence: self.sequence,
+            requested_depth: self.requested_depth,
+        }
+    }
+
+    /// Serializes the order book with each side padded to `depth` levels with `null`.
+    pub fn to_padded_json(&self, depth: usize) -> Value {
+        let pad = |levels: &[(f64, f64)]| {
+            let mut padded: Vec<Value> = levels.iter().take(depth).map(|level| serde_json::json!(level)).collect();
+            padded.resize(depth, Value::Null);
+            Value::Array(padded)
+        };
+        let mut json = serde_json::json!({
+            "bids": pad(&self.bids),
+            "asks": pad(&self.asks),
+            "exchange_ts": self.exchange_ts,
+        });
+        if let Some(sequence) = self.sequence {
+            json["sequence"] = sequence.into();
+        }
+        json
+    }
+
+    /// Returns the levels that changed from `self` to `next`, as an order book-shaped delta.
+    ///
+    /// The delta lists every level of `next` that is new or has a different quantity, and every
+    /// level of `self` missing from `next` with a quantity of `0`. Its `exchange_ts` and `sequence` are those of `next`.
+    pub fn diff(&self, next: &OrderBook) -> OrderBook {
+        OrderBook {
+            bids: diff_levels(&self.bids, &next.bids),
+            asks: diff_levels(&self.asks, &next.asks),
+            exchange_ts: next.exchange_ts,
+            sequence: next.sequence,
+            requested_depth: None,
+        }
+    }
+
+    /// Applies a delta produced by `diff`, updating, adding and removing levels in place.
+    ///
+    /// Consumers rebuild the book of a `"delta"` record by applying every delta since the last
+    /// `"snapshot"` record, in order, to that snapshot. Exchange diff streams use the same semantics.
+    pub fn apply(&mut self, delta: &OrderBook) {
+        apply_levels(&mut self.bids, &delta.bids, true);
+        apply_levels(&mut self.asks, &delta.asks, false);
+        self.exchange_ts = delta.exchange_ts;
+        self.sequence = delta.sequence;
+    }
+
+    /// Computes the cumulative quantity on each side within `thresholds_pct` percent of the midprice.
+    ///
+    /// Only the levels present in the book are counted, so the curve of a distance beyond the
+    /// deepest fetched level understates the real liquidity.
+    ///
+    /// # Returns
+    ///
+    /// The `DepthCurve`, or `None` if the book has no midprice.
+    pub fn depth_curve(&self, thresholds_pct: &[f64]) -> Option<DepthCurve> {
+        let mid = self.mid_price()?;
+        let within = |levels: &[(f64, f64)], limit: &dyn Fn(f64) -> bool| -> f64 {
+            levels.iter().filter(|(price, _)| limit(*price)).map(|(_, quantity)| quantity).sum()
+        };
+        Some(DepthCurve {
+            mid,
+            pct: thresholds_pct.to_vec(),
+            bids: thresholds_pct.iter()
+                .map(|pct| within(&self.bids, &|price| price >= mid * (1.0 - pct / 100.0)))
+                .collect(),
+            asks: thresholds_pct.iter()
+                .map(|pct| within(&self.asks, &|price| price <= mid * (1.0 + pct / 100.0)))
+                .collect(),
+        })
+    }
+
+    /// Returns the spread between the best ask and the best bid in basis points of the midprice.
+    ///
+    /// The spread is negative for a crossed book, whose best ask is below its best bid.
+    pub fn spread_bps(&self) -> Option<f64> {
+        let (best_bid, _) = self.bids.first()?;
+        let (best_ask, _) = self.asks.first()?;
+        let mid = self.mid_price()?;
+        Some((best_ask - best_bid) / mid * 10_000.0)
+    }
+
+    /// Returns the midpoint between the best bid and the best ask, if both sides have levels.
+    pub fn mid_price(&self) -> Option<f64> {
+        let (best_bid, _) = self.bids.first()?;
+        let (best_ask, _) = self.asks.first()?;
+        Some((best_bid + best_ask) / 2.0)
+    }
+}
+
+/// The cumulative quantity available within several distances of the midprice.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DepthCurve {
+    /// The midprice the distances are measured from.
+    pub mid: f64,
+    /// The distances from the midprice, in percent.
+    pub pct: Vec<f64>,
+    /// The total bid quantity priced within each distance below the midprice.
+    pub bids: Vec<f64>,
+    /// The total ask quantity priced within each distance above the midprice.
+    pub asks: Vec<f64>,
+}
+
+/// A normalized order book tagged with the time it was collected and where it came from.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Snapshot {
+    /// The unix timestamp at which the snapshot was collected.
+    pub time: i64,
+    /// The name of the exchange (e.g., "BINANCE").
+    pub exchange: String,
+    /// The ticker symbol in "BASE_QUOTE" form.
+    pub symbol: String,
+    #[serde(flatten)]
+    pub book: OrderBook,
+}
+
+/// Returns the levels of `next` that differ from `previous`, plus removed levels with a zero quantity.
+fn diff_levels(previous: &[(f64, f64)], next: &[(f64, f64)]) -> Vec<(f64, f64)> {
+    let changed = next.iter()
+        .filter(|level| !previous.contains(level))
+        .cloned();
+    let removed = previous.iter()
+        .filter(|(price, _)| !next.iter().any(|(next_price, _)| next_price == price))
+        .map(|(price, _)| (*price, 0.0));
+    changed.chain(removed).collect()
+}
+
+/// Applies changed levels to one side of a book, keeping bids descending and asks ascending by price.
+fn apply_levels(levels: &mut Vec<(f64, f64)>, changes: &[(f64, f64)], descending: bool) {
+    for (price, quantity) in changes {
+        levels.retain(|(level_price, _)| level_price != price);
+        if *quantity != 0.0 {
+            levels.push((*price, *quantity));
+        }
+    }
+    levels.sort_by(|a, b| if descending { b.0.total_cmp(&a.0) } else { a.0.total_cmp(&b.0) });
+}
+
+/// Parses a JSON array of order book levels into `(price, quantity)` pairs.
+///
+/// Exchanges encode the numbers either as JSON numbers or as strings (e.g. `["42000.01", "0.5"]`),
+/// so both are accepted. Any elements after the price and quantity are ignored.
+///
+/// # Arguments
+///
+/// * `levels` - A JSON value expected to be an array of `[price, quantity, ...]` arrays.
+///
+/// # Errors
+///
+/// Returns an error if the value is not an array of levels or a number cannot be parsed.
+pub fn parse_levels(levels: &Value) -> Result<Vec<(f64, f64)>, Box<dyn Error>> {
+    let levels = levels.as_array().ok_or("Order book levels are not an array")?;
+    levels
+        .iter()
+        .map(|level| {
+            let price = level.get(0).ok_or("Missing price in order book level")?;
+            let quantity = level.get(1).ok_or("Missing quantity in order book level")?;
+            Ok((parse_number(price)?, parse_number(quantity)?))
+        })
+        .collect()
+}
+
+/// Parses a JSON number or a string-encoded number into an `f64`.
+fn parse_number(value: &Value) -> Result<f64, Box<dyn Error>> {
+    match value {
+        Value::Number(number) => number.as_f64().ok_or_else(|| "Invalid number".into()),
+        Value::String(text) => Ok(text.parse::<f64>()?),
+        _ => Err(format!("Expected a number, got {}", value).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_levels_accepts_strings_and_numbers() {
+        let levels = json!([["42000.01", "0.5"], [41999.5, 1.25, 3]]);
+        assert_eq!(parse_levels(&levels).unwrap(), vec![(42000.01, 0.5), (41999.5, 1.25)]);
+    }
+
+    #[test]
+    fn test_parse_levels_rejects_invalid_input() {
+        assert!(parse_levels(&json!({"price": 1})).is_err());
+        assert!(parse_levels(&json!([["abc", "1"]])).is_err());
+        assert!(parse_levels(&json!([["1"]])).is_err());
+    }
+
+    #[test]
+    fn test_with_requested_depth_notes_short_books() {
+        let levels = |count: usize| (0..count).map(|i| (100.0 + i as f64, 1.0)).collect::<Vec<_>>();
+        let short = OrderBook { bids: levels(3), asks: levels(3), ..Default::default() }.with_requested_depth(10);
+        assert_eq!(short.requested_depth, Some(10));
+        assert!(short.is_short(10) && !short.is_empty());
+        assert_eq!(serde_json::to_value(&short).unwrap()["requested_depth"], 10);
+
+        let full = OrderBook { bids: levels(10), asks: levels(12), ..Default::default() }.with_requested_depth(10);
+        assert_eq!(full.requested_depth, None);
+        assert!(serde_json::to_value(&full).unwrap().get("requested_depth").is_none());
+        let one_sided = OrderBook { bids: levels(10), asks: vec![], ..Default::default() };
+        assert!(one_sided.is_short(10) && !one_sided.is_empty());
+        assert!(OrderBook::default().is_empty());
+    }
+
+    #[test]
+    fn test_same_levels_ignores_exchange_ts() {
+        let book = OrderBook {
+            bids: vec![(2.0, 1.0)],
+            asks: vec![(3.0, 1.0)],
+            exchange_ts: Some(1),
+            sequence: None,
+            requested_depth: None,
+        };
+        let later = OrderBook { exchange_ts: Some(2), ..book.clone() };
+        assert!(book.same_levels(&later));
+        let changed = OrderBook { asks: vec![(3.0, 1.5)], ..book.clone() };
+        assert!(!book.same_levels(&changed));
+    }
+
+    #[test]
+    fn test_truncated_keeps_best_levels() {
+        let book = OrderBook {
+            bids: vec![(3.0, 1.0), (2.0, 1.0), (1.0, 1.0)],
+            asks: vec![(4.0, 1.0)],
+            exchange_ts: Some(7),
+            sequence: None,
+            requested_depth: None,
+        };
+        let top = book.truncated(2);
+        assert_eq!(top.bids, vec![(3.0, 1.0), (2.0, 1.0)]);
+        assert_eq!(top.asks, vec![(4.0, 1.0)]);
+        assert_eq!(top.exchange_ts, Some(7));
+    }
+
+    #[test]
+    fn test_diff_and_apply_round_trip() {
+        let previous = OrderBook {
+            bids: vec![(3.0, 1.0), (2.0, 1.0), (1.0, 1.0)],
+            asks: vec![(4.0, 1.0), (5.0, 1.0)],
+            exchange_ts: Some(1),
+            sequence: None,
+            requested_depth: None,
+        };
+        let next = OrderBook {
+            bids: vec![(3.5, 2.0), (3.0, 1.0), (2.0, 3.0)],
+            asks: vec![(5.0, 1.0)],
+            exchange_ts: Some(2),
+            sequence: None,
+            requested_depth: None,
+        };
+
+        let delta = previous.diff(&next);
+        assert_eq!(delta.bids, vec![(3.5, 2.0), (2.0, 3.0), (1.0, 0.0)]);
+        assert_eq!(delta.asks, vec![(4.0, 0.0)]);
+
+        let mut rebuilt = previous.clone();
+        rebuilt.apply(&delta);
+        assert_eq!(rebuilt, next);
+    }
+
+    #[test]
+    fn test_short_book_padding() {
+        let short = OrderBook {
+            bids: vec![(3.0, 1.0), (2.0, 0.0)],
+            asks: vec![(4.0, 1.0), (5.0, 2.0), (6.0, 3.0)],
+            exchange_ts: None,
+            sequence: None,
+            requested_depth: None,
+        };
+
+        let cleaned = short.without_padding(2);
+        assert_eq!(cleaned.bids, vec![(3.0, 1.0)]);
+        assert_eq!(cleaned.asks, vec![(4.0, 1.0), (5.0, 2.0)]);
+
+        assert_eq!(cleaned.to_padded_json(3), json!({
+            "bids": [[3.0, 1.0], null, null],
+            "asks": [[4.0, 1.0], [5.0, 2.0], null],
+            "exchange_ts": null
+        }));
+    }
+
+    #[test]
+    fn test_sequence_serialized_only_if_known() {
+        let book = OrderBook {
+            bids: vec![(1.0, 2.0)],
+            asks: vec![],
+            exchange_ts: None,
+            sequence: None,
+            requested_depth: None,
+        };
+        assert_eq!(serde_json::to_value(&book).unwrap(), json!({"bids": [[1.0, 2.0]], "asks": [], "exchange_ts": null}));
+
+        let sequenced = OrderBook { sequence: Some(42), ..book };
+        assert_eq!(serde_json::to_value(&sequenced).unwrap()["sequence"], json!(42));
+        assert_eq!(sequenced.to_padded_json(1)["sequence"], json!(42));
+        assert_eq!(serde_json::from_value::<OrderBook>(json!({"bids": [], "asks": [], "exchange_ts": null})).unwrap().sequence, None);
+    }
+
+    #[test]
+    fn test_depth_curve() {
+        let book = OrderBook {
+            bids: vec![(99.95, 1.0), (99.6, 2.0), (98.0, 4.0)],
+            asks: vec![(100.05, 1.5), (100.8, 2.5)],
+            exchange_ts: None,
+            sequence: None,
+            requested_depth: None,
+        };
+        let curve = book.depth_curve(&[0.1, 0.5, 1.0]).unwrap();
+        assert_eq!(curve.mid, 100.0);
+        assert_eq!(curve.bids, vec![1.0, 3.0, 3.0]);
+        assert_eq!(curve.asks, vec![1.5, 1.5, 4.0]);
+        assert_eq!(OrderBook::default().depth_curve(&[0.1]), None);
+    }
+
+    #[test]
+    fn test_mid_price() {
+        let book = OrderBook {
+            bids: vec![(99.0, 1.0)],
+            asks: vec![(101.0, 1.0)],
+            exchange_ts: None,
+            sequence: None,
+            requested_depth: None,
+        };
+        assert_eq!(book.mid_price(), Some(100.0));
+        assert_eq!(OrderBook::default().mid_price(), None);
+    }
+
+    #[test]
+    fn test_spread_bps() {
+        let book = OrderBook {
+            bids: vec![(99.95, 1.0)],
+            asks: vec![(100.05, 1.0)],
+            exchange_ts: None,
+            sequence: None,
+            requested_depth: None,
+        };
+        assert!((book.spread_bps().unwrap() - 10.0).abs() < 1e-9);
+
+        let crossed = OrderBook {
+            bids: vec![(100.05, 1.0)],
+            asks: vec![(99.95, 1.0)],
+            exchange_ts: None,
+            sequence: None,
+            requested_depth: None,
+        };
+        assert!(crossed.spread_bps().unwrap() < 0.0);
+        assert_eq!(OrderBook::default().spread_bps(), None);
+    }
+
+    #[test]
+    fn test_snapshot_serializes_flat() {
+        let snapshot = Snapshot {
+            time: 1,
+            exchange: "BINANCE".to_string(),
+            symbol: "BTC_USDT".to_string(),
+            book: OrderBook {
+                bids: vec![(1.0, 2.0)],
+                asks: vec![],
+                exchange_ts: None,
+                sequence: None,
+                requested_depth: None,
+            },
+        };
+        let value = serde_json::to_value(&snapshot).unwrap();
+        assert_eq!(value, json!({
+            "time": 1, "exchange": "BINANCE", "symbol": "BTC_USDT",
+            "bids": [[1.0, 2.0]], "asks": [], "exchange_ts": null
+        }));
+    }
+}
@@ -1,19 +1,85 @@
-use std::collections::HashMap;
-use std::fs::{create_dir_all, OpenOptions};
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread;
-use tokio::time::{sleep, Duration};
-use chrono::prelude::Utc;
-use std::io::Write;
-use std::fmt::Write as FmtWrite;
+use std::time::{Duration, Instant};
+use tokio::runtime::{Handle, Runtime};
+use tokio::sync::broadcast;
+use tokio::task::{AbortHandle, JoinHandle};
 use crate::cex_api::CexApi;
+use crate::check::{self, CheckResult};
+use crate::clock::{Clock, SystemClock};
+use crate::config::{Config, ConfigError, ExchangeConfig, TickerConfig};
+use crate::events::{Event, Events};
+use crate::exchanges;
+use crate::metrics::Metrics;
+use crate::order_book::{OrderBook, Snapshot};
+use crate::recorder::LatestBooks;
+use crate::schedule::Schedule;
+use crate::sink::Sink;
 use crate::ticker::Ticker;
+use crate::rate_limit::RateLimiters;
+use crate::worker::{self, Cooldowns, LastWrites, Pauses, WorkerContext};
+use tracing::{error, info, warn};
+
+/// Identifies a worker by its exchange name and its key on that exchange: a symbol or a batch of symbols.
+///
+/// Keying by exchange keeps the same pair collected from two exchanges apart.
+type WorkerKey = (&'static str, String);
 
 /// A collector for order book data from cryptocurrency exchanges (CEXs).
+///
+/// Every worker is a task on one Tokio runtime owned by the collector.
 pub struct OrderBookCollector {
-    handles: HashMap<String, thread::JoinHandle<()>>,
-    alive: HashMap<String, Arc<AtomicBool>>,
+    handles: HashMap<WorkerKey, JoinHandle<()>>,
+    alive: HashMap<WorkerKey, Arc<AtomicBool>>,
+    config: Arc<Config>,
+    snapshots: broadcast::Sender<Arc<Snapshot>>,
+    events: Events,
+    latest_books: LatestBooks,
+    sinks: Vec<Arc<dyn Sink>>,
+    cooldowns: Cooldowns,
+    rate_limiters: RateLimiters,
+    /// The ticker configurations and API of each running worker, used to restart it.
+    workers: HashMap<WorkerKey, (Vec<TickerConfig>, Arc<dyn CexApi>)>,
+    /// The valid symbols of each exchange, loaded before its first workers are started.
+    symbols: HashMap<&'static str, Option<HashSet<String>>>,
+    /// The runtime the workers run on, built from the `runtime` settings when the first worker starts.
+    runtime: Option<Runtime>,
+    /// The tasks of workers asked to stop that `await_stopped` has not waited for yet.
+    stopping: Vec<(WorkerKey, JoinHandle<()>)>,
+    clock: Arc<dyn Clock>,
+    /// The pause flag of each symbol, kept across worker restarts.
+    pauses: Pauses,
+    metrics: Metrics,
+    /// The time of the last record written for each collected symbol.
+    last_writes: LastWrites,
+    /// The time each running worker was started at, in unix milliseconds.
+    started_at: HashMap<WorkerKey, i64>,
+    /// The configuration each running worker was started with, compared on reload.
+    started_with: HashMap<WorkerKey, Arc<Config>>,
+    /// The symbols reported stalled by `check_stalled` that have not written since.
+    stalled: HashSet<(String, String)>,
+}
+
+/// Aborts a task when dropped, so that aborting a supervisor also aborts the worker it awaits.
+struct AbortOnDrop(AbortHandle);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// The number of snapshots buffered for each subscriber before it is considered lagging.
+const SNAPSHOT_CHANNEL_CAPACITY: usize = 1024;
+
+impl Default for OrderBookCollector {
+    /// Creates a new `OrderBookCollector`, see `OrderBookCollector::new`.
+    fn default() -> Self {
+        OrderBookCollector::new()
+    }
 }
 
 impl OrderBookCollector {
@@ -22,172 +88,1431 @@ impl OrderBookCollector {
         OrderBookCollector {
             handles: HashMap::new(),
             alive: HashMap::new(),
+            config: Arc::new(Config::default()),
+            snapshots: broadcast::channel(SNAPSHOT_CHANNEL_CAPACITY).0,
+            events: Events::default(),
+            latest_books: LatestBooks::default(),
+            sinks: Vec::new(),
+            cooldowns: Cooldowns::default(),
+            rate_limiters: RateLimiters::default(),
+            workers: HashMap::new(),
+            symbols: HashMap::new(),
+            runtime: None,
+            stopping: Vec::new(),
+            clock: Arc::new(SystemClock),
+            pauses: Pauses::default(),
+            metrics: Metrics::default(),
+            last_writes: LastWrites::default(),
+            started_at: HashMap::new(),
+            started_with: HashMap::new(),
+            stalled: HashSet::new(),
         }
     }
 
-    /// Starts collecting order book data for a given symbol using a specified API.
+    /// Returns a handle to the workers' runtime, building the runtime on first use.
+    fn runtime(&mut self) -> Handle {
+        let settings = &self.config.runtime;
+        self.runtime
+            .get_or_insert_with(|| settings.build().expect("Unable to create Tokio runtime"))
+            .handle()
+            .clone()
+    }
+
+    /// Sets the handle workers report significant events to.
+    ///
+    /// # Arguments
+    ///
+    /// * `events` - The `Events` handle, e.g. one feeding a webhook.
+    pub fn set_events(&mut self, events: Events) {
+        self.events = events;
+    }
+
+    /// Sets the clock that workers started after this call schedule fetches and stamp records with.
+    ///
+    /// # Arguments
+    ///
+    /// * `clock` - The clock, e.g. a `MockClock` in tests. The system clock is used by default.
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    /// Sets the sinks that workers started after this call publish their records to.
+    ///
+    /// # Arguments
+    ///
+    /// * `sinks` - The sinks, e.g. created by `sink::from_config`.
+    pub fn set_sinks(&mut self, sinks: Vec<Arc<dyn Sink>>) {
+        self.sinks = sinks;
+    }
+
+    /// Returns the metrics counted by the workers, e.g. to serve them with `metrics::spawn`.
+    pub fn metrics(&self) -> Metrics {
+        self.metrics.clone()
+    }
+
+    /// Returns the channel on which workers publish normalized snapshots.
+    ///
+    /// Workers only parse responses into snapshots while the channel has at least one subscriber.
+    pub fn snapshot_sender(&self) -> broadcast::Sender<Arc<Snapshot>> {
+        self.snapshots.clone()
+    }
+
+    /// Returns the latest normalized order book collected for a symbol on an exchange.
+    ///
+    /// Books are only tracked while `track_latest_books` is enabled in the configuration.
+    ///
+    /// # Arguments
+    ///
+    /// * `exchange` - The name of the exchange (e.g., "BINANCE").
+    /// * `symbol` - The ticker symbol in "BASE_QUOTE" form.
+    ///
+    /// # Returns
+    ///
+    /// The latest `OrderBook`, or `None` if the symbol is not collected or no book was parsed yet.
+    pub fn latest_book(&self, exchange: &str, symbol: &str) -> Option<OrderBook> {
+        self.latest_books.read().unwrap().get(&(exchange.to_string(), symbol.to_string())).cloned()
+    }
+
+    /// Returns the time of the last order book record written for a symbol on an exchange.
+    ///
+    /// Every newly fetched book counts, including one only published to sinks, while stale fills,
+    /// `"unchanged"` markers, timeouts and records whose file write failed do not.
     ///
     /// # Arguments
     ///
-    /// * `symbol` - A string slice that holds the symbol to collect data for.
+    /// * `exchange` - The name of the exchange (e.g., "BINANCE").
+    /// * `symbol` - The ticker symbol in "BASE_QUOTE" form.
+    ///
+    /// # Returns
+    ///
+    /// The unix time in milliseconds of the collector's clock, or `None` if nothing was written yet.
+    pub fn last_write(&self, exchange: &str, symbol: &str) -> Option<i64> {
+        self.last_writes.get(exchange, symbol)
+    }
+
+    /// Returns the configuration used by workers started from now on.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Returns the symbols being collected, as sorted `(exchange, symbol)` pairs.
+    ///
+    /// Each ticker of a batched worker is listed on its own. Workers asked to stop are not listed,
+    /// so after a reload this is the configured set the collector converged to.
+    pub fn active_symbols(&self) -> Vec<(String, String)> {
+        let mut symbols: Vec<(String, String)> = self.workers.iter()
+            .filter(|(key, _)| self.handles.contains_key(*key))
+            .flat_map(|((exchange, _), (group, _))| {
+                group.iter().map(|ticker| (exchange.to_string(), ticker.symbol.clone()))
+            })
+            .collect();
+        symbols.sort();
+        symbols
+    }
+
+    /// Returns whether `symbol` is being collected from `exchange`, on its own or in a batch.
+    ///
+    /// # Arguments
+    ///
+    /// * `exchange` - The name of the exchange (e.g., "BINANCE").
+    /// * `symbol` - The ticker symbol in "BASE_QUOTE" form.
+    pub fn is_running(&self, exchange: &str, symbol: &str) -> bool {
+        self.workers.iter().any(|(key, (group, _))| {
+            key.0 == exchange && self.handles.contains_key(key) && group.iter().any(|ticker| ticker.symbol == symbol)
+        })
+    }
+
+    /// Pauses the collection of `symbol` on every exchange, without stopping its workers.
+    ///
+    /// The workers keep running and aligned to their schedule but skip their fetches, and streams stay
+    /// connected but record nothing, so `resume` continues on the next regular fetch. Unlike `request_stop`,
+    /// the pause is kept when the worker is restarted or the configuration is reloaded.
+    ///
+    /// # Arguments
+    ///
+    /// * `symbol` - The ticker symbol in "BASE_QUOTE" form.
+    pub fn pause(&mut self, symbol: &str) {
+        info!("Pause {}", symbol);
+        self.pauses.set(symbol, true);
+    }
+
+    /// Resumes the collection of `symbol` paused by `pause`.
+    ///
+    /// # Arguments
+    ///
+    /// * `symbol` - The ticker symbol in "BASE_QUOTE" form.
+    pub fn resume(&mut self, symbol: &str) {
+        info!("Resume {}", symbol);
+        self.pauses.set(symbol, false);
+    }
+
+    /// Pauses every symbol being collected, see `pause`.
+    pub fn pause_all(&mut self) {
+        let symbols: HashSet<String> = self.active_symbols().into_iter().map(|(_, symbol)| symbol).collect();
+        for symbol in symbols {
+            self.pause(&symbol);
+        }
+    }
+
+    /// Resumes every paused symbol.
+    pub fn resume_all(&mut self) {
+        for symbol in self.pauses.paused() {
+            self.resume(&symbol);
+        }
+    }
+
+    /// Returns whether `symbol` is paused.
+    ///
+    /// # Arguments
+    ///
+    /// * `symbol` - The ticker symbol in "BASE_QUOTE" form.
+    pub fn is_paused(&self, symbol: &str) -> bool {
+        self.pauses.is_paused(symbol)
+    }
+
+    /// Sets the configuration used by workers started after this call.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - An `Arc` pointing to the loaded `Config`.
+    pub fn set_config(&mut self, config: Arc<Config>) {
+        self.config = config;
+    }
+
+    /// Switches collection to `config`, e.g. after the configuration file changed, only if all of it
+    /// can be applied.
+    ///
+    /// The configuration is validated and the API of every exchange is built before any worker is
+    /// touched, so a configuration that fails, e.g. on an exchange not compiled into this build,
+    /// leaves the running workers and the current configuration as they are.
+    ///
+    /// # Errors
+    ///
+    /// Returns the `ConfigError` that kept the configuration from being applied.
+    pub fn reload(&mut self, config: Config) -> Result<(), ConfigError> {
+        config.validate()?;
+        let apis = exchanges::build_apis(&config)?;
+        self.apply(Arc::new(config), &apis);
+        Ok(())
+    }
+
+    /// Collects the tickers of every exchange in `apis` under `config`, stopping the workers of
+    /// exchanges and tickers no longer configured, restarting those whose settings changed and
+    /// starting those newly configured.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The configuration to collect under.
+    /// * `apis` - Each configured exchange with its API, e.g. built by `reload`.
+    pub fn apply(&mut self, config: Arc<Config>, apis: &[(ExchangeConfig, Arc<dyn CexApi>)]) {
+        self.set_config(config);
+        let names: Vec<String> = apis.iter().map(|(exchange, _)| exchange.cex.clone()).collect();
+        self.retain_exchanges(&names);
+        for (exchange, api) in apis {
+            info!("CEX: {}", exchange.cex);
+            self.start_multiple(&exchange.tickers, api.clone());
+        }
+    }
+
+    /// Starts collecting order book data for a given ticker using a specified API.
+    ///
+    /// A worker already running for the ticker on the exchange is stopped first, so it is replaced rather than orphaned.
+    ///
+    /// # Arguments
+    ///
+    /// * `ticker_config` - The configuration of the ticker to collect data for.
     /// * `api` - An `Arc` pointing to an object that implements the `CexApi` trait.
-    pub fn start<T>(&mut self, symbol: &str, api: Arc<T>)
-        where
-            T: 'static + Send + Sync + CexApi,
-    {
+    pub fn start(&mut self, ticker_config: &TickerConfig, api: Arc<dyn CexApi>) {
+        let symbol = ticker_config.symbol.as_str();
         if let Some(ticker) = Ticker::new(symbol) {
-            println!("Start {}", symbol);
-            let alive_flag = self.alive.entry(symbol.to_string())
-                .or_insert_with(|| Arc::new(AtomicBool::new(true)));
-            alive_flag.store(true, Ordering::SeqCst);
-            let alive_clone = alive_flag.clone();
-
-            let api_clone = api.clone(); // Clone the API object
-
-            let handle = thread::spawn(move || {
-                let runtime = tokio::runtime::Runtime::new().unwrap(); // Create a new Tokio runtime
-                runtime.block_on(async move {
-                    OrderBookCollector::worker(ticker, api_clone, alive_clone).await;
-                });
+            let schedules = ticker_config.schedule(api.get_order_book_interval())
+                .and_then(|schedule| Ok((schedule, ticker_config.mark_interval()?)));
+            let (schedule, mark_interval) = match schedules {
+                Ok(schedules) => schedules,
+                Err(e) => {
+                    warn!("Invalid schedule for {}: {}", symbol, e);
+                    return;
+                }
+            };
+            let key: WorkerKey = (api.name(), symbol.to_string());
+            self.stop_running(&key);
+            info!("Start {}", symbol);
+            self.workers.insert(key.clone(), (vec![ticker_config.clone()], api.clone()));
+            let mut ctx = self.worker_context(&key, api);
+            if let Some(depth) = ticker_config.main_depth() {
+                ctx.config = Arc::new(Config { depth, ..(*ctx.config).clone() });
+            }
+            let extra_depths = ticker_config.extra_depths().to_vec();
+            let liquidations = ticker_config.liquidations;
+            let diff_book = ticker_config.diff_book;
+            let stream = ticker_config.stream;
+
+            let runtime = self.runtime();
+            let handle = OrderBookCollector::spawn_supervised(&runtime, symbol, ctx.clone(), move || {
+                let (ticker, schedule, ctx) = (ticker.clone(), schedule.clone(), ctx.clone());
+                let extra_depths = extra_depths.clone();
+                async move {
+                    let mark_worker = async {
+                        if let Some(interval) = mark_interval {
+                            worker::run_mark_price(ticker.clone(), interval, ctx.clone()).await;
+                        }
+                    };
+                    let liquidation_worker = async {
+                        if liquidations {
+                            worker::run_liquidations(ticker.clone(), ctx.clone()).await;
+                        }
+                    };
+                    let book_worker = async {
+                        if diff_book {
+                            worker::run_diff_book(ticker.clone(), schedule, ctx.clone()).await;
+                        } else if stream {
+                            worker::run_stream(ticker.clone(), schedule, ctx.clone()).await;
+                        } else if !extra_depths.is_empty() {
+                            worker::run_depths(ticker.clone(), extra_depths, schedule, ctx.clone()).await;
+                        } else {
+                            worker::run(ticker.clone(), schedule, ctx.clone()).await;
+                        }
+                    };
+                    tokio::join!(book_worker, mark_worker, liquidation_worker);
+                }
             });
 
-            self.handles.insert(symbol.to_string(), handle);
+            self.handles.insert(key, handle);
+            self.update_active_symbols();
         } else {
-            eprintln!("Invalid symbol format: {}", symbol);
+            warn!("Invalid symbol format: {}", symbol);
         }
     }
 
-    /// Stops collecting order book data for a given symbol.
+    /// Asks the worker of a given symbol to stop, without waiting for it to exit.
+    ///
+    /// The worker is no longer tracked as running; `await_stopped` waits for it to exit.
+    /// Its key must not be started again before then, as that would keep the old worker alive.
     ///
     /// # Arguments
     ///
-    /// * `symbol` - A string slice that holds the symbol to stop collecting data for.
-    pub fn stop(&mut self, symbol: &str) {
-        if let Some(alive) = self.alive.get(symbol) {
-            println!("Stop {}", symbol);
+    /// * `key` - The exchange and the symbol, or batch key, to stop collecting data for.
+    pub fn request_stop(&mut self, key: &WorkerKey) {
+        if let Some(alive) = self.alive.get(key) {
+            info!("Stop {} on {}", key.1, key.0);
             alive.store(false, Ordering::SeqCst);
-            if let Some(handle) = self.handles.remove(symbol) {
-                handle.join().expect("Could not join spawned thread");
+            self.workers.remove(key);
+            self.started_at.remove(key);
+            self.started_with.remove(key);
+            if let Some(handle) = self.handles.remove(key) {
+                self.stopping.push((key.clone(), handle));
             }
+            self.update_active_symbols();
         }
     }
 
-    /// Starts collecting order book data for multiple symbols.
+    /// Waits for every worker asked to stop by `request_stop` to exit.
+    ///
+    /// The workers stop concurrently and share one configured `stop_grace_period_ms`. A worker
+    /// that is still running afterwards, e.g. because of a hung request, is aborted with a warning
+    /// so that stopping or reloading never blocks indefinitely.
+    pub fn await_stopped(&mut self) {
+        let deadline = Instant::now() + self.grace_period();
+        for (key, handle) in self.stopping.drain(..) {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            OrderBookCollector::stop_with_grace(&key.1, handle, remaining);
+        }
+    }
+
+    /// Stops the worker tracked under `key`, if one is running, and waits for it to exit.
+    ///
+    /// Called before a worker is started under `key`, as starting resets its alive flag.
+    fn stop_running(&mut self, key: &WorkerKey) {
+        if self.handles.contains_key(key) {
+            info!("{} on {} is already running, replacing its worker", key.1, key.0);
+            self.request_stop(key);
+            self.await_stopped();
+        }
+    }
+
+    /// Stops the workers of every exchange that is not in `exchanges`, e.g. after it was removed from the config.
     ///
     /// # Arguments
     ///
-    /// * `symbols` - A slice of strings that holds the symbols to collect data for.
-    /// * `api` - An `Arc` pointing to an object that implements the `CexApi` trait.
-    pub fn start_multiple<T>(&mut self, symbols: &[String], api: Arc<T>)
-        where
-            T: 'static + Send + Sync + CexApi,
-    {
-        let symbol_set: std::collections::HashSet<_> = symbols.iter().cloned().collect();
+    /// * `exchanges` - The names of the exchanges to keep collecting from.
+    pub fn retain_exchanges(&mut self, exchanges: &[String]) {
+        let removed: Vec<WorkerKey> = self.handles.keys()
+            .filter(|(exchange, _)| !exchanges.iter().any(|name| name == exchange))
+            .cloned()
+            .collect();
+        for key in removed {
+            self.request_stop(&key);
+        }
+        self.await_stopped();
+    }
 
-        for existing_symbol in self.handles.keys().cloned().collect::<Vec<_>>() {
-            if !symbol_set.contains(&existing_symbol) {
-                self.stop(&existing_symbol);
-            }
+    /// Stops and restarts every running worker with the current configuration.
+    ///
+    /// Restarted workers begin with fresh state, e.g. after a transient exchange outage,
+    /// and realign to their schedule.
+    pub fn restart_all(&mut self) {
+        info!("Restart all workers");
+        let keys: Vec<WorkerKey> = self.workers.keys().cloned().collect();
+        self.restart(&keys);
+    }
+
+    /// Stops and restarts the workers tracked under `keys` with the current configuration.
+    fn restart(&mut self, keys: &[WorkerKey]) {
+        let workers: Vec<_> = keys.iter()
+            .filter_map(|key| self.workers.get(key).map(|(group, api)| (key.clone(), group.clone(), api.clone())))
+            .collect();
+        for (key, _, _) in &workers {
+            self.request_stop(key);
+        }
+        self.await_stopped();
+        for (key, group, api) in workers {
+            self.start_group(&key.1, &group, api);
         }
+    }
 
-        for symbol in symbols {
-            if !self.handles.contains_key(symbol) {
-                self.start(symbol, api.clone());
+    /// Reports the symbols whose running worker has not written a record for `stall_intervals` of
+    /// their fetch intervals, e.g. because every fetch fails, and restarts their workers if
+    /// `restart_stalled` is enabled. Call it periodically to watch the workers.
+    ///
+    /// A symbol that has not written since its worker started is measured from the start, and
+    /// warmup snapshots extend the allowance. Paused symbols are not checked. Each stall is logged
+    /// as an error, counted in the `worker_stalls_total` metric and reported as an
+    /// `Event::WorkerStalled` once, until the symbol writes again or its worker is restarted.
+    ///
+    /// # Returns
+    ///
+    /// The `(exchange, symbol)` pairs newly reported stalled.
+    pub fn check_stalled(&mut self) -> Vec<(String, String)> {
+        if self.config.stall_intervals == 0 {
+            return Vec::new();
+        }
+        let now = self.clock.now_millis();
+        let allowed_intervals = (self.config.stall_intervals + self.config.warmup_snapshots) as u64;
+        let mut checked = HashSet::new();
+        let mut newly_stalled = Vec::new();
+        let mut restarts = Vec::new();
+        for (key, (group, api)) in &self.workers {
+            if !self.handles.contains_key(key) {
+                continue;
+            }
+            let started_at = self.started_at.get(key).copied().unwrap_or(now);
+            for ticker in group {
+                let pair = (key.0.to_string(), ticker.symbol.clone());
+                checked.insert(pair.clone());
+                let Some(interval_ms) = self.fetch_interval_ms(ticker, api.as_ref(), now) else {
+                    continue;
+                };
+                if self.pauses.is_paused(&ticker.symbol) {
+                    continue;
+                }
+                let since = self.last_writes.get(key.0, &ticker.symbol).map_or(started_at, |last| last.max(started_at));
+                let silent_ms = (now - since).max(0) as u64;
+                if silent_ms <= interval_ms * allowed_intervals {
+                    if self.stalled.remove(&pair) {
+                        info!("{} on {} is writing again", ticker.symbol, key.0);
+                    }
+                    continue;
+                }
+                if self.stalled.insert(pair.clone()) {
+                    error!(
+                        "{} on {} has not written a record for {} s, its worker may be stalled",
+                        ticker.symbol, key.0, silent_ms / 1000
+                    );
+                    self.metrics.worker_stalled(key.0, &ticker.symbol);
+                    self.events.emit(Event::WorkerStalled {
+                        exchange: key.0.to_string(),
+                        symbol: ticker.symbol.clone(),
+                        silent_secs: silent_ms / 1000,
+                    });
+                    newly_stalled.push(pair);
+                    if self.config.restart_stalled && !restarts.contains(key) {
+                        restarts.push(key.clone());
+                    }
+                }
             }
         }
+        // Symbols no longer collected are forgotten, so they are reported again if they return
+        self.stalled.retain(|pair| checked.contains(pair));
+        if !restarts.is_empty() {
+            for key in &restarts {
+                warn!("Restarting the stalled worker for {} on {}", key.1, key.0);
+                if let Some((group, _)) = self.workers.get(key) {
+                    for ticker in group {
+                        self.stalled.remove(&(key.0.to_string(), ticker.symbol.clone()));
+                    }
+                }
+            }
+            self.restart(&restarts);
+        }
+        newly_stalled
     }
 
-    /// Stops all collecting threads.
-    #[allow(dead_code)]
-    pub fn stop_all(&mut self) {
-        for alive in self.alive.values() {
-            alive.store(false, Ordering::SeqCst);
+    /// Returns the time in milliseconds between two fetches of a ticker, at the slowest pace of
+    /// its adaptive interval, or `None` if its schedule has no upcoming fetches.
+    fn fetch_interval_ms(&self, ticker: &TickerConfig, api: &dyn CexApi, now: i64) -> Option<u64> {
+        let schedule = ticker.schedule(api.get_order_book_interval()).ok()?;
+        let interval_ms = schedule.period_millis(now)?;
+        match (&schedule, &self.config.adaptive_interval) {
+            (Schedule::Interval(_), Some(adaptive)) => Some(interval_ms.max(adaptive.max_interval_ms)),
+            _ => Some(interval_ms),
         }
+    }
 
-        for handle in self.handles.drain().map(|(_, h)| h) {
-            handle.join().expect("Could not join spawned thread");
+    /// Starts the worker of a planned group, batching the request if the group has several tickers.
+    fn start_group(&mut self, key: &str, group: &[TickerConfig], api: Arc<dyn CexApi>) {
+        if group.len() == 1 {
+            self.start(&group[0], api);
+        } else {
+            self.start_batch(key, group, api);
         }
     }
 
-    /// The worker function for collecting order book data.
+    /// Returns the configured time stopping workers are given to exit.
+    fn grace_period(&self) -> Duration {
+        Duration::from_millis(self.config.stop_grace_period_ms)
+    }
+
+    /// Waits for a stopping worker's task to finish, aborting it if it does not exit within `grace_period`.
+    ///
+    /// Must not be called from a worker task, as it blocks the calling thread while waiting.
     ///
     /// # Arguments
     ///
-    /// * `ticker` - A `Ticker` object representing the asset pair.
-    /// * `api` - An `Arc` pointing to an object that implements the `CexApi` trait.
-    /// * `alive` - An `Arc` pointing to an `AtomicBool` that indicates whether the thread should continue running.
-    pub async fn worker(ticker: Ticker, api: Arc<dyn CexApi>, alive: Arc<AtomicBool>) {
-        let interval_in_milliseconds = api.get_order_book_interval() * 1000;
-        let remainder = Utc::now().timestamp_millis() as u64 % interval_in_milliseconds;
-        if remainder > 0 {
-            sleep(Duration::from_millis(interval_in_milliseconds - remainder)).await;
+    /// * `key` - The key of the worker, used for logging.
+    /// * `handle` - The handle of the worker's task.
+    /// * `grace_period` - The maximum time to wait for the worker to exit.
+    ///
+    /// # Returns
+    ///
+    /// `true` if the worker exited, `false` if it was aborted.
+    fn stop_with_grace(key: &str, handle: JoinHandle<()>, grace_period: Duration) -> bool {
+        let deadline = Instant::now() + grace_period;
+        while !handle.is_finished() {
+            if Instant::now() >= deadline {
+                warn!(
+                    "Worker for {} did not exit within {} ms, aborting it",
+                    key, grace_period.as_millis()
+                );
+                handle.abort();
+                return false;
+            }
+            thread::sleep(Duration::from_millis(10));
         }
+        true
+    }
 
-        let dir = format!("data/{}/{}", api.name(), ticker.to_string());
-        OrderBookCollector::create_directory(dir.as_str());
+    /// Starts collecting order book data for multiple tickers.
+    ///
+    /// When batching is enabled in the configuration and the exchange supports batched requests,
+    /// tickers using the exchange's default schedule are grouped so that one request fetches
+    /// the order books of a whole group. With the exchange's `batch_mode` enabled, all of those
+    /// tickers instead share one worker fetching them concurrently on each tick.
+    /// Otherwise every ticker gets its own worker.
+    ///
+    /// If the exchange provides a symbol list, tickers it does not trade are skipped and symbols
+    /// given without a separator are resolved against it first.
+    ///
+    /// Workers already running keep running, unless their tickers' settings or the configuration
+    /// they were started with changed, e.g. a reloaded `interval_secs` or `depth`, in which case
+    /// they are restarted under the current configuration.
+    ///
+    /// # Arguments
+    ///
+    /// * `tickers` - A slice of ticker configurations to collect data for.
+    /// * `api` - An `Arc` pointing to an object that implements the `CexApi` trait.
+    pub fn start_multiple(&mut self, tickers: &[TickerConfig], api: Arc<dyn CexApi>) {
+        let resolved;
+        let tickers = match self.valid_symbols(api.as_ref()) {
+            Some(valid_symbols) => {
+                resolved = OrderBookCollector::resolve_tickers(tickers, valid_symbols);
+                resolved.as_slice()
+            }
+            None => tickers,
+        };
 
-        let mut file_path = dir.clone();
-        let mut last_saved_hour_timestamp = 0;
+        let mut batch_size = 1;
+        if self.config.batch_mode(api.name()) {
+            batch_size = usize::MAX;
+        } else if self.config.batch {
+            batch_size = api.max_batch_size();
+            if batch_size <= 1 {
+                info!("{} does not support batched requests, using per-symbol requests", api.name());
+            }
+        }
 
-        while alive.load(Ordering::SeqCst) {
-            let response_result = api.get_order_book(&ticker, 10).await;
+        let plan = OrderBookCollector::plan_workers(tickers, batch_size);
+        let key_set: HashSet<WorkerKey> = plan.iter()
+            .map(|(key, _)| (api.name(), key.clone()))
+            .filter(|key| !self.settings_changed(key, &plan))
+            .collect();
 
-            match response_result {
-                Ok(response_text) => {
-                    let timestamp = Utc::now().timestamp();
-                    let response_text = response_text.trim_end_matches('\n');
+        // Only this exchange's workers are replaced; other exchanges keep running.
+        // All removed and changed workers are stopped together, so a reload waits for them at most once.
+        for existing_key in self.handles.keys().cloned().collect::<Vec<_>>() {
+            if existing_key.0 == api.name() && !key_set.contains(&existing_key) {
+                self.request_stop(&existing_key);
+            }
+        }
+        self.await_stopped();
 
-                    let json_data = format!(
-                        r#"{{"time": {}, "response": {}}}"#,
-                        timestamp, response_text
-                    );
+        for (key, group) in plan {
+            if !self.handles.contains_key(&(api.name(), key.clone())) {
+                self.start_group(&key, &group, api.clone());
+            }
+        }
+    }
 
-                    let hour_timestamp = timestamp / 3600i64 * 3600;
-                    if hour_timestamp > last_saved_hour_timestamp {
-                        file_path.truncate(dir.len());
-                        write!(file_path, "/{}.json", hour_timestamp).unwrap();
-                        println!("{}", file_path);
+    /// Returns whether the worker tracked under `key` runs with other tickers' settings than its
+    /// group in `plan`, or under a configuration that differs from the current one.
+    fn settings_changed(&self, key: &WorkerKey, plan: &[(String, Vec<TickerConfig>)]) -> bool {
+        let (Some((running, _)), Some(started_with)) = (self.workers.get(key), self.started_with.get(key)) else {
+            return false;
+        };
+        let planned = plan.iter().find(|(planned_key, _)| *planned_key == key.1).map(|(_, group)| group);
+        planned.is_some_and(|group| group != running) || !started_with.same_worker_settings(&self.config, key.0)
+    }
 
-                        last_saved_hour_timestamp = hour_timestamp;
-                    }
+    /// Starts collecting order book data for a group of tickers fetched with batched requests,
+    /// or with concurrent requests on a shared tick if the exchange's `batch_mode` is enabled.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key under which the group's worker is tracked.
+    /// * `group` - The configurations of the tickers in the group.
+    /// * `api` - An `Arc` pointing to an object that implements the `CexApi` trait.
+    fn start_batch(&mut self, key: &str, group: &[TickerConfig], api: Arc<dyn CexApi>) {
+        let tickers: Vec<Ticker> = group.iter().filter_map(|t| Ticker::new(&t.symbol)).collect();
+        if tickers.len() != group.len() {
+            warn!("Invalid symbol format in batch: {}", key);
+            return;
+        }
 
-                    OrderBookCollector::save_to_file(&file_path, &json_data);
-                }
-                Err(error) => {
-                    eprintln!("Error fetching order book: {:?}", error);
+        let worker_key: WorkerKey = (api.name(), key.to_string());
+        self.stop_running(&worker_key);
+        info!("Start batch {}", key);
+        self.workers.insert(worker_key.clone(), (group.to_vec(), api.clone()));
+        let schedule = Schedule::Interval(api.get_order_book_interval());
+        let aligned = self.config.batch_mode(api.name());
+        let ctx = self.worker_context(&worker_key, api);
+
+        let runtime = self.runtime();
+        let handle = OrderBookCollector::spawn_supervised(&runtime, key, ctx.clone(), move || {
+            let (tickers, schedule, ctx) = (tickers.clone(), schedule.clone(), ctx.clone());
+            async move {
+                if aligned {
+                    worker::run_aligned(tickers, schedule, ctx).await;
+                } else {
+                    worker::run_batch(tickers, schedule, ctx).await;
                 }
             }
-            let remainder = Utc::now().timestamp_millis() as u64 % interval_in_milliseconds;
-            if remainder > 0 {
-                sleep(Duration::from_millis(interval_in_milliseconds - remainder)).await;
+        });
+
+        self.handles.insert(worker_key, handle);
+        self.update_active_symbols();
+    }
+
+    /// Fetches and records one order book of each ticker, then returns, e.g. for `--once`.
+    ///
+    /// Symbols are resolved against the exchange's symbol list like `start_multiple` does, and
+    /// each ticker's `depth` applies. The tickers are fetched concurrently.
+    ///
+    /// # Arguments
+    ///
+    /// * `tickers` - A slice of ticker configurations to fetch.
+    /// * `api` - The exchange API to fetch from.
+    ///
+    /// # Returns
+    ///
+    /// The number of tickers that could not be fetched, including invalid symbols.
+    pub fn collect_once(&mut self, tickers: &[TickerConfig], api: Arc<dyn CexApi>) -> usize {
+        let resolved = match self.valid_symbols(api.as_ref()) {
+            Some(valid_symbols) => OrderBookCollector::resolve_tickers(tickers, valid_symbols),
+            None => tickers.to_vec(),
+        };
+        let mut failed = tickers.len() - resolved.len();
+        let mut fetches = Vec::new();
+        for ticker_config in &resolved {
+            let Some(ticker) = Ticker::new(&ticker_config.symbol) else {
+                warn!("Invalid symbol format: {}", ticker_config.symbol);
+                failed += 1;
+                continue;
+            };
+            let mut ctx = self.worker_context(&(api.name(), ticker_config.symbol.clone()), api.clone());
+            if let Some(depth) = ticker_config.main_depth() {
+                ctx.config = Arc::new(Config { depth, ..(*ctx.config).clone() });
             }
+            fetches.push(worker::run_once(ticker, ctx));
         }
-        println!("Worker for {} is stopped", ticker.base);
+        let fetched = self.runtime().block_on(futures_util::future::join_all(fetches));
+        failed + fetched.into_iter().filter(|fetched| !fetched).count()
     }
 
-    /// Creates a directory if it does not exist.
+    /// Fetches and parses one order book of each ticker without recording it, e.g. for `--check`.
+    ///
+    /// Symbols are resolved against the exchange's symbol list like `start_multiple` does, and a
+    /// ticker the exchange does not list fails. Each ticker's `depth` applies, and the tickers are
+    /// checked concurrently.
     ///
     /// # Arguments
     ///
-    /// * `path` - A string slice that holds the path of the directory to create.
-    fn create_directory(path: &str) {
-        create_dir_all(path).expect(&format!("Cannot create dir {}", path));
-        println!("Directory {} created or already exists", path);
+    /// * `tickers` - A slice of ticker configurations to check.
+    /// * `api` - The exchange API to fetch from.
+    ///
+    /// # Returns
+    ///
+    /// The result of each ticker, in the order of `tickers`.
+    pub fn check(&mut self, tickers: &[TickerConfig], api: Arc<dyn CexApi>) -> Vec<CheckResult> {
+        let valid_symbols = self.valid_symbols(api.as_ref()).cloned();
+        let checks = tickers.iter().map(|ticker_config| {
+            let api = api.clone();
+            let resolved = match &valid_symbols {
+                Some(valid_symbols) => OrderBookCollector::resolve_tickers(std::slice::from_ref(ticker_config), valid_symbols).pop(),
+                None => Some(ticker_config.clone()),
+            };
+            let depth = ticker_config.main_depth().unwrap_or(self.config.depth);
+            async move {
+                match resolved {
+                    Some(resolved) => CheckResult {
+                        symbol: ticker_config.symbol.clone(),
+                        ..check::check_ticker(api.as_ref(), &resolved.symbol, depth).await
+                    },
+                    None => CheckResult::failed(api.name(), &ticker_config.symbol, "Not listed by the exchange"),
+                }
+            }
+        }).collect::<Vec<_>>();
+        self.runtime().block_on(futures_util::future::join_all(checks))
+    }
+
+    /// Returns the exchange's valid symbols, loading them on first use.
+    ///
+    /// A failed load is logged and retried on the next call; `None` disables validation.
+    fn valid_symbols(&mut self, api: &dyn CexApi) -> Option<&HashSet<String>> {
+        if !self.symbols.contains_key(api.name()) {
+            match self.runtime().block_on(api.load_symbols()) {
+                Ok(symbols) => {
+                    if let Some(symbols) = &symbols {
+                        info!("Loaded {} symbols from {}", symbols.len(), api.name());
+                    }
+                    self.symbols.insert(api.name(), symbols);
+                }
+                Err(e) => {
+                    warn!("Failed to load symbols from {}, skipping validation: {:?}", api.name(), e);
+                    return None;
+                }
+            }
+        }
+        self.symbols.get(api.name()).and_then(|symbols| symbols.as_ref())
+    }
+
+    /// Validates tickers against an exchange's symbol list.
+    ///
+    /// A symbol without a `_` separator (e.g. `BTCUSDT`) is resolved to the single listed symbol
+    /// it matches once the separator is removed. Tickers that are not listed, or are ambiguous,
+    /// are logged and dropped, as are tickers resolving to a symbol that is already collected.
+    fn resolve_tickers(tickers: &[TickerConfig], valid_symbols: &HashSet<String>) -> Vec<TickerConfig> {
+        let mut seen = HashSet::new();
+        tickers.iter()
+            .filter_map(|ticker| {
+                if valid_symbols.contains(&ticker.symbol) {
+                    return Some(ticker.clone());
+                }
+                if !ticker.symbol.contains('_') {
+                    let matches: Vec<_> = valid_symbols.iter()
+                        .filter(|valid| valid.replace('_', "") == ticker.symbol)
+                        .collect();
+                    if let [symbol] = matches.as_slice() {
+                        info!("Resolved {} to {}", ticker.symbol, symbol);
+                        return Some(TickerConfig { symbol: symbol.to_string(), ..ticker.clone() });
+                    }
+                    if matches.len() > 1 {
+                        warn!("Ambiguous symbol {}, skipping", ticker.symbol);
+                        return None;
+                    }
+                }
+                warn!("Unknown symbol {}, skipping", ticker.symbol);
+                None
+            })
+            .filter(|ticker| seen.insert(ticker.symbol.clone()))
+            .collect()
     }
 
-    /// Saves data to a file.
+    /// Spawns a worker task that runs `body`, restarting it with a backoff when it panics.
+    ///
+    /// After `panic_restart.max_restarts` restarts the worker is given up: the failure is logged
+    /// and reported as an `Event::WorkerFailed`, and the ticker stays stopped until it is restarted
+    /// or removed from the configuration. Other workers are unaffected.
     ///
     /// # Arguments
     ///
-    /// * `file_path` - A string slice that holds the path of the file to write to.
-    /// * `data` - A string slice containing the data to be written.
-    fn save_to_file(file_path: &String, data: &String) {
-        let mut file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(file_path)
-            .expect("Unable to open file");
+    /// * `runtime` - The runtime the worker runs on.
+    /// * `key` - The key of the worker, used for logging and reporting.
+    /// * `ctx` - The `WorkerContext` of the worker.
+    /// * `body` - Returns the future running the worker to completion; called again after each panic.
+    ///
+    /// # Returns
+    ///
+    /// The handle of the supervising task. Aborting it also aborts the running worker.
+    fn spawn_supervised<F, Fut>(runtime: &Handle, key: &str, ctx: WorkerContext, body: F) -> JoinHandle<()>
+        where
+            F: Fn() -> Fut + Send + 'static,
+            Fut: Future<Output = ()> + Send + 'static,
+    {
+        let key = key.to_string();
+        runtime.spawn(async move {
+            let policy = ctx.config.panic_restart.clone();
+            let mut restarts = 0;
+            let mut backoff_ms = policy.backoff_ms;
+            loop {
+                let task = tokio::spawn(body());
+                let _abort = AbortOnDrop(task.abort_handle());
+                let panicked = matches!(task.await, Err(e) if e.is_panic());
+                if !panicked || !ctx.alive.load(Ordering::SeqCst) {
+                    break;
+                }
+                if restarts >= policy.max_restarts {
+                    error!("Worker for {} panicked after {} restarts, marking it failed", key, restarts);
+                    ctx.events.emit(Event::WorkerFailed {
+                        exchange: ctx.api.name().to_string(),
+                        symbol: key.clone(),
+                        restarts,
+                    });
+                    break;
+                }
+                restarts += 1;
+                warn!(
+                    "Worker for {} panicked, restarting in {} ms ({}/{})",
+                    key, backoff_ms, restarts, policy.max_restarts
+                );
+                let deadline = Instant::now() + Duration::from_millis(backoff_ms);
+                while Instant::now() < deadline && ctx.alive.load(Ordering::SeqCst) {
+                    tokio::time::sleep(Duration::from_millis(100).min(deadline - Instant::now())).await;
+                }
+                backoff_ms = (backoff_ms * 2).min(policy.max_backoff_ms);
+            }
+        })
+    }
+
+    /// Builds the context of a new worker tracked under `key`, marking it alive.
+    fn worker_context(&mut self, key: &WorkerKey, api: Arc<dyn CexApi>) -> WorkerContext {
+        let alive_flag = self.alive.entry(key.clone())
+            .or_insert_with(|| Arc::new(AtomicBool::new(true)));
+        alive_flag.store(true, Ordering::SeqCst);
+        self.started_at.insert(key.clone(), self.clock.now_millis());
+        self.started_with.insert(key.clone(), self.config.clone());
+        let rate = self.config.requests_per_second.get(api.name()).copied().unwrap_or(api.rate_limit());
+        let rate_limiter = self.rate_limiters.for_exchange(api.name(), rate);
+
+        WorkerContext {
+            api,
+            alive: alive_flag.clone(),
+            config: self.config.clone(),
+            snapshots: self.snapshots.clone(),
+            events: self.events.clone(),
+            latest_books: self.latest_books.clone(),
+            sinks: self.sinks.clone(),
+            cooldowns: self.cooldowns.clone(),
+            rate_limiter,
+            clock: self.clock.clone(),
+            pauses: self.pauses.clone(),
+            metrics: self.metrics.clone(),
+            last_writes: self.last_writes.clone(),
+        }
+    }
+
+    /// Updates the `active_symbols` gauge after workers were started or stopped.
+    fn update_active_symbols(&self) {
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        for (exchange, _) in self.active_symbols() {
+            *counts.entry(exchange).or_insert(0) += 1;
+        }
+        self.metrics.set_active_symbols(&counts);
+    }
+
+    /// Groups tickers into the workers that should be running, keyed by worker key.
+    ///
+    /// Tickers with a custom schedule or depth, or with mark price, liquidation, stream or diff book
+    /// collection always get their own worker, keyed by their symbol. The remaining tickers are split into chunks of up to `batch_size`
+    /// tickers; a chunk of several tickers is keyed by its symbols joined with `+`.
+    fn plan_workers(tickers: &[TickerConfig], batch_size: usize) -> Vec<(String, Vec<TickerConfig>)> {
+        let (batchable, individual): (Vec<_>, Vec<_>) = tickers.iter()
+            .cloned()
+            .partition(|t| {
+                batch_size > 1 && t.cron.is_none() && t.interval_secs.is_none() && t.depth.is_empty()
+                    && t.mark_interval_secs.is_none() && !t.liquidations && !t.diff_book && !t.stream
+            });
+
+        let mut plan: Vec<(String, Vec<TickerConfig>)> = individual.into_iter()
+            .map(|t| (t.symbol.clone(), vec![t]))
+            .collect();
+        for chunk in batchable.chunks(batch_size.max(1)) {
+            let key = chunk.iter().map(|t| t.symbol.as_str()).collect::<Vec<_>>().join("+");
+            plan.push((key, chunk.to_vec()));
+        }
+        plan
+    }
+
+    /// Stops all collecting tasks.
+    pub fn stop_all(&mut self) {
+        for alive in self.alive.values() {
+            alive.store(false, Ordering::SeqCst);
+        }
+
+        self.workers.clear();
+        self.stopping.extend(self.handles.drain());
+        self.update_active_symbols();
+        self.await_stopped();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+
+    /// An exchange that never returns data, for tests that do not fetch.
+    struct StubApi;
+
+    #[async_trait::async_trait]
+    impl CexApi for StubApi {
+        fn name(&self) -> &'static str {
+            "STUB"
+        }
+
+        async fn get_order_book(&self, _symbol: &Ticker, _depth: u32) -> Result<String, Box<dyn std::error::Error>> {
+            Err("stub".into())
+        }
+
+        fn parse_order_book(&self, _raw: &str) -> Result<OrderBook, Box<dyn std::error::Error>> {
+            Err("stub".into())
+        }
+
+        fn get_order_book_interval(&self) -> u64 {
+            1
+        }
+    }
+
+    /// An exchange named at construction that never returns data.
+    struct NamedStubApi(&'static str);
+
+    #[async_trait::async_trait]
+    impl CexApi for NamedStubApi {
+        fn name(&self) -> &'static str {
+            self.0
+        }
+
+        async fn get_order_book(&self, _symbol: &Ticker, _depth: u32) -> Result<String, Box<dyn std::error::Error>> {
+            Err("stub".into())
+        }
+
+        fn parse_order_book(&self, _raw: &str) -> Result<OrderBook, Box<dyn std::error::Error>> {
+            Err("stub".into())
+        }
+
+        fn get_order_book_interval(&self) -> u64 {
+            1
+        }
+    }
+
+    /// An exchange that returns a large order book tagged with its symbol immediately.
+    struct EchoApi;
+
+    #[async_trait::async_trait]
+    impl CexApi for EchoApi {
+        fn name(&self) -> &'static str {
+            "STRESS_TEST"
+        }
+
+        async fn get_order_book(&self, symbol: &Ticker, depth: u32) -> Result<String, Box<dyn std::error::Error>> {
+            let level = format!("[\"{}.5\",\"1.0\"]", depth);
+            let levels = vec![level; 500].join(",");
+            Ok(format!(r#"{{"symbol":"{}","bids":[{}],"asks":[{}]}}"#, symbol, levels, levels))
+        }
+
+        fn parse_order_book(&self, _raw: &str) -> Result<OrderBook, Box<dyn std::error::Error>> {
+            Ok(OrderBook {
+                bids: vec![(1.0, 1.0)],
+                asks: vec![(2.0, 1.0)],
+                exchange_ts: None,
+                sequence: None,
+                requested_depth: None,
+            })
+        }
+
+        fn get_order_book_interval(&self) -> u64 {
+            1
+        }
+    }
+
+    fn ticker_config(symbol: &str) -> TickerConfig {
+        TickerConfig { symbol: symbol.to_string(), ..Default::default() }
+    }
+
+    #[test]
+    fn test_plan_workers_without_batching() {
+        let tickers = vec![ticker_config("BTC_USDT"), ticker_config("ETH_USDT")];
+        let keys: Vec<_> = OrderBookCollector::plan_workers(&tickers, 1).into_iter().map(|(key, _)| key).collect();
+        assert_eq!(keys, vec!["BTC_USDT", "ETH_USDT"]);
+    }
+
+    #[test]
+    fn test_plan_workers_groups_default_schedule_tickers() {
+        let mut scheduled = ticker_config("SOL_USDT");
+        scheduled.cron = Some("0 0 * * * *".to_string());
+        let tickers = vec![
+            ticker_config("BTC_USDT"),
+            ticker_config("ETH_USDT"),
+            scheduled,
+            ticker_config("XRP_USDT"),
+        ];
+
+        let keys: Vec<_> = OrderBookCollector::plan_workers(&tickers, 2).into_iter().map(|(key, _)| key).collect();
+        assert_eq!(keys, vec!["SOL_USDT", "BTC_USDT+ETH_USDT", "XRP_USDT"]);
+    }
+
+    #[test]
+    fn test_resolve_tickers() {
+        let valid: HashSet<String> = ["BTC_USDT", "ETH_USDT", "AB_CD", "A_BCD"].iter().map(|s| s.to_string()).collect();
+        let tickers = vec![ticker_config("BTC_USDT"), ticker_config("ETHUSDT"), ticker_config("ABCD"), ticker_config("DOGE_USDT"), ticker_config("BTCUSDT")];
+
+        let symbols: Vec<_> = OrderBookCollector::resolve_tickers(&tickers, &valid).into_iter().map(|t| t.symbol).collect();
+        assert_eq!(symbols, vec!["BTC_USDT", "ETH_USDT"]);
+    }
+
+    #[test]
+    fn test_latest_book_reads_shared_state() {
+        let collector = OrderBookCollector::new();
+        assert_eq!(collector.latest_book("BINANCE", "BTC_USDT"), None);
+
+        let book = OrderBook {
+            bids: vec![(1.0, 2.0)],
+            asks: vec![(3.0, 4.0)],
+            exchange_ts: None,
+            sequence: None,
+            requested_depth: None,
+        };
+        collector.latest_books.write().unwrap().insert(("BINANCE".to_string(), "BTC_USDT".to_string()), book.clone());
+        assert_eq!(collector.latest_book("BINANCE", "BTC_USDT"), Some(book));
+        assert_eq!(collector.latest_book("BITKUB", "BTC_USDT"), None);
+    }
+
+    #[test]
+    fn test_spawn_supervised_restarts_until_limit() {
+        let mut collector = OrderBookCollector::new();
+        let mut config = Config::default();
+        config.panic_restart = crate::config::PanicRestartConfig { max_restarts: 2, backoff_ms: 1, max_backoff_ms: 1 };
+        collector.set_config(Arc::new(config));
+        let ctx = collector.worker_context(&("STUB", "BTC_USDT".to_string()), Arc::new(StubApi));
+
+        let runs = Arc::new(std::sync::atomic::AtomicU32::new(0));
+        let counter = runs.clone();
+        let runtime = collector.runtime();
+        let handle = OrderBookCollector::spawn_supervised(&runtime, "BTC_USDT", ctx, move || {
+            let counter = counter.clone();
+            async move {
+                counter.fetch_add(1, Ordering::SeqCst);
+                panic!("parser bug");
+            }
+        });
+        runtime.block_on(handle).unwrap();
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_stop_with_grace_aborts_hung_worker() {
+        let mut collector = OrderBookCollector::new();
+        let runtime = collector.runtime();
+        let finished = runtime.spawn(async {});
+        assert!(OrderBookCollector::stop_with_grace("BTC_USDT", finished, Duration::from_secs(1)));
+
+        let hung = runtime.spawn(async { tokio::time::sleep(Duration::from_secs(5)).await });
+        let abort = hung.abort_handle();
+        let started = Instant::now();
+        assert!(!OrderBookCollector::stop_with_grace("ETH_USDT", hung, Duration::from_millis(50)));
+        assert!(started.elapsed() < Duration::from_secs(1));
+        thread::sleep(Duration::from_millis(50));
+        assert!(abort.is_finished());
+    }
+
+    #[test]
+    fn test_worker_between_fetches_exits_within_grace_period() {
+        let output_dir = std::env::temp_dir().join("cex_collector_test_long_interval");
+        let mut collector = OrderBookCollector::new();
+        collector.set_config(Arc::new(Config { output_dir: output_dir.to_str().unwrap().to_string(), ..Default::default() }));
+        let key: WorkerKey = ("STUB", "BTC_USDT".to_string());
+        collector.start(&TickerConfig { interval_secs: Some(3600), ..ticker_config("BTC_USDT") }, Arc::new(StubApi));
+        thread::sleep(Duration::from_millis(50));
+
+        collector.request_stop(&key);
+        let (_, handle) = collector.stopping.pop().unwrap();
+        assert!(OrderBookCollector::stop_with_grace("BTC_USDT", handle, Duration::from_secs(1)));
+        let _ = std::fs::remove_dir_all(output_dir);
+    }
+
+    #[test]
+    fn test_stopping_workers_share_one_grace_period() {
+        let mut collector = OrderBookCollector::new();
+        collector.set_config(Arc::new(Config { stop_grace_period_ms: 200, ..Config::default() }));
+        let runtime = collector.runtime();
+        let keys: Vec<WorkerKey> = (0..5).map(|i| ("STUB", format!("COIN{}_USDT", i))).collect();
+        for key in &keys {
+            let ctx = collector.worker_context(key, Arc::new(StubApi));
+            let handle = runtime.spawn(async move {
+                while ctx.alive.load(Ordering::SeqCst) {
+                    tokio::time::sleep(Duration::from_millis(150)).await;
+                }
+            });
+            collector.handles.insert(key.clone(), handle);
+        }
+
+        let started = Instant::now();
+        for key in &keys {
+            collector.request_stop(key);
+        }
+        assert!(collector.handles.is_empty());
+        assert!(started.elapsed() < Duration::from_millis(50));
+        collector.await_stopped();
+        // Waiting one after the other would take five sleeps
+        assert!(started.elapsed() < Duration::from_millis(400));
+        assert!(collector.stopping.is_empty());
+    }
+
+    #[test]
+    fn test_concurrent_workers_write_separate_files() {
+        let output_dir = std::env::temp_dir().join("cex_collector_test_stress");
+        let _ = std::fs::remove_dir_all(&output_dir);
+        let data_dir = output_dir.join("STRESS_TEST");
+
+        let mut collector = OrderBookCollector::new();
+        let mut config = Config::default();
+        config.output_dir = output_dir.to_str().unwrap().to_string();
+        // Poll as fast as possible so the workers' writes overlap
+        config.adaptive_interval = Some(crate::config::AdaptiveIntervalConfig {
+            min_interval_ms: 5,
+            max_interval_ms: 5,
+            threshold_bps: 5.0,
+        });
+        collector.set_config(Arc::new(config));
+        let symbols = ["BTC_USDT", "ETH_USDT", "SOL_USDT", "XRP_USDT", "ADA_USDT", "DOT_USDT"];
+        let tickers: Vec<_> = symbols.iter().map(|symbol| ticker_config(symbol)).collect();
+        collector.start_multiple(&tickers, Arc::new(EchoApi));
+        // The first fetch waits up to a second for the interval to align
+        thread::sleep(Duration::from_millis(1_500));
+        collector.stop_all();
+
+        for symbol in symbols {
+            let mut records = 0;
+            for entry in std::fs::read_dir(data_dir.join(symbol)).unwrap() {
+                let contents = std::fs::read_to_string(entry.unwrap().path()).unwrap();
+                assert!(contents.ends_with('\n'));
+                for line in contents.lines() {
+                    let record: serde_json::Value = serde_json::from_str(line).unwrap();
+                    assert_eq!(record["response"]["symbol"], symbol);
+                    records += 1;
+                }
+            }
+            assert!(records > 1, "{} has only {} records", symbol, records);
+        }
+        std::fs::remove_dir_all(output_dir).unwrap();
+    }
+
+    #[test]
+    fn test_batch_mode_aligns_ticks() {
+        let output_dir = std::env::temp_dir().join("cex_collector_test_batch_mode");
+        let _ = std::fs::remove_dir_all(&output_dir);
+        let mut collector = OrderBookCollector::new();
+        let config = Config {
+            output_dir: output_dir.to_str().unwrap().to_string(),
+            exchanges: vec![crate::config::ExchangeConfig { cex: "STRESS_TEST".to_string(), tickers: vec![], batch_mode: true }],
+            timestamp_unit: crate::config::TimestampUnit::Millis,
+            ..Default::default()
+        };
+        collector.set_config(Arc::new(config));
+        let symbols = ["BTC_USDT", "ETH_USDT", "SOL_USDT"];
+        let tickers: Vec<_> = symbols.iter().map(|symbol| ticker_config(symbol)).collect();
+        collector.start_multiple(&tickers, Arc::new(EchoApi));
+        let keys: Vec<_> = collector.handles.keys().cloned().collect();
+        assert_eq!(keys, vec![("STRESS_TEST", "BTC_USDT+ETH_USDT+SOL_USDT".to_string())]);
+        thread::sleep(Duration::from_millis(1_500));
+        collector.stop_all();
+
+        // Every ticker is recorded with the same time on each tick
+        let times = |symbol: &str| -> Vec<i64> {
+            let dir = output_dir.join("STRESS_TEST").join(symbol);
+            let mut times: Vec<i64> = std::fs::read_dir(dir).unwrap()
+                .flat_map(|entry| {
+                    let contents = std::fs::read_to_string(entry.unwrap().path()).unwrap();
+                    contents.lines()
+                        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["time"].as_i64().unwrap())
+                        .collect::<Vec<_>>()
+                })
+                .collect();
+            times.sort();
+            times
+        };
+        let expected = times("BTC_USDT");
+        assert!(!expected.is_empty());
+        for symbol in symbols {
+            assert_eq!(times(symbol), expected);
+        }
+        std::fs::remove_dir_all(output_dir).unwrap();
+    }
+
+    #[test]
+    fn test_several_depths_share_ticks() {
+        let output_dir = std::env::temp_dir().join("cex_collector_test_depths");
+        let _ = std::fs::remove_dir_all(&output_dir);
+        let mut collector = OrderBookCollector::new();
+        collector.set_config(Arc::new(Config {
+            output_dir: output_dir.to_str().unwrap().to_string(),
+            timestamp_unit: crate::config::TimestampUnit::Millis,
+            ..Default::default()
+        }));
+        let ticker = TickerConfig { depth: vec![10, 100], ..ticker_config("BTC_USDT") };
+        collector.start(&ticker, Arc::new(EchoApi));
+        thread::sleep(Duration::from_millis(1_500));
+        collector.stop_all();
+
+        let records = |dir: std::path::PathBuf| -> Vec<serde_json::Value> {
+            std::fs::read_dir(dir).unwrap()
+                .map(|entry| entry.unwrap().path())
+                .filter(|path| path.is_file())
+                .flat_map(|path| std::fs::read_to_string(path).unwrap().lines()
+                    .map(|line| serde_json::from_str(line).unwrap())
+                    .collect::<Vec<_>>())
+                .collect()
+        };
+        let ticker_dir = output_dir.join("STRESS_TEST").join("BTC_USDT");
+        let main = records(ticker_dir.clone());
+        let deep = records(ticker_dir.join("depth_100"));
+        assert!(!main.is_empty());
+        assert_eq!(main.iter().map(|r| r["time"].clone()).collect::<Vec<_>>(), deep.iter().map(|r| r["time"].clone()).collect::<Vec<_>>());
+        assert!(main.iter().all(|r| r["depth"].is_null() && r["response"]["bids"][0][0] == "10.5"));
+        assert!(deep.iter().all(|r| r["depth"] == 100 && r["response"]["bids"][0][0] == "100.5"));
+        std::fs::remove_dir_all(output_dir).unwrap();
+    }
+
+    #[test]
+    fn test_collect_once() {
+        let output_dir = std::env::temp_dir().join("cex_collector_test_once");
+        let _ = std::fs::remove_dir_all(&output_dir);
+        let mut collector = OrderBookCollector::new();
+        collector.set_config(Arc::new(Config { output_dir: output_dir.to_str().unwrap().to_string(), ..Default::default() }));
+
+        let tickers = [ticker_config("BTC_USDT"), ticker_config("ETH_USDT"), ticker_config("BTC_")];
+        assert_eq!(collector.collect_once(&tickers, Arc::new(EchoApi)), 1);
+        assert!(collector.handles.is_empty());
+        for symbol in ["BTC_USDT", "ETH_USDT"] {
+            let dir = output_dir.join("STRESS_TEST").join(symbol);
+            let files: Vec<_> = std::fs::read_dir(dir).unwrap().collect();
+            assert_eq!(files.len(), 1);
+            let contents = std::fs::read_to_string(files[0].as_ref().unwrap().path()).unwrap();
+            assert_eq!(contents.lines().count(), 1);
+        }
+        std::fs::remove_dir_all(output_dir).unwrap();
+    }
+
+    #[test]
+    fn test_check() {
+        let mut collector = OrderBookCollector::new();
+        let tickers = [ticker_config("BTC_USDT"), ticker_config("BTC_")];
+        let results = collector.check(&tickers, Arc::new(EchoApi));
+        assert_eq!(results.iter().map(|result| result.passed()).collect::<Vec<_>>(), vec![true, false]);
+        assert_eq!(results[1].error.as_deref(), Some("Invalid symbol format"));
+        assert!(collector.handles.is_empty());
+    }
+
+    #[test]
+    fn test_check_stalled() {
+        let output_dir = std::env::temp_dir().join("cex_collector_test_stalled");
+        let clock = Arc::new(MockClock::new(1_000_000));
+        let mut collector = OrderBookCollector::new();
+        collector.set_clock(clock.clone());
+        collector.set_config(Arc::new(Config { output_dir: output_dir.to_str().unwrap().to_string(), ..Default::default() }));
+        collector.start(&ticker_config("BTC_USDT"), Arc::new(NamedStubApi("STUB")));
+        assert!(collector.check_stalled().is_empty());
+
+        // Every fetch fails, so nothing is written within 10 intervals of 1 second
+        clock.advance(10_001);
+        assert_eq!(collector.check_stalled(), vec![("STUB".to_string(), "BTC_USDT".to_string())]);
+        assert_eq!(collector.metrics().render().lines().filter(|line| line.starts_with("worker_stalls_total")).count(), 1);
+        assert!(collector.check_stalled().is_empty());
+        assert_eq!(collector.last_write("STUB", "BTC_USDT"), None);
+
+        collector.last_writes.record("STUB", "BTC_USDT", clock.now_millis());
+        assert_eq!(collector.last_write("STUB", "BTC_USDT"), Some(1_010_001));
+        assert!(collector.check_stalled().is_empty());
+        assert!(collector.stalled.is_empty());
+
+        collector.pause("BTC_USDT");
+        clock.advance(20_000);
+        assert!(collector.check_stalled().is_empty());
+        collector.stop_all();
+        let _ = std::fs::remove_dir_all(output_dir);
+    }
+
+    #[test]
+    fn test_restart_stalled() {
+        let output_dir = std::env::temp_dir().join("cex_collector_test_restart_stalled");
+        let clock = Arc::new(MockClock::new(1_000_000));
+        let mut collector = OrderBookCollector::new();
+        collector.set_clock(clock.clone());
+        let config = Config { output_dir: output_dir.to_str().unwrap().to_string(), restart_stalled: true, ..Default::default() };
+        collector.set_config(Arc::new(config));
+        collector.start(&ticker_config("BTC_USDT"), Arc::new(NamedStubApi("STUB")));
+
+        clock.advance(10_001);
+        assert_eq!(collector.check_stalled().len(), 1);
+        assert!(collector.is_running("STUB", "BTC_USDT"));
+        // The restarted worker is measured from its restart
+        assert!(collector.check_stalled().is_empty());
+        clock.advance(10_001);
+        assert_eq!(collector.check_stalled().len(), 1);
+        collector.stop_all();
+        let _ = std::fs::remove_dir_all(output_dir);
+    }
+
+    #[test]
+    fn test_failed_reload_keeps_running_workers() {
+        let output_dir = std::env::temp_dir().join("cex_collector_test_failed_reload");
+        let mut collector = OrderBookCollector::new();
+        let config = Config { output_dir: output_dir.to_str().unwrap().to_string(), ..Default::default() };
+        let exchange = ExchangeConfig { cex: "STUB".to_string(), tickers: vec![ticker_config("BTC_USDT")], batch_mode: false };
+        collector.apply(Arc::new(config.clone()), &[(exchange, Arc::new(NamedStubApi("STUB")))]);
+        assert!(collector.is_running("STUB", "BTC_USDT"));
+
+        // The second exchange cannot be built, so the first one is not applied either
+        let bad: Config = serde_json::from_str(r#"{"exchanges": [
+            {"cex": "BINANCE", "tickers": ["ETH_USDT"]},
+            {"cex": "FTX", "tickers": ["ETH_USDT"]}
+        ]}"#).unwrap();
+        assert!(collector.reload(bad).is_err());
+        assert_eq!(collector.active_symbols(), vec![("STUB".to_string(), "BTC_USDT".to_string())]);
+        assert_eq!(collector.config(), &config);
+        collector.stop_all();
+        let _ = std::fs::remove_dir_all(output_dir);
+    }
+
+    #[test]
+    fn test_reload_restarts_changed_workers() {
+        let output_dir = std::env::temp_dir().join("cex_collector_test_reload_changed");
+        let clock = Arc::new(MockClock::new(1_000_000));
+        let mut collector = OrderBookCollector::new();
+        collector.set_clock(clock.clone());
+        let config = Arc::new(Config {
+            output_dir: output_dir.to_str().unwrap().to_string(),
+            stop_grace_period_ms: 100,
+            ..Default::default()
+        });
+        let api: Arc<dyn CexApi> = Arc::new(NamedStubApi("STUB"));
+        let exchange = |tickers| ExchangeConfig { cex: "STUB".to_string(), tickers, batch_mode: false };
+        let key: WorkerKey = ("STUB", "BTC_USDT".to_string());
+        collector.apply(config.clone(), &[(exchange(vec![ticker_config("BTC_USDT")]), api.clone())]);
+        let first = collector.handles[&key].abort_handle();
+
+        // An unchanged ticker keeps its worker when another one is added
+        collector.apply(config.clone(), &[(exchange(vec![ticker_config("BTC_USDT"), ticker_config("ETH_USDT")]), api.clone())]);
+        thread::sleep(Duration::from_millis(50));
+        assert!(!first.is_finished());
+
+        let slower = TickerConfig { interval_secs: Some(5), ..ticker_config("BTC_USDT") };
+        collector.apply(config.clone(), &[(exchange(vec![slower, ticker_config("ETH_USDT")]), api.clone())]);
+        thread::sleep(Duration::from_millis(50));
+        assert!(first.is_finished());
+        assert!(collector.is_running("STUB", "BTC_USDT"));
+        assert_eq!(collector.workers[&key].0[0].interval_secs, Some(5));
+
+        // Only ETH_USDT, still fetched every second, stalls within 10 seconds
+        clock.advance(10_001);
+        assert_eq!(collector.check_stalled(), vec![("STUB".to_string(), "ETH_USDT".to_string())]);
+        clock.advance(40_000);
+        assert_eq!(collector.check_stalled(), vec![("STUB".to_string(), "BTC_USDT".to_string())]);
+
+        // A changed global option restarts every worker under the new configuration
+        let second = collector.handles[&key].abort_handle();
+        let deeper = Arc::new(Config { depth: 20, ..(*config).clone() });
+        collector.apply(deeper.clone(), &[(exchange(vec![ticker_config("BTC_USDT")]), api)]);
+        thread::sleep(Duration::from_millis(50));
+        assert!(second.is_finished());
+        assert_eq!(collector.started_with[&key], deeper);
+        collector.stop_all();
+        let _ = std::fs::remove_dir_all(output_dir);
+    }
+
+    #[test]
+    fn test_same_symbol_on_two_exchanges() {
+        let output_dir = std::env::temp_dir().join("cex_collector_test_exchanges");
+        let mut collector = OrderBookCollector::new();
+        let config = Config { output_dir: output_dir.to_str().unwrap().to_string(), ..Default::default() };
+        collector.set_config(Arc::new(config));
+        collector.start_multiple(&[ticker_config("BTC_USDT")], Arc::new(NamedStubApi("STUB_A")));
+        collector.start_multiple(&[ticker_config("BTC_USDT")], Arc::new(NamedStubApi("STUB_B")));
+        let mut keys: Vec<_> = collector.handles.keys().cloned().collect();
+        keys.sort();
+        assert_eq!(keys, vec![("STUB_A", "BTC_USDT".to_string()), ("STUB_B", "BTC_USDT".to_string())]);
+
+        assert_eq!(collector.active_symbols(), vec![
+            ("STUB_A".to_string(), "BTC_USDT".to_string()),
+            ("STUB_B".to_string(), "BTC_USDT".to_string()),
+        ]);
+
+        collector.retain_exchanges(&["STUB_B".to_string()]);
+        let keys: Vec<_> = collector.handles.keys().cloned().collect();
+        assert_eq!(keys, vec![("STUB_B", "BTC_USDT".to_string())]);
+        assert!(collector.is_running("STUB_B", "BTC_USDT"));
+        assert!(!collector.is_running("STUB_A", "BTC_USDT"));
+        assert_eq!(collector.active_symbols(), vec![("STUB_B".to_string(), "BTC_USDT".to_string())]);
+        collector.stop_all();
+        let _ = std::fs::remove_dir_all(output_dir);
+    }
+
+    #[test]
+    fn test_start_replaces_running_worker() {
+        let output_dir = std::env::temp_dir().join("cex_collector_test_restart");
+        let mut collector = OrderBookCollector::new();
+        let config = Config {
+            output_dir: output_dir.to_str().unwrap().to_string(),
+            stop_grace_period_ms: 100,
+            ..Default::default()
+        };
+        collector.set_config(Arc::new(config));
+        let key: WorkerKey = ("STUB", "BTC_USDT".to_string());
+        collector.start(&ticker_config("BTC_USDT"), Arc::new(StubApi));
+        let first = collector.handles[&key].abort_handle();
+
+        collector.start(&ticker_config("BTC_USDT"), Arc::new(StubApi));
+        // The old worker exited or, if it was still waiting for its first fetch, was aborted
+        thread::sleep(Duration::from_millis(50));
+        assert!(first.is_finished());
+        assert_eq!(collector.handles.len(), 1);
+        assert!(!collector.handles[&key].is_finished());
+        collector.stop_all();
+        let _ = std::fs::remove_dir_all(output_dir);
+    }
+
+    #[test]
+    fn test_pause_keeps_worker_running() {
+        let output_dir = std::env::temp_dir().join("cex_collector_test_pause");
+        let mut collector = OrderBookCollector::new();
+        let config = Config { output_dir: output_dir.to_str().unwrap().to_string(), ..Default::default() };
+        collector.set_config(Arc::new(config));
+        collector.start_multiple(&[ticker_config("BTC_USDT"), ticker_config("ETH_USDT")], Arc::new(StubApi));
+        let key: WorkerKey = ("STUB", "BTC_USDT".to_string());
+
+        collector.pause("BTC_USDT");
+        assert!(collector.is_paused("BTC_USDT"));
+        assert!(!collector.is_paused("ETH_USDT"));
+        thread::sleep(Duration::from_millis(50));
+        assert!(!collector.handles[&key].is_finished());
+        assert!(collector.is_running("STUB", "BTC_USDT"));
 
-        writeln!(file, "{}", data).expect("Unable to write data");
+        // A restarted worker stays paused until resumed
+        collector.restart_all();
+        assert!(collector.is_paused("BTC_USDT"));
+        collector.pause_all();
+        assert!(collector.is_paused("ETH_USDT"));
+        collector.resume_all();
+        assert!(!collector.is_paused("BTC_USDT") && !collector.is_paused("ETH_USDT"));
+        collector.stop_all();
+        let _ = std::fs::remove_dir_all(output_dir);
     }
 }
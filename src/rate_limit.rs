@@ -0,0 +1,120 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::time::{sleep, Duration};
+
+/// A token bucket limiting the request weight sent to an exchange per second.
+///
+/// The bucket holds up to one second worth of tokens and refills continuously, so workers
+/// started at the same moment are spread out instead of firing together.
+pub struct RateLimiter {
+    state: Mutex<BucketState>,
+}
+
+struct BucketState {
+    /// The tokens added per second, which is also the bucket capacity. `0` disables limiting.
+    rate: u32,
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl RateLimiter {
+    /// Creates a full bucket allowing `rate` weight per second, or an unlimited one if `rate` is 0.
+    pub fn new(rate: u32) -> RateLimiter {
+        RateLimiter {
+            state: Mutex::new(BucketState { rate, tokens: rate as f64, refilled_at: Instant::now() }),
+        }
+    }
+
+    /// Changes the allowed weight per second, keeping the tokens currently available.
+    pub fn set_rate(&self, rate: u32) {
+        let mut state = self.state.lock().unwrap();
+        state.rate = rate;
+        state.tokens = state.tokens.min(rate as f64);
+    }
+
+    /// Waits until `weight` tokens are available and takes them.
+    ///
+    /// A weight above the bucket capacity waits for a full bucket, so oversized requests
+    /// still go through, at most one per second.
+    pub async fn acquire(&self, weight: u32) {
+        while let Some(wait) = self.try_acquire(weight, Instant::now()) {
+            sleep(wait).await;
+        }
+    }
+
+    /// Takes `weight` tokens if they are available at `now`.
+    ///
+    /// # Returns
+    ///
+    /// `None` if the tokens were taken, or the time to wait before trying again.
+    fn try_acquire(&self, weight: u32, now: Instant) -> Option<Duration> {
+        let mut state = self.state.lock().unwrap();
+        if state.rate == 0 {
+            return None;
+        }
+        let rate = state.rate as f64;
+        let elapsed = now.saturating_duration_since(state.refilled_at).as_secs_f64();
+        state.tokens = (state.tokens + elapsed * rate).min(rate);
+        state.refilled_at = now;
+
+        let needed = (weight as f64).min(rate);
+        if state.tokens >= needed {
+            state.tokens -= needed;
+            return None;
+        }
+        Some(Duration::from_secs_f64((needed - state.tokens) / rate))
+    }
+}
+
+/// The rate limiter of each exchange, shared by all of its workers.
+#[derive(Clone, Default)]
+pub struct RateLimiters {
+    limiters: Arc<Mutex<HashMap<&'static str, Arc<RateLimiter>>>>,
+}
+
+impl RateLimiters {
+    /// Returns the limiter of `exchange`, creating it or updating its rate to `rate`.
+    pub fn for_exchange(&self, exchange: &'static str, rate: u32) -> Arc<RateLimiter> {
+        let mut limiters = self.limiters.lock().unwrap();
+        let limiter = limiters.entry(exchange).or_insert_with(|| Arc::new(RateLimiter::new(rate)));
+        limiter.set_rate(rate);
+        limiter.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket_refills_over_time() {
+        let limiter = RateLimiter::new(10);
+        let start = Instant::now();
+        for _ in 0..10 {
+            assert_eq!(limiter.try_acquire(1, start), None);
+        }
+        let wait = limiter.try_acquire(5, start).unwrap();
+        assert!((wait.as_secs_f64() - 0.5).abs() < 1e-6);
+        assert_eq!(limiter.try_acquire(5, start + Duration::from_millis(500)), None);
+        // Oversized requests wait for a full bucket
+        assert!(limiter.try_acquire(50, start + Duration::from_millis(500)).is_some());
+        assert_eq!(limiter.try_acquire(50, start + Duration::from_millis(1500)), None);
+    }
+
+    #[test]
+    fn test_zero_rate_is_unlimited() {
+        let limiter = RateLimiter::new(0);
+        for _ in 0..1000 {
+            assert_eq!(limiter.try_acquire(100, Instant::now()), None);
+        }
+    }
+
+    #[test]
+    fn test_limiters_are_shared_per_exchange() {
+        let limiters = RateLimiters::default();
+        let binance = limiters.for_exchange("BINANCE", 100);
+        assert!(Arc::ptr_eq(&binance, &limiters.for_exchange("BINANCE", 100)));
+        assert!(!Arc::ptr_eq(&binance, &limiters.for_exchange("BITKUB", 100)));
+    }
+}
@@ -0,0 +1,951 @@
+use std::collections::HashMap;
+use std::fmt::Write as FmtWrite;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::sync::broadcast;
+use crate::cex_api::CexApi;
+use crate::clock::Clock;
+use crate::metrics::Metrics;
+use crate::config::{Config, DepthPolicy, OutputFormat, TimestampUnit};
+use crate::order_book::{OrderBook, Snapshot};
+use crate::schedule::Schedule;
+use crate::sink::Sink;
+use crate::storage::{create_directory, HourlyFile, SequenceFile, SnapshotFiles};
+use crate::ticker::Ticker;
+use crate::worker::{FetchedBook, LastWrites, WorkerContext};
+use tracing::{debug, error, info, warn};
+
+/// The latest normalized order book of each collected ticker, keyed by exchange name and "BASE_QUOTE" symbol.
+pub type LatestBooks = Arc<RwLock<HashMap<(String, String), OrderBook>>>;
+
+/// The version of the record format, stored as the `"v"` of every record and reported in file headers.
+///
+/// Bumped whenever the layout of records changes, so consumers can tell which layout a record uses.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// The minimum number of seconds between two `"unchanged"` markers while `dedupe` skips records.
+const UNCHANGED_MARKER_INTERVAL_SECS: i64 = 60;
+
+/// The column header of CSV data files.
+const CSV_HEADER: &str = "time,side,level,price,qty";
+
+/// Turns the fetched order book responses of a single ticker into saved records.
+///
+/// Each response is wrapped with its collection time and appended to the ticker's hourly file
+/// in `<output_dir>/{exchange}/{ticker}/`. With `store_depth` set, the record holds the top levels of the
+/// normalized book instead of (or, with `store_full_response`, next to) the raw response.
+/// With `delta_snapshot_interval_secs` set, the normalized book is stored as a periodic full
+/// `"snapshot"` and, in between, as `"delta"` records holding the levels changed since the previous record.
+/// With `depth_curve_pct` set, the cumulative depth around the midprice is stored in `depth_curve/`.
+/// With `spread_bps` enabled, the spread of each snapshot is appended to `spread_bps.csv`.
+/// With `emit_spread` enabled, the top of book of each snapshot is appended to the exchange's `spread/{ticker}.csv`.
+/// With `stale_fill` enabled, a failed fetch re-records the last-good response flagged as stale.
+/// With `sequence_numbers` enabled, each record carries a persisted per-ticker `"seq"` number.
+/// With `dedupe` enabled, a fetched book with the same levels as the previous one is not written;
+/// during such a quiet period an `{"time": ..., "unchanged": true}` marker is written at most once a minute.
+/// With `file_per_snapshot` enabled, each record is written to its own file instead of the hourly file.
+/// With the `csv` output format, the hourly files hold one row per level of each fetched book instead,
+/// and stale or timed-out fetches are only published to sinks.
+/// The first `warmup_snapshots` responses are discarded, as are failures before warmup completes.
+/// While the snapshot channel has subscribers, the response is also normalized and published on it.
+/// While the ticker is paused, nothing is recorded or published.
+pub struct Recorder {
+    ticker: Ticker,
+    api: Arc<dyn CexApi>,
+    file: HourlyFile,
+    /// The per-record files, used instead of `file` when `file_per_snapshot` is enabled.
+    snapshot_files: Option<SnapshotFiles>,
+    snapshots: broadcast::Sender<Arc<Snapshot>>,
+    /// The depth the recorded books are fetched at.
+    depth: u32,
+    /// The depth of a recorder of an extra depth, stored as the `"depth"` of each record.
+    extra_depth: Option<u32>,
+    store_depth: Option<usize>,
+    store_full_response: bool,
+    sequence: Option<SequenceFile>,
+    parse_always: bool,
+    last_book: Option<OrderBook>,
+    /// The exchange sequence number of the last parsed book that carried one.
+    last_sequence: Option<u64>,
+    delta_snapshot_interval_secs: Option<u64>,
+    /// The time of the last full snapshot record and the book stored by the latest record since.
+    delta_base: Option<(i64, OrderBook)>,
+    depth_policy: DepthPolicy,
+    /// The depth that normalized books are shaped to under `depth_policy`.
+    policy_depth: usize,
+    stale_fill: bool,
+    /// The collection time and text of the last response, kept while `stale_fill` is enabled.
+    last_good: Option<(i64, String)>,
+    /// The shared latest books, updated while `track_latest_books` is enabled.
+    latest_books: Option<LatestBooks>,
+    write_files: bool,
+    sinks: Vec<Arc<dyn Sink>>,
+    /// The derived depth curve file and its thresholds, if depth curves are enabled.
+    depth_curve: Option<(HourlyFile, Vec<f64>)>,
+    /// The number of responses still to discard before recording starts.
+    warmup_remaining: u32,
+    /// The `spread_bps.csv` file, if spreads are recorded.
+    spread_file: Option<File>,
+    /// The `spread/{ticker}.csv` file, if the top of book is recorded.
+    top_of_book_file: Option<File>,
+    /// Whether the hourly files are written as CSV rows instead of JSON records.
+    csv: bool,
+    dedupe: bool,
+    /// The time of the last record or `"unchanged"` marker, kept while `dedupe` is enabled.
+    last_written_at: Option<i64>,
+    /// The unit of all timestamps of the recorder.
+    timestamp_unit: TimestampUnit,
+    clock: Arc<dyn Clock>,
+    /// The pause flag of the ticker, set by the collector.
+    paused: Arc<AtomicBool>,
+    metrics: Metrics,
+    last_writes: LastWrites,
+}
+
+impl Recorder {
+    /// Creates a new `Recorder`, creating the ticker's data directory if needed.
+    ///
+    /// # Arguments
+    ///
+    /// * `ticker` - The ticker whose responses are recorded.
+    /// * `ctx` - The `WorkerContext` of the worker, providing the API, config and shared channels.
+    /// * `schedule` - The worker's `Schedule`, reported in file headers.
+    pub fn new(ticker: Ticker, ctx: &WorkerContext, schedule: &Schedule) -> Recorder {
+        let dir = ctx.config.ticker_dir(ctx.api.name(), &ticker.to_string());
+        Recorder::in_dir(ticker, ctx, schedule, dir)
+    }
+
+    /// Creates a `Recorder` of the ticker's order book at an extra `depth`, fetched next to its main depth.
+    ///
+    /// Its records carry a `"depth"` field and are written to the `depth_<depth>` subdirectory of
+    /// the ticker's data directory. The snapshot channel, the latest books and the exchange's
+    /// top-of-book file are left to the recorder of the main depth, as they hold one book per ticker.
+    ///
+    /// # Arguments
+    ///
+    /// * `ticker` - The ticker whose responses are recorded.
+    /// * `ctx` - The `WorkerContext` of the worker, providing the API, config and shared channels.
+    /// * `schedule` - The worker's `Schedule`, reported in file headers.
+    /// * `depth` - The depth the recorded books are fetched at.
+    pub fn for_depth(ticker: Ticker, ctx: &WorkerContext, schedule: &Schedule, depth: u32) -> Recorder {
+        let config = Config { depth, emit_spread: false, track_latest_books: false, ..(*ctx.config).clone() };
+        let ctx = WorkerContext { config: Arc::new(config), snapshots: broadcast::channel(1).0, ..ctx.clone() };
+        let dir = format!("{}/depth_{}", ctx.config.ticker_dir(ctx.api.name(), &ticker.to_string()), depth);
+        let mut recorder = Recorder::in_dir(ticker, &ctx, schedule, dir);
+        recorder.extra_depth = Some(depth);
+        recorder
+    }
+
+    /// Creates a new `Recorder` writing to `dir`, creating the directory if needed.
+    fn in_dir(ticker: Ticker, ctx: &WorkerContext, schedule: &Schedule, dir: String) -> Recorder {
+        let api = ctx.api.clone();
+        let config = &ctx.config;
+        create_directory(dir.as_str());
+
+        let mut file = HourlyFile::from_config(&dir, config, api.name(), &ticker.to_string());
+        let csv = config.output_format == OutputFormat::Csv;
+        if csv {
+            file.set_extension("csv");
+            file.set_header(CSV_HEADER.to_string());
+        } else if config.file_header {
+            let depth = config.store_depth.unwrap_or(config.depth as usize);
+            file.set_header(header_record(api.name(), &ticker.to_string(), Some(depth), schedule.describe()));
+        }
+
+        let depth_curve = config.depth_curve_pct.as_ref().map(|thresholds| {
+            let curve_dir = format!("{}/depth_curve", dir);
+            create_directory(curve_dir.as_str());
+            let curve_file = HourlyFile::from_config(&curve_dir, config, api.name(), &ticker.to_string());
+            (curve_file, thresholds.clone())
+        });
+
+        let spread_file = if config.spread_bps {
+            open_spread_file(&dir).map_err(|e| error!("Cannot open spread file of {}: {}", ticker, e)).ok()
+        } else {
+            None
+        };
+
+        let top_of_book_file = if config.emit_spread {
+            let spread_dir = config.spread_dir(api.name());
+            create_directory(spread_dir.as_str());
+            let path = format!("{}/{}.csv", spread_dir, ticker);
+            open_csv_file(&path, TOP_OF_BOOK_HEADER).map_err(|e| error!("Cannot open {}: {}", path, e)).ok()
+        } else {
+            None
+        };
+
+        let snapshot_files = if config.file_per_snapshot {
+            Some(SnapshotFiles::new(&dir, config.compression, config.snapshot_collision))
+        } else {
+            None
+        };
+
+        let paused = ctx.pauses.flag(&ticker.to_string());
+        Recorder {
+            ticker,
+            api,
+            file,
+            snapshot_files,
+            snapshots: ctx.snapshots.clone(),
+            depth: config.depth,
+            extra_depth: None,
+            store_depth: config.store_depth,
+            store_full_response: config.store_full_response,
+            sequence: if config.sequence_numbers { Some(SequenceFile::load(&dir)) } else { None },
+            parse_always: config.adaptive_interval.is_some()
+                || config.delta_snapshot_interval_secs.is_some()
+                || config.track_latest_books
+                || config.depth_curve_pct.is_some()
+                || config.spread_bps
+                || config.emit_spread
+                || config.dedupe
+                || csv,
+            last_book: None,
+            last_sequence: None,
+            delta_snapshot_interval_secs: config.delta_snapshot_interval_secs,
+            delta_base: None,
+            depth_policy: config.depth_policy,
+            policy_depth: config.store_depth.unwrap_or(config.depth as usize),
+            stale_fill: config.stale_fill,
+            last_good: None,
+            latest_books: if config.track_latest_books { Some(ctx.latest_books.clone()) } else { None },
+            write_files: config.writes_files(),
+            sinks: ctx.sinks.clone(),
+            depth_curve,
+            warmup_remaining: config.warmup_snapshots,
+            spread_file,
+            top_of_book_file,
+            csv,
+            dedupe: config.dedupe,
+            last_written_at: None,
+            timestamp_unit: config.timestamp_unit,
+            clock: ctx.clock.clone(),
+            paused,
+            metrics: ctx.metrics.clone(),
+            last_writes: ctx.last_writes.clone(),
+        }
+    }
+
+    /// Counts an order book record of the ticker that was written in full in the collection metrics
+    /// and notes its time.
+    fn count_write(&self) {
+        let symbol = self.ticker.to_string();
+        self.metrics.snapshot_written(self.api.name(), &symbol);
+        self.last_writes.record(self.api.name(), &symbol, self.clock.now_millis());
+    }
+
+    /// Counts a failed or timed-out fetch of the ticker in the collection metrics.
+    pub fn count_fetch_error(&self) {
+        self.metrics.fetch_error(self.api.name(), &self.ticker.to_string());
+    }
+
+    /// Returns the current time in the unit of the records.
+    fn now(&self) -> i64 {
+        self.timestamp_unit.timestamp(self.clock.now_millis())
+    }
+
+    /// Returns the key of the ticker in the shared latest books.
+    fn book_key(&self) -> (String, String) {
+        (self.api.name().to_string(), self.ticker.to_string())
+    }
+
+    /// Returns whether the ticker is paused, in which case the worker should skip its fetches.
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    /// Returns the depth the recorded books are fetched at.
+    pub fn depth(&self) -> u32 {
+        self.depth
+    }
+
+    /// Returns the extra depth this recorder writes for, or `None` for the ticker's main depth.
+    pub fn extra_depth(&self) -> Option<u32> {
+        self.extra_depth
+    }
+
+    /// Returns the ticker this recorder writes for.
+    pub fn ticker(&self) -> &Ticker {
+        &self.ticker
+    }
+
+    /// Returns the normalized order book of the last recorded response, if it was parsed.
+    ///
+    /// Responses are parsed when `store_depth`, `adaptive_interval`, `delta_snapshot_interval_secs`
+    /// or `track_latest_books` is set, or while the
+    /// snapshot channel has subscribers.
+    pub fn last_book(&self) -> Option<&OrderBook> {
+        self.last_book.as_ref()
+    }
+
+    /// Saves a raw order book response and publishes its normalized form.
+    ///
+    /// # Arguments
+    ///
+    /// * `response_text` - The raw JSON response returned by the exchange.
+    /// * `latency_ms` - The time the exchange took to serve the response, stored as `"latency_ms"` if known.
+    pub fn record(&mut self, response_text: &str, latency_ms: Option<u64>) {
+        let timestamp = self.now();
+        self.record_at(timestamp, response_text, latency_ms);
+    }
+
+    /// Saves a raw order book response collected at `timestamp`, e.g. the shared time of an aligned tick.
+    ///
+    /// # Arguments
+    ///
+    /// * `timestamp` - The collection time of the response, in the unit of the records.
+    /// * `response_text` - The raw JSON response returned by the exchange.
+    /// * `latency_ms` - The time the exchange took to serve the response, stored as `"latency_ms"` if known.
+    pub fn record_at(&mut self, timestamp: i64, response_text: &str, latency_ms: Option<u64>) {
+        self.record_parsed(timestamp, response_text, None, latency_ms);
+    }
+
+    /// Saves a fetched order book response, reusing the book parsed when the fetch was checked.
+    ///
+    /// # Arguments
+    ///
+    /// * `fetched` - The fetched response, its book and latency.
+    pub fn record_fetched(&mut self, fetched: FetchedBook) {
+        let timestamp = self.now();
+        self.record_fetched_at(timestamp, fetched);
+    }
+
+    /// Saves a fetched order book response collected at `timestamp`, see `record_fetched`.
+    ///
+    /// # Arguments
+    ///
+    /// * `timestamp` - The collection time of the response, in the unit of the records.
+    /// * `fetched` - The fetched response, its book and latency.
+    pub fn record_fetched_at(&mut self, timestamp: i64, fetched: FetchedBook) {
+        self.record_parsed(timestamp, &fetched.response_text, fetched.book, Some(fetched.latency_ms));
+    }
+
+    /// Saves a response, parsing it unless its book is given.
+    fn record_parsed(&mut self, timestamp: i64, response_text: &str, book: Option<OrderBook>, latency_ms: Option<u64>) {
+        if self.is_paused() {
+            return;
+        }
+        if self.warmup_remaining > 0 {
+            self.warmup_remaining -= 1;
+            if self.warmup_remaining == 0 {
+                info!("Warmup of {} on {} complete, recording starts", self.ticker, self.api.name());
+            }
+            return;
+        }
+        let response_text = response_text.trim_end_matches('\n');
+        if self.stale_fill {
+            self.last_good = Some((timestamp, response_text.to_string()));
+        }
+
+        let book = match book {
+            Some(book) if self.needs_book() => Some(self.shape(book)),
+            _ => self.parse(response_text),
+        };
+        if let Some(book) = &book {
+            self.check_sequence(book);
+        }
+        if let (true, Some(book), Some(last_book)) = (self.dedupe, &book, &self.last_book) {
+            if book.same_levels(last_book) {
+                self.record_unchanged(timestamp);
+                return;
+            }
+        }
+        self.last_written_at = Some(timestamp);
+        let flags = latency_ms.map(|latency_ms| ("latency_ms", latency_ms.to_string())).into_iter().collect();
+        let (book, mut written) = self.store(timestamp, response_text, book, flags);
+        match &book {
+            Some(book) => written &= self.save_csv(timestamp, book),
+            // Only parsed books have CSV rows
+            None => written &= !(self.csv && self.write_files),
+        }
+        if written {
+            self.count_write();
+        }
+
+        if let (Some((curve_file, thresholds)), Some(book)) = (&mut self.depth_curve, &book) {
+            if let Some(curve) = book.depth_curve(thresholds) {
+                let curve = serde_json::to_string(&curve).expect("Depth curve is serializable");
+                if let Err(e) = curve_file.write(timestamp, &build_record(timestamp, &[("depth", curve)])) {
+                    error!("Cannot write depth curve of {} on {}: {}", self.ticker, self.api.name(), e);
+                }
+            }
+        }
+        if let (Some(spread_file), Some(book)) = (&mut self.spread_file, &book) {
+            match book.spread_bps() {
+                Some(spread) if spread < 0.0 => {
+                    warn!("Crossed order book of {} on {}, skipping spread", self.ticker, self.api.name());
+                }
+                Some(spread) => {
+                    if let Err(e) = writeln!(spread_file, "{},{:.4}", timestamp, spread) {
+                        error!("Cannot write spread of {} on {}: {}", self.ticker, self.api.name(), e);
+                    }
+                }
+                None => {}
+            }
+        }
+        if let (Some(top_of_book_file), Some(book)) = (&mut self.top_of_book_file, &book) {
+            if book.spread_bps().is_some_and(|spread| spread <= 0.0) {
+                warn!("Crossed order book of {} on {}: best bid is not below best ask", self.ticker, self.api.name());
+            }
+            if let Err(e) = writeln!(top_of_book_file, "{}", top_of_book_row(timestamp, book)) {
+                error!("Cannot write top of book of {} on {}: {}", self.ticker, self.api.name(), e);
+            }
+        }
+        self.last_book = book.clone();
+        if let (Some(latest_books), Some(book)) = (&self.latest_books, &book) {
+            latest_books.write().unwrap().insert(self.book_key(), book.clone());
+        }
+        if let Some(book) = book {
+            if self.snapshots.receiver_count() > 0 {
+                let _ = self.snapshots.send(Arc::new(Snapshot {
+                    time: timestamp,
+                    exchange: self.api.name().to_string(),
+                    symbol: self.ticker.to_string(),
+                    book,
+                }));
+            }
+        }
+    }
+
+    /// Saves the last successfully fetched response again after a failed fetch, if `stale_fill` is enabled.
+    ///
+    /// The record is flagged with `"stale": true` and carries the original collection time in
+    /// `"fetched_at"`, so the time series stays contiguous. Stale records are not published.
+    ///
+    /// # Returns
+    ///
+    /// `true` if a stale record was written, `false` if there is no last-good response.
+    pub fn record_stale(&mut self) -> bool {
+        if self.warmup_remaining > 0 || self.is_paused() {
+            return false;
+        }
+        let (fetched_at, response_text) = match self.last_good.clone() {
+            Some(last_good) => last_good,
+            None => return false,
+        };
+        let timestamp = self.now();
+        let flags = vec![("stale", "true".to_string()), ("fetched_at", fetched_at.to_string())];
+        let book = self.parse(&response_text);
+        self.store(timestamp, &response_text, book, flags);
+        true
+    }
+
+    /// Saves an `{"time": ..., "unchanged": true}` marker for a deduplicated book,
+    /// unless the last record or marker is less than a minute old.
+    fn record_unchanged(&mut self, timestamp: i64) {
+        if !unchanged_marker_due(self.last_written_at, timestamp, self.timestamp_unit) {
+            return;
+        }
+        self.last_written_at = Some(timestamp);
+        let mut fields = self.leading_fields();
+        fields.push(("unchanged", "true".to_string()));
+        self.write(timestamp, &build_record(timestamp, &fields));
+    }
+
+    /// Saves a record marking a fetch that was cancelled at the tick deadline,
+    /// e.g. `{"time": 1700000000, "timeout": true, "response": null}`.
+    pub fn record_timeout(&mut self) {
+        if self.is_paused() {
+            return;
+        }
+        if self.warmup_remaining > 0 {
+            return;
+        }
+        let timestamp = self.now();
+        let mut fields = self.leading_fields();
+        fields.push(("timeout", "true".to_string()));
+        fields.push(("response", "null".to_string()));
+        self.write(timestamp, &build_record(timestamp, &fields));
+    }
+
+    /// Saves an order book maintained locally, e.g. from a diff depth stream, as a `"book"` record.
+    ///
+    /// The book is stored truncated to `store_depth` if set, and is published like a fetched one.
+    ///
+    /// # Arguments
+    ///
+    /// * `book` - The current normalized order book.
+    pub fn record_book(&mut self, book: &OrderBook) {
+        if self.is_paused() {
+            return;
+        }
+        if self.warmup_remaining > 0 {
+            self.warmup_remaining -= 1;
+            return;
+        }
+        self.check_sequence(book);
+        let timestamp = self.now();
+        let mut fields = self.leading_fields();
+        let stored = self.store_depth.map_or_else(|| book.clone(), |depth| book.truncated(depth));
+        fields.push(("book", serde_json::to_string(&stored).expect("Order book is serializable")));
+        let written = self.write(timestamp, &build_record(timestamp, &fields));
+        if self.save_csv(timestamp, book) && written {
+            self.count_write();
+        }
+
+        if let Some(latest_books) = &self.latest_books {
+            latest_books.write().unwrap().insert(self.book_key(), book.clone());
+        }
+        if self.snapshots.receiver_count() > 0 {
+            let _ = self.snapshots.send(Arc::new(Snapshot {
+                time: timestamp,
+                exchange: self.api.name().to_string(),
+                symbol: self.ticker.to_string(),
+                book: book.clone(),
+            }));
+        }
+        self.last_book = Some(book.clone());
+    }
+
+    /// Returns the fields every record starts with after its `"time"`: the next `"seq"` number
+    /// if `sequence_numbers` is enabled and it can be persisted, and the `"depth"` of a recorder
+    /// of an extra depth.
+    fn leading_fields(&mut self) -> Vec<(&'static str, String)> {
+        let mut fields = Vec::new();
+        if let Some(sequence) = &mut self.sequence {
+            // A record is worth more than its number, so it is written without one if need be
+            match sequence.next() {
+                Ok(seq) => fields.push(("seq", seq.to_string())),
+                Err(e) => error!(
+                    exchange = self.api.name(), ticker = %self.ticker,
+                    "Cannot persist sequence number, writing record without it: {}", e
+                ),
+            }
+        }
+        if let Some(depth) = self.extra_depth {
+            fields.push(("depth", depth.to_string()));
+        }
+        fields
+    }
+
+    /// Compares the exchange sequence number of `book`, if it has one, to that of the previous book.
+    ///
+    /// A sequence that went backwards is warned about. One that skipped ahead is only logged at
+    /// debug level: the exchanges number every book change, so polled snapshots and partial
+    /// streams normally skip, while the diff depth stream detects its own gaps from the update ids.
+    fn check_sequence(&mut self, book: &OrderBook) {
+        let Some(sequence) = book.sequence else {
+            return;
+        };
+        match self.last_sequence.map(|last| sequence_step(last, sequence)) {
+            Some(SequenceStep::Backwards) => warn!(
+                "Sequence of {} on {} went backwards from {} to {}",
+                self.ticker, self.api.name(), self.last_sequence.unwrap_or_default(), sequence
+            ),
+            Some(SequenceStep::Skipped(missed)) => debug!(
+                "Sequence of {} on {} skipped {} numbers to {}", self.ticker, self.api.name(), missed, sequence
+            ),
+            _ => {}
+        }
+        self.last_sequence = Some(sequence);
+    }
+
+    /// Returns whether the book of a response is stored, used or someone is listening.
+    fn needs_book(&self) -> bool {
+        self.store_depth.is_some() || self.parse_always || self.snapshots.receiver_count() > 0
+    }
+
+    /// Normalizes a response, if its book is needed.
+    fn parse(&self, response_text: &str) -> Option<OrderBook> {
+        if !self.needs_book() {
+            return None;
+        }
+        match self.api.parse_order_book(response_text) {
+            Ok(book) => Some(self.shape(book)),
+            Err(error) => {
+                warn!(exchange = self.api.name(), ticker = %self.ticker, "Error parsing order book: {:?}", error);
+                None
+            }
+        }
+    }
+
+    /// Shapes a parsed book to the `depth_policy` and notes the depth it was requested at.
+    fn shape(&self, book: OrderBook) -> OrderBook {
+        match self.depth_policy {
+            DepthPolicy::Keep => book.with_requested_depth(self.depth),
+            _ => book.without_padding(self.policy_depth).with_requested_depth(self.depth),
+        }
+    }
+
+    /// Builds and writes the record of a response and its parsed book.
+    ///
+    /// # Returns
+    ///
+    /// The book, and whether the record was written, see `write`.
+    fn store(
+        &mut self,
+        timestamp: i64,
+        response_text: &str,
+        book: Option<OrderBook>,
+        flags: Vec<(&'static str, String)>,
+    ) -> (Option<OrderBook>, bool) {
+        let mut fields = self.leading_fields();
+        fields.extend(flags);
+        match (self.store_depth, &book) {
+            (depth, Some(book)) if self.delta_snapshot_interval_secs.is_some() => {
+                let stored = depth.map_or_else(|| book.clone(), |depth| book.truncated(depth));
+                fields.push(self.snapshot_or_delta(timestamp, stored));
+                if self.store_full_response {
+                    fields.push(("response", response_text.to_string()));
+                }
+            }
+            (Some(depth), Some(book)) => {
+                let stored = match self.depth_policy {
+                    DepthPolicy::Pad => book.to_padded_json(depth).to_string(),
+                    _ => serde_json::to_string(&book.truncated(depth)).expect("Order book is serializable"),
+                };
+                fields.push(("book", stored));
+                if self.store_full_response {
+                    fields.push(("response", response_text.to_string()));
+                }
+            }
+            // Without a parsed book the raw response is kept so no data is lost
+            _ => {
+                // The next book can no longer be expressed as a delta of the stored one
+                self.delta_base = None;
+                fields.push(("response", response_text.to_string()));
+            }
+        }
+        let json_data = build_record(timestamp, &fields);
+
+        let written = self.write(timestamp, &json_data);
+        (book, written)
+    }
+
+    /// Writes the levels of `book`, truncated to `store_depth` if set, as rows of the CSV data file.
+    ///
+    /// Does nothing unless the `csv` output format is selected and files are written.
+    ///
+    /// # Returns
+    ///
+    /// `false` if the rows could not be written, `true` otherwise.
+    fn save_csv(&mut self, timestamp: i64, book: &OrderBook) -> bool {
+        if !self.csv || !self.write_files {
+            return true;
+        }
+        let stored = self.store_depth.map_or_else(|| book.clone(), |depth| book.truncated(depth));
+        // The rows of a book are one record, so rotation never splits them
+        match self.file.write_rows(timestamp, &csv_rows(timestamp, &stored)) {
+            Ok(()) => true,
+            Err(e) => {
+                self.write_failed(e);
+                false
+            }
+        }
+    }
+
+    /// Writes a record to the JSON data files, unless disabled, and publishes it to every sink.
+    ///
+    /// # Returns
+    ///
+    /// `false` if the record could not be written to the data files, or, without data files, was
+    /// taken by no sink. `true` otherwise, including for CSV data files, written by `save_csv`.
+    fn write(&mut self, timestamp: i64, record: &str) -> bool {
+        let mut written = true;
+        if self.write_files && !self.csv {
+            let result = match &self.snapshot_files {
+                Some(snapshot_files) => snapshot_files.write(self.clock.now_millis(), record).map(|_| ()),
+                None => self.file.write(timestamp, record),
+            };
+            if let Err(e) = result {
+                self.write_failed(e);
+                written = false;
+            }
+        }
+        let symbol = self.ticker.to_string();
+        let mut published = false;
+        for sink in &self.sinks {
+            match sink.publish(self.api.name(), &symbol, record) {
+                Ok(()) => published = true,
+                Err(e) => warn!(exchange = self.api.name(), ticker = %self.ticker, "Cannot publish record, dropping it: {}", e),
+            }
+        }
+        // Without data files, the record is only kept if a sink took it
+        written && (self.write_files || published)
+    }
+
+    /// Logs a failed data file write. The record is dropped and the worker keeps collecting, so
+    /// a transient failure, e.g. a full disk, only loses the records written while it lasts.
+    fn write_failed(&self, error: io::Error) {
+        error!(exchange = self.api.name(), ticker = %self.ticker, "Cannot write record, dropping it: {}", error);
+    }
+
+    /// Returns the field storing `book` in delta mode: a full `"snapshot"` when one is due, else a `"delta"`.
+    fn snapshot_or_delta(&mut self, timestamp: i64, book: OrderBook) -> (&'static str, String) {
+        let interval = self.delta_snapshot_interval_secs.unwrap_or(0) as i64 * self.timestamp_unit.per_second();
+        let (snapshot_time, name, stored) = match &self.delta_base {
+            Some((snapshot_time, previous)) if timestamp - snapshot_time < interval => {
+                (*snapshot_time, "delta", previous.diff(&book))
+            }
+            _ => (timestamp, "snapshot", book.clone()),
+        };
+        self.delta_base = Some((snapshot_time, book));
+        (name, serde_json::to_string(&stored).expect("Order book is serializable"))
+    }
+
+    /// Closes the current data files and withdraws the ticker's latest book.
+    pub fn close(&mut self) {
+        if let Some((curve_file, _)) = &mut self.depth_curve {
+            if let Err(e) = curve_file.close() {
+                error!("Cannot close depth curve file of {}: {}", self.ticker, e);
+            }
+        }
+        if let Some(latest_books) = &self.latest_books {
+            latest_books.write().unwrap().remove(&self.book_key());
+        }
+        if let Err(e) = self.file.close() {
+            error!("Cannot close data file of {}: {}", self.ticker, e);
+        }
+        if let Some(Err(e)) = self.sequence.as_mut().map(SequenceFile::close) {
+            error!("Cannot store sequence number of {}: {}", self.ticker, e);
+        }
+    }
+}
+
+/// How the sequence number of a book moved from that of the previous book.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SequenceStep {
+    /// The sequence is the next one, or the same for a repeated snapshot.
+    Next,
+    /// The sequence skipped the given number of sequence numbers.
+    Skipped(u64),
+    /// The sequence is lower than the previous one.
+    Backwards,
+}
+
+/// Classifies the move of the sequence number from `previous` to `next`.
+fn sequence_step(previous: u64, next: u64) -> SequenceStep {
+    match next.checked_sub(previous) {
+        None => SequenceStep::Backwards,
+        Some(0 | 1) => SequenceStep::Next,
+        Some(step) => SequenceStep::Skipped(step - 1),
+    }
+}
+
+/// Returns whether an `"unchanged"` marker is due at `timestamp`, given the time of the last written line.
+fn unchanged_marker_due(last_written_at: Option<i64>, timestamp: i64, unit: TimestampUnit) -> bool {
+    last_written_at.is_none_or(|last| timestamp - last >= UNCHANGED_MARKER_INTERVAL_SECS * unit.per_second())
+}
+
+/// Opens a ticker's `spread_bps.csv` for appending, writing the column header if the file is new.
+fn open_spread_file(dir: &str) -> io::Result<File> {
+    open_csv_file(&format!("{}/spread_bps.csv", dir), "time,spread_bps")
+}
+
+/// Opens the CSV file at `path` for appending, writing `header` if the file is new.
+fn open_csv_file(path: &str, header: &str) -> io::Result<File> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    if file.metadata()?.len() == 0 {
+        writeln!(file, "{}", header)?;
+    }
+    Ok(file)
+}
+
+/// The column header of the `spread/{ticker}.csv` files.
+const TOP_OF_BOOK_HEADER: &str = "time,best_bid,best_ask,mid,spread_bps";
+
+/// Builds the top-of-book row of a book, e.g. `1700000000,99.95,100.05,100,10.0000`.
+///
+/// The values that need a missing side of a one-sided book are left empty.
+fn top_of_book_row(timestamp: i64, book: &OrderBook) -> String {
+    format!(
+        "{},{},{},{},{}",
+        timestamp,
+        book.bids.first().map(|(price, _)| price.to_string()).unwrap_or_default(),
+        book.asks.first().map(|(price, _)| price.to_string()).unwrap_or_default(),
+        book.mid_price().map(|mid| mid.to_string()).unwrap_or_default(),
+        book.spread_bps().map(|spread| format!("{:.4}", spread)).unwrap_or_default(),
+    )
+}
+
+/// Builds the CSV rows of a book, e.g. `1700000000,bid,0,42000.5,0.25`, bids first.
+///
+/// `level` counts from 0 at the best price of each side.
+fn csv_rows(timestamp: i64, book: &OrderBook) -> Vec<String> {
+    let bids = book.bids.iter().enumerate().map(|(level, level_data)| ("bid", level, level_data));
+    let asks = book.asks.iter().enumerate().map(|(level, level_data)| ("ask", level, level_data));
+    bids.chain(asks)
+        .map(|(side, level, (price, qty))| format!("{},{},{},{},{}", timestamp, side, level, price, qty))
+        .collect()
+}
+
+/// Builds the metadata record written at the top of each data file, e.g.
+/// `{"meta": {"exchange": "BINANCE", "symbol": "BTC_USDT", "depth": 10, "interval": 1, "format_version": 1}}`.
+///
+/// # Arguments
+///
+/// * `exchange` - The name of the exchange.
+/// * `symbol` - The ticker symbol in "BASE_QUOTE" form.
+/// * `depth` - The number of levels stored per side, if the file holds order books.
+/// * `interval` - The fetch interval in seconds, or the cron expression of the schedule.
+pub fn header_record(exchange: &str, symbol: &str, depth: Option<usize>, interval: serde_json::Value) -> String {
+    serde_json::json!({
+        "meta": {
+            "exchange": exchange,
+            "symbol": symbol,
+            "depth": depth,
+            "interval": interval,
+            "format_version": FORMAT_VERSION,
+        }
+    }).to_string()
+}
+
+/// Wraps a raw exchange response into a record with the collection timestamp.
+pub fn wrap_response(timestamp: i64, response_text: &str) -> String {
+    build_record(timestamp, &[("response", response_text.to_string())])
+}
+
+/// Builds a JSON record from the collection timestamp and already-serialized JSON field values.
+///
+/// The record starts with its `"time"` and the `"v"` of `FORMAT_VERSION`. The values are embedded
+/// verbatim, so a raw exchange response is stored without re-serializing it.
+fn build_record(timestamp: i64, fields: &[(&str, String)]) -> String {
+    let mut record = format!(r#"{{"time": {}, "v": {}"#, timestamp, FORMAT_VERSION);
+    for (name, value) in fields {
+        write!(record, r#", "{}": {}"#, name, value).unwrap();
+    }
+    record.push('}');
+    record
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn test_wrap_response() {
+        assert_eq!(wrap_response(5, r#"{"raw":true}"#), r#"{"time": 5, "v": 1, "response": {"raw":true}}"#);
+    }
+
+    #[test]
+    fn test_open_spread_file_writes_header_once() {
+        let dir = std::env::temp_dir().join("cex_recorder_test_spread");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let dir_str = dir.to_str().unwrap();
+
+        writeln!(open_spread_file(dir_str).unwrap(), "5,1.2500").unwrap();
+        writeln!(open_spread_file(dir_str).unwrap(), "6,1.5000").unwrap();
+        assert_eq!(
+            std::fs::read_to_string(dir.join("spread_bps.csv")).unwrap(),
+            "time,spread_bps\n5,1.2500\n6,1.5000\n"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_top_of_book_row() {
+        let book = OrderBook {
+            bids: vec![(99.95, 1.0)],
+            asks: vec![(100.05, 2.0)],
+            exchange_ts: None,
+            sequence: None,
+            requested_depth: None,
+        };
+        assert_eq!(top_of_book_row(5, &book), "5,99.95,100.05,100,10.0000");
+
+        let bids_only = OrderBook {
+            bids: vec![(99.95, 1.0)],
+            asks: vec![],
+            exchange_ts: None,
+            sequence: None,
+            requested_depth: None,
+        };
+        assert_eq!(top_of_book_row(6, &bids_only), "6,99.95,,,");
+        let asks_only = OrderBook {
+            bids: vec![],
+            asks: vec![(100.05, 2.0)],
+            exchange_ts: None,
+            sequence: None,
+            requested_depth: None,
+        };
+        assert_eq!(top_of_book_row(7, &asks_only), "7,,100.05,,");
+        assert_eq!(top_of_book_row(8, &OrderBook::default()), "8,,,,");
+
+        let crossed = OrderBook {
+            bids: vec![(100.05, 1.0)],
+            asks: vec![(99.95, 2.0)],
+            exchange_ts: None,
+            sequence: None,
+            requested_depth: None,
+        };
+        assert_eq!(top_of_book_row(9, &crossed), "9,100.05,99.95,100,-10.0000");
+    }
+
+    #[test]
+    fn test_sequence_step() {
+        assert_eq!(sequence_step(7, 8), SequenceStep::Next);
+        assert_eq!(sequence_step(7, 7), SequenceStep::Next);
+        assert_eq!(sequence_step(7, 10), SequenceStep::Skipped(2));
+        assert_eq!(sequence_step(7, 6), SequenceStep::Backwards);
+    }
+
+    #[test]
+    fn test_unchanged_marker_due_once_a_minute() {
+        assert!(unchanged_marker_due(None, 100, TimestampUnit::Seconds));
+        assert!(!unchanged_marker_due(Some(100), 159, TimestampUnit::Seconds));
+        assert!(unchanged_marker_due(Some(100), 160, TimestampUnit::Seconds));
+        assert!(!unchanged_marker_due(Some(100_000), 159_999, TimestampUnit::Millis));
+        assert!(unchanged_marker_due(Some(100_000), 160_000, TimestampUnit::Millis));
+    }
+
+    #[test]
+    fn test_csv_rows() {
+        let book = OrderBook {
+            bids: vec![(2.5, 1.0), (2.0, 3.0)],
+            asks: vec![(3.0, 0.25)],
+            exchange_ts: None,
+            sequence: None,
+            requested_depth: None,
+        };
+        assert_eq!(csv_rows(5, &book), vec!["5,bid,0,2.5,1", "5,bid,1,2,3", "5,ask,0,3,0.25"]);
+    }
+
+    #[test]
+    fn test_header_record() {
+        let header: Value = serde_json::from_str(&header_record("BINANCE", "BTC_USDT", Some(10), json!(1))).unwrap();
+        assert_eq!(header, json!({
+            "meta": {"exchange": "BINANCE", "symbol": "BTC_USDT", "depth": 10, "interval": 1, "format_version": 1}
+        }));
+    }
+
+    #[test]
+    fn test_build_record_with_book_and_sequence() {
+        let book = OrderBook {
+            bids: vec![(1.0, 2.0)],
+            asks: vec![(3.0, 4.0)],
+            exchange_ts: None,
+            sequence: None,
+            requested_depth: None,
+        };
+        let fields = vec![
+            ("seq", "9".to_string()),
+            ("book", serde_json::to_string(&book).unwrap()),
+        ];
+
+        let record: Value = serde_json::from_str(&build_record(5, &fields)).unwrap();
+        assert_eq!(record, json!({
+            "time": 5, "v": 1, "seq": 9,
+            "book": {"bids": [[1.0, 2.0]], "asks": [[3.0, 4.0]], "exchange_ts": null}
+        }));
+    }
+
+    #[test]
+    fn test_records_carry_version() {
+        let records = [
+            wrap_response(5, r#"{"bids":[["1.5","2"]],"asks":[]}"#),
+            build_record(5, &[("timeout", "true".to_string()), ("response", "null".to_string())]),
+            build_record(5, &[("unchanged", "true".to_string())]),
+        ];
+        for record in records {
+            let json: Value = serde_json::from_str(&record).unwrap();
+            assert_eq!(json["v"], json!(FORMAT_VERSION));
+            assert_eq!(json["time"], json!(5));
+            let round_tripped: Value = serde_json::from_str(&serde_json::to_string(&json).unwrap()).unwrap();
+            assert_eq!(round_tripped, json);
+        }
+    }
+}
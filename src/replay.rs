@@ -0,0 +1,240 @@
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use flate2::read::GzDecoder;
+use serde_json::Value;
+use crate::cex_api::CexApi;
+use crate::sink::Sink;
+use crate::storage::{date_dirs, decode_line};
+use tracing::{debug, info, warn};
+
+/// The outcome of a replay.
+#[derive(Debug, Default, PartialEq)]
+pub struct ReplayStats {
+    /// The data files read.
+    pub files: usize,
+    /// The records published to the sinks.
+    pub records: usize,
+    /// The lines left out besides file headers: undecodable or truncated lines, records without
+    /// a time, and responses the exchange's parser rejects.
+    pub skipped: usize,
+    /// The records a sink failed to take, counted once per sink.
+    pub failed: usize,
+}
+
+/// Returns the exchange and symbol of a ticker's data directory, e.g. `("BINANCE", "BTC_USDT")`
+/// for `data/BINANCE/BTC_USDT`, from its last two components.
+pub fn ticker_of_dir(dir: &Path) -> Option<(String, String)> {
+    let symbol = dir.file_name()?.to_str()?;
+    let exchange = dir.parent()?.file_name()?.to_str()?;
+    Some((exchange.to_string(), symbol.to_string()))
+}
+
+/// Re-publishes the records of a ticker's data directory to `sinks`, e.g. to backfill a new
+/// database from previously collected files.
+///
+/// The `.json` and rotated `.json.gz` files of the directory and of its date directories are
+/// read one at a time, ordered by the time of their first record, and the records of each file
+/// are published in time order. Compressed lines are decoded first, and file headers are left out.
+/// Each record is published exactly as the collector would have published it, through
+/// `Sink::replay`, so a full sink queue slows the replay instead of dropping records.
+///
+/// # Arguments
+///
+/// * `dir` - The ticker's data directory, e.g. `data/BINANCE/BTC_USDT`.
+/// * `exchange` - The exchange name passed to the sinks.
+/// * `symbol` - The ticker symbol passed to the sinks.
+/// * `api` - The exchange's API, if available, whose parser checks every raw `"response"`. A
+///   response it rejects, e.g. a recorded error page, is skipped.
+/// * `sinks` - The sinks to publish the records to.
+///
+/// # Errors
+///
+/// Returns an `io::Error` if the directory or one of its files cannot be read.
+pub fn replay(
+    dir: &Path,
+    exchange: &str,
+    symbol: &str,
+    api: Option<&dyn CexApi>,
+    sinks: &[Arc<dyn Sink>],
+) -> io::Result<ReplayStats> {
+    let mut files = Vec::new();
+    for file in data_files(dir)? {
+        files.push((first_time(&file)?, file));
+    }
+    files.sort();
+
+    let mut stats = ReplayStats::default();
+    for (_, file) in files {
+        info!("Replaying {}", file.display());
+        let mut records = Vec::new();
+        for line in open(&file)?.lines() {
+            let line = line?;
+            if line.starts_with(r#"{"meta""#) {
+                continue;
+            }
+            match replayable(&line, api) {
+                Some(record) => records.push(record),
+                None => stats.skipped += 1,
+            }
+        }
+        // The sort is stable, so records of the same time keep their order in the file
+        records.sort_by_key(|(time, _)| *time);
+        for (_, record) in &records {
+            for sink in sinks {
+                if let Err(e) = sink.replay(exchange, symbol, record) {
+                    warn!("Cannot replay record of {} on {}: {}", symbol, exchange, e);
+                    stats.failed += 1;
+                }
+            }
+        }
+        stats.files += 1;
+        stats.records += records.len();
+    }
+    Ok(stats)
+}
+
+/// Returns the data files of `dir` and of its date directories, in no particular order.
+fn data_files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for dir in std::iter::once(dir.to_path_buf()).chain(date_dirs(dir)) {
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            let name = path.to_string_lossy();
+            if path.is_file() && (name.ends_with(".json") || name.ends_with(".json.gz")) {
+                files.push(path);
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Opens a data file for reading by line, decompressing a `.gz` file.
+fn open(path: &Path) -> io::Result<Box<dyn BufRead>> {
+    let file = File::open(path)?;
+    let reader: Box<dyn Read> = match path.extension().is_some_and(|extension| extension == "gz") {
+        true => Box::new(GzDecoder::new(file)),
+        false => Box::new(file),
+    };
+    Ok(Box::new(BufReader::new(reader)))
+}
+
+/// Returns the time of the first record of a data file, or `i64::MAX` for a file without records.
+fn first_time(path: &Path) -> io::Result<i64> {
+    for line in open(path)?.lines() {
+        if let Some((time, _)) = replayable(&line?, None) {
+            return Ok(time);
+        }
+    }
+    Ok(i64::MAX)
+}
+
+/// Decodes a line of a data file into its record and time.
+///
+/// # Returns
+///
+/// The time and the decoded record, or `None` for a line that cannot be decoded or parsed,
+/// a record without a time, e.g. a header, or a raw response that `api` cannot parse.
+fn replayable(line: &str, api: Option<&dyn CexApi>) -> Option<(i64, String)> {
+    let record = decode_line(line).map_err(|e| debug!("Cannot decode line: {}", e)).ok()?;
+    let json: Value = serde_json::from_str(&record).map_err(|e| debug!("Cannot parse record: {}", e)).ok()?;
+    let time = json["time"].as_i64()?;
+    if let (Some(api), Some(response)) = (api, json.get("response").filter(|response| !response.is_null())) {
+        if let Err(e) = api.parse_order_book(&response.to_string()) {
+            warn!("Skipping record at {} with an unparseable response: {}", time, e);
+            return None;
+        }
+    }
+    Some((time, record))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+    use std::io::Write;
+    use std::sync::Mutex;
+    use crate::order_book::OrderBook;
+    use crate::storage::encode_line;
+    use crate::ticker::Ticker;
+
+    /// A sink collecting the records it receives.
+    #[derive(Default)]
+    struct CollectingSink(Mutex<Vec<String>>);
+
+    impl Sink for CollectingSink {
+        fn publish(&self, exchange: &str, symbol: &str, record: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+            self.0.lock().unwrap().push(format!("{} {} {}", exchange, symbol, record));
+            Ok(())
+        }
+    }
+
+    /// An exchange whose parser only accepts responses with `"ok": true`.
+    struct StubApi;
+
+    #[async_trait::async_trait]
+    impl CexApi for StubApi {
+        fn name(&self) -> &'static str {
+            "BINANCE"
+        }
+
+        async fn get_order_book(&self, _symbol: &Ticker, _depth: u32) -> Result<String, Box<dyn Error>> {
+            Err("stub".into())
+        }
+
+        fn parse_order_book(&self, raw: &str) -> Result<OrderBook, Box<dyn Error>> {
+            match serde_json::from_str::<Value>(raw)?["ok"].as_bool() {
+                Some(true) => Ok(OrderBook::default()),
+                _ => Err("not ok".into()),
+            }
+        }
+
+        fn get_order_book_interval(&self) -> u64 {
+            1
+        }
+    }
+
+    #[test]
+    fn test_ticker_of_dir() {
+        assert_eq!(
+            ticker_of_dir(Path::new("data/BINANCE/BTC_USDT")),
+            Some(("BINANCE".to_string(), "BTC_USDT".to_string()))
+        );
+        assert_eq!(ticker_of_dir(Path::new("BTC_USDT")), None);
+    }
+
+    #[test]
+    fn test_replay_in_time_order() {
+        let dir = std::env::temp_dir().join("cex_replay_test").join("BINANCE").join("BTC_USDT");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("2023-11-14")).unwrap();
+        // The later hour sorts first by name, and its records are out of order
+        fs::write(dir.join("3600-1.json"), "{\"time\": 3602, \"response\": {\"ok\":true}}\n{\"time\": 3601, \"response\": {\"ok\":true}}\n").unwrap();
+        fs::write(
+            dir.join("2023-11-14/00.json"),
+            format!(
+                "{{\"meta\": {{}}}}\n{}\n{{\"time\": 2, \"response\": {{\"ok\":false}}}}\n{{\"time\": 3, \"timeout\": true, \"response\": null}}\n{{\"time\": 4, \"resp",
+                encode_line("{\"time\": 1, \"response\": {\"ok\":true}}").unwrap()
+            ),
+        ).unwrap();
+        let mut gz = flate2::write::GzEncoder::new(File::create(dir.join("7200.json.gz")).unwrap(), flate2::Compression::default());
+        gz.write_all(b"{\"time\": 7200, \"book\": {}}\n").unwrap();
+        gz.finish().unwrap();
+        fs::write(dir.join(".seq"), "9").unwrap();
+
+        let sink = Arc::new(CollectingSink::default());
+        let sinks: Vec<Arc<dyn Sink>> = vec![sink.clone()];
+        let stats = replay(&dir, "BINANCE", "BTC_USDT", Some(&StubApi), &sinks).unwrap();
+
+        assert_eq!(stats, ReplayStats { files: 3, records: 5, skipped: 2, failed: 0 });
+        assert_eq!(*sink.0.lock().unwrap(), vec![
+            "BINANCE BTC_USDT {\"time\": 1, \"response\": {\"ok\":true}}",
+            "BINANCE BTC_USDT {\"time\": 3, \"timeout\": true, \"response\": null}",
+            "BINANCE BTC_USDT {\"time\": 3601, \"response\": {\"ok\":true}}",
+            "BINANCE BTC_USDT {\"time\": 3602, \"response\": {\"ok\":true}}",
+            "BINANCE BTC_USDT {\"time\": 7200, \"book\": {}}",
+        ]);
+        fs::remove_dir_all(std::env::temp_dir().join("cex_replay_test")).unwrap();
+    }
+}
@@ -0,0 +1,232 @@
+use chrono::{TimeZone, Utc};
+use std::str::FromStr;
+use crate::config::AdaptiveIntervalConfig;
+
+/// Determines when a worker should fetch the next order book snapshot.
+///
+/// A schedule is either a fixed interval, aligned to multiples of the interval since the
+/// unix epoch, or a cron expression evaluated in UTC.
+#[derive(Clone)]
+pub enum Schedule {
+    /// Fetch every `n` seconds, aligned to the interval boundary.
+    Interval(u64),
+    /// Fetch at the times described by a cron expression.
+    Cron(Box<cron::Schedule>),
+}
+
+impl Schedule {
+    /// Parses a cron expression into a `Schedule`.
+    ///
+    /// The expression uses the `cron` crate syntax, which includes a leading seconds field,
+    /// e.g. `"0 0 0,8,16 * * *"` for every day at 00:00, 08:00 and 16:00 UTC.
+    ///
+    /// # Errors
+    ///
+    /// Returns the parse error message if the expression is not a valid cron string.
+    pub fn cron(expression: &str) -> Result<Schedule, String> {
+        cron::Schedule::from_str(expression)
+            .map(|schedule| Schedule::Cron(Box::new(schedule)))
+            .map_err(|e| format!("Invalid cron expression '{}': {}", expression, e))
+    }
+
+    /// Returns a JSON description of the schedule: the interval in seconds, or the cron expression.
+    pub fn describe(&self) -> serde_json::Value {
+        match self {
+            Schedule::Interval(seconds) => (*seconds).into(),
+            Schedule::Cron(schedule) => schedule.source().into(),
+        }
+    }
+
+    /// Returns the time in milliseconds between the fetches following `now_millis`: the interval,
+    /// or the gap between the next two fire times of a cron schedule.
+    ///
+    /// # Returns
+    ///
+    /// The period, or `None` if a cron schedule has fewer than two upcoming fire times.
+    pub fn period_millis(&self, now_millis: i64) -> Option<u64> {
+        match self {
+            Schedule::Interval(seconds) => Some(seconds * 1000),
+            Schedule::Cron(schedule) => {
+                let now = Utc.timestamp_millis_opt(now_millis).single()?;
+                let mut fires = schedule.after(&now);
+                let (first, second) = (fires.next()?, fires.next()?);
+                Some((second.timestamp_millis() - first.timestamp_millis()).max(0) as u64)
+            }
+        }
+    }
+
+    /// Returns the next fetch time in unix milliseconds, anchored to the boundaries of the schedule.
+    ///
+    /// The fetch time is the first boundary after `now_millis`, or at it for an interval, that is
+    /// later than `last_millis`, the previous fetch time. A tick is therefore never fetched twice,
+    /// however quickly the previous fetch returned, and the ticks a slow fetch ran past are skipped
+    /// instead of caught up, so every fetch lands on a boundary.
+    ///
+    /// # Arguments
+    ///
+    /// * `now_millis` - The current unix time in milliseconds.
+    /// * `last_millis` - The previous fetch time in unix milliseconds, if there was one.
+    ///
+    /// # Returns
+    ///
+    /// The next fetch time, or `None` if a cron schedule has no upcoming fire time.
+    pub fn next_fetch_millis(&self, now_millis: i64, last_millis: Option<i64>) -> Option<i64> {
+        let next = now_millis + self.delay_millis(now_millis)? as i64;
+        match (self, last_millis) {
+            (Schedule::Interval(seconds), Some(last)) if next <= last => Some(last + *seconds as i64 * 1000),
+            (Schedule::Cron(schedule), Some(last)) if next <= last => {
+                let last = Utc.timestamp_millis_opt(last).single()?;
+                Some(schedule.after(&last).next()?.timestamp_millis())
+            }
+            _ => Some(next),
+        }
+    }
+
+    /// Returns the number of milliseconds to wait from `now_millis` until the next fetch.
+    ///
+    /// # Arguments
+    ///
+    /// * `now_millis` - The current unix time in milliseconds.
+    ///
+    /// # Returns
+    ///
+    /// The delay in milliseconds, or `None` if a cron schedule has no upcoming fire time.
+    pub fn delay_millis(&self, now_millis: i64) -> Option<u64> {
+        match self {
+            Schedule::Interval(seconds) => {
+                let interval_in_milliseconds = seconds * 1000;
+                let remainder = now_millis as u64 % interval_in_milliseconds;
+                if remainder > 0 {
+                    Some(interval_in_milliseconds - remainder)
+                } else {
+                    Some(0)
+                }
+            }
+            Schedule::Cron(schedule) => {
+                let now = Utc.timestamp_millis_opt(now_millis).single()?;
+                let next = schedule.after(&now).next()?;
+                Some((next.timestamp_millis() - now_millis).max(0) as u64)
+            }
+        }
+    }
+}
+
+/// Scales the interval between fetches with the recent midprice change.
+///
+/// After each successful fetch the relative midprice change since the previous fetch is
+/// measured in basis points. A change of `threshold_bps` or more targets the minimum interval,
+/// no change targets the maximum interval, and changes in between target a linearly interpolated
+/// interval. The interval moves halfway towards its target per fetch, so a single outlier does not
+/// swing it from one bound to the other. Fetches without a midprice keep the current interval.
+pub struct AdaptiveInterval {
+    config: AdaptiveIntervalConfig,
+    interval_ms: u64,
+    last_mid: Option<f64>,
+}
+
+impl AdaptiveInterval {
+    /// Creates a new `AdaptiveInterval` starting at the maximum interval.
+    pub fn new(config: &AdaptiveIntervalConfig) -> AdaptiveInterval {
+        let max_interval_ms = config.max_interval_ms.max(config.min_interval_ms);
+        AdaptiveInterval {
+            config: config.clone(),
+            interval_ms: max_interval_ms,
+            last_mid: None,
+        }
+    }
+
+    /// Records the midprice of the latest fetch and returns the delay in milliseconds until the next one.
+    ///
+    /// # Arguments
+    ///
+    /// * `mid` - The midprice of the latest order book, or `None` if the fetch or parse failed.
+    pub fn next_delay_millis(&mut self, mid: Option<f64>) -> u64 {
+        let min = self.config.min_interval_ms.min(self.config.max_interval_ms);
+        let max = self.config.max_interval_ms.max(self.config.min_interval_ms);
+        if let Some(mid) = mid {
+            if let Some(last_mid) = self.last_mid.filter(|last_mid| *last_mid > 0.0) {
+                let change_bps = ((mid - last_mid) / last_mid).abs() * 10_000.0;
+                let volatility = if self.config.threshold_bps > 0.0 {
+                    (change_bps / self.config.threshold_bps).min(1.0)
+                } else {
+                    1.0
+                };
+                let target = max as f64 - (max - min) as f64 * volatility;
+                self.interval_ms = ((self.interval_ms as f64 + target) / 2.0).round() as u64;
+            }
+            self.last_mid = Some(mid);
+        }
+        self.interval_ms.clamp(min, max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AdaptiveInterval, Schedule};
+    use crate::config::AdaptiveIntervalConfig;
+
+    #[test]
+    fn test_interval_delay_aligns_to_boundary() {
+        let schedule = Schedule::Interval(2);
+        assert_eq!(schedule.delay_millis(10_000), Some(0));
+        assert_eq!(schedule.delay_millis(10_500), Some(1_500));
+        assert_eq!(schedule.delay_millis(11_999), Some(1));
+    }
+
+    #[test]
+    fn test_next_fetch_never_repeats_a_tick() {
+        let schedule = Schedule::Interval(2);
+        assert_eq!(schedule.next_fetch_millis(10_500, None), Some(12_000));
+        // A fetch returning within the millisecond of its tick waits for the next tick
+        assert_eq!(schedule.next_fetch_millis(12_000, Some(12_000)), Some(14_000));
+        assert_eq!(schedule.next_fetch_millis(12_000, Some(10_000)), Some(12_000));
+        // A fetch running past ticks skips them and stays on the boundaries
+        assert_eq!(schedule.next_fetch_millis(16_700, Some(12_000)), Some(18_000));
+
+        let cron = Schedule::cron("0 0 * * * *").unwrap();
+        // 2024-01-15T07:30:00Z, with a clock stepped back from the 08:00:00Z fetch
+        assert_eq!(cron.next_fetch_millis(1_705_303_800_000, Some(1_705_305_600_000)), Some(1_705_309_200_000));
+    }
+
+    #[test]
+    fn test_cron_delay_until_next_fire_time() {
+        let schedule = Schedule::cron("0 0 0,8,16 * * *").unwrap();
+        // 2024-01-15T07:30:00Z -> next fire at 08:00:00Z
+        let now = 1_705_303_800_000;
+        assert_eq!(schedule.delay_millis(now), Some(30 * 60 * 1000));
+        // 2024-01-15T16:00:00Z exactly -> next fire is 2024-01-16T00:00:00Z
+        let now = 1_705_334_400_000;
+        assert_eq!(schedule.delay_millis(now), Some(8 * 3600 * 1000));
+    }
+
+    #[test]
+    fn test_period_millis() {
+        assert_eq!(Schedule::Interval(5).period_millis(0), Some(5000));
+        let schedule = Schedule::cron("0 0 0,8,16 * * *").unwrap();
+        // 2024-01-15T07:30:00Z -> fires at 08:00:00Z and 16:00:00Z
+        assert_eq!(schedule.period_millis(1_705_303_800_000), Some(8 * 3600 * 1000));
+    }
+
+    #[test]
+    fn test_cron_invalid_expression() {
+        assert!(Schedule::cron("not a cron").is_err());
+    }
+
+    #[test]
+    fn test_describe() {
+        assert_eq!(Schedule::Interval(5).describe(), serde_json::json!(5));
+        assert_eq!(Schedule::cron("0 0 * * * *").unwrap().describe(), serde_json::json!("0 0 * * * *"));
+    }
+
+    #[test]
+    fn test_adaptive_interval_follows_volatility() {
+        let config = AdaptiveIntervalConfig { min_interval_ms: 100, max_interval_ms: 1000, threshold_bps: 10.0 };
+        let mut adaptive = AdaptiveInterval::new(&config);
+        assert_eq!(adaptive.next_delay_millis(Some(100.0)), 1000);
+        // A 10 bps move targets the minimum, moving halfway there
+        assert_eq!(adaptive.next_delay_millis(Some(100.1)), 550);
+        assert_eq!(adaptive.next_delay_millis(None), 550);
+        // No move targets the maximum
+        assert_eq!(adaptive.next_delay_millis(Some(100.1)), 775);
+    }
+}
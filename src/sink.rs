@@ -0,0 +1,90 @@
+use std::error::Error;
+use std::sync::Arc;
+use crate::config::{Config, Output};
+
+/// A destination that receives every saved record, next to or instead of the data files.
+///
+/// Implementations must not block collection: records that cannot be delivered in time are
+/// dropped and reported as an error rather than applying back pressure to the workers. The
+/// recorder logs the errors, so implementations do not log them themselves.
+pub trait Sink: Send + Sync {
+    /// Publishes a record.
+    ///
+    /// # Arguments
+    ///
+    /// * `exchange` - The name of the exchange the record was collected from.
+    /// * `symbol` - The ticker symbol in "BASE_QUOTE" form.
+    /// * `record` - The record, exactly as written to the data files.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the record was dropped, e.g. because the sink's queue is full.
+    fn publish(&self, exchange: &str, symbol: &str, record: &str) -> Result<(), Box<dyn Error + Send + Sync>>;
+
+    /// Publishes a record read back from the data files by a replay.
+    ///
+    /// A replay reads records much faster than they were collected, so sinks with a bounded queue
+    /// should wait for room instead of dropping the record. Defaults to `publish`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the record was dropped.
+    fn replay(&self, exchange: &str, symbol: &str, record: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.publish(exchange, symbol, record)
+    }
+}
+
+/// A sink printing every record to stdout, one per line, e.g. for `--once --stdout`.
+pub struct StdoutSink;
+
+impl Sink for StdoutSink {
+    fn publish(&self, _exchange: &str, _symbol: &str, record: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        println!("{}", record);
+        Ok(())
+    }
+}
+
+/// Creates the sinks configured in `config`: Kafka, and the `sqlite` and `stdout` outputs.
+///
+/// The `files` output is written by the workers themselves. Every record goes to each sink in
+/// turn, and sinks do not block, so a failing sink only loses its own records. Sinks that fail
+/// to start are logged and left out, so collection continues with the others.
+pub fn from_config(config: &Config) -> Vec<Arc<dyn Sink>> {
+    #[allow(unused_mut)]
+    let mut sinks: Vec<Arc<dyn Sink>> = Vec::new();
+    if let Some(kafka) = &config.kafka {
+        #[cfg(feature = "kafka")]
+        match crate::kafka_sink::KafkaSink::new(kafka) {
+            Ok(sink) => sinks.push(Arc::new(sink)),
+            Err(e) => tracing::error!("Failed to create Kafka producer for {}: {}", kafka.brokers, e),
+        }
+        #[cfg(not(feature = "kafka"))]
+        tracing::warn!("Kafka topic {} is configured but this binary was built without `--features kafka`", kafka.topic);
+    }
+    if config.outputs_to(Output::Sqlite) {
+        let db_path = config.db_path();
+        #[cfg(feature = "sqlite")]
+        match crate::sqlite_sink::SqliteSink::open(&db_path) {
+            Ok(sink) => sinks.push(Arc::new(sink)),
+            Err(e) => tracing::error!("Failed to open SQLite database {}: {}", db_path, e),
+        }
+        #[cfg(not(feature = "sqlite"))]
+        tracing::warn!("SQLite output to {} is configured but this binary was built without `--features sqlite`", db_path);
+    }
+    if config.outputs_to(Output::Stdout) {
+        sinks.push(Arc::new(StdoutSink));
+    }
+    sinks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_config_creates_output_sinks() {
+        let config: Config = serde_json::from_str(r#"{"cex": "BINANCE", "tickers": [], "output": ["files", "stdout"]}"#).unwrap();
+        assert_eq!(from_config(&config).len(), 1);
+        assert!(from_config(&Config::default()).is_empty());
+    }
+}
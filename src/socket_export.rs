@@ -0,0 +1,123 @@
+use std::fs;
+use std::io;
+use std::os::unix::net::UnixListener as StdUnixListener;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::broadcast::{self, error::RecvError};
+use crate::order_book::Snapshot;
+use tracing::{info, warn};
+
+/// Starts a Unix domain socket server that streams collected snapshots to its clients as NDJSON.
+///
+/// Every connected client receives each snapshot sent on `sender` after it connected, one JSON
+/// object per line. Clients that fall behind the channel capacity are disconnected so that a
+/// slow consumer never blocks collection.
+///
+/// # Arguments
+///
+/// * `path` - The filesystem path of the socket. A stale socket file at this path is replaced.
+/// * `sender` - The broadcast channel the workers publish normalized snapshots on.
+///
+/// # Errors
+///
+/// Returns an `io::Error` if the socket cannot be bound or its runtime cannot be created.
+pub fn spawn(path: &str, sender: broadcast::Sender<Arc<Snapshot>>) -> io::Result<thread::JoinHandle<()>> {
+    if Path::new(path).exists() {
+        fs::remove_file(path)?;
+    }
+    let listener = StdUnixListener::bind(path)?;
+    listener.set_nonblocking(true)?;
+    let runtime = tokio::runtime::Runtime::new()?;
+    let listener = {
+        let _guard = runtime.enter();
+        UnixListener::from_std(listener)?
+    };
+    info!("Streaming snapshots on unix socket {}", path);
+
+    Ok(thread::spawn(move || {
+        runtime.block_on(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        tokio::spawn(serve_client(stream, sender.subscribe()));
+                    }
+                    Err(e) => warn!("Error accepting socket client: {}", e),
+                }
+            }
+        });
+    }))
+}
+
+/// Writes snapshots to a single client until it disconnects or falls behind.
+async fn serve_client(mut stream: UnixStream, mut receiver: broadcast::Receiver<Arc<Snapshot>>) {
+    loop {
+        match receiver.recv().await {
+            Ok(snapshot) => {
+                let mut line = serde_json::to_string(&*snapshot).expect("Snapshot is serializable");
+                line.push('\n');
+                if stream.write_all(line.as_bytes()).await.is_err() {
+                    break;
+                }
+            }
+            Err(RecvError::Lagged(skipped)) => {
+                warn!("Dropping slow socket client ({} snapshots behind)", skipped);
+                break;
+            }
+            Err(RecvError::Closed) => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::order_book::OrderBook;
+    use std::io::{BufRead, BufReader};
+    use std::os::unix::net::UnixStream as StdUnixStream;
+    use std::time::Duration;
+
+    #[test]
+    fn test_client_receives_ndjson_snapshots() {
+        let path = std::env::temp_dir().join("cex_socket_export_test.sock");
+        let path = path.to_str().unwrap();
+        let (sender, _) = broadcast::channel(16);
+        spawn(path, sender.clone()).unwrap();
+
+        let client = StdUnixStream::connect(path).unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        // Wait for the server to subscribe the client before publishing
+        while sender.receiver_count() == 0 {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let snapshot = Snapshot {
+            time: 1,
+            exchange: "BINANCE".to_string(),
+            symbol: "BTC_USDT".to_string(),
+            book: OrderBook {
+                bids: vec![(1.0, 2.0)],
+                asks: vec![(3.0, 4.0)],
+                exchange_ts: None,
+                sequence: None,
+                requested_depth: None,
+            },
+        };
+        sender.send(Arc::new(snapshot.clone())).unwrap();
+
+        let mut line = String::new();
+        BufReader::new(client).read_line(&mut line).unwrap();
+        let received: Snapshot = serde_json::from_str(&line).unwrap();
+        assert_eq!(received, snapshot);
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_unbindable_path_is_an_error() {
+        let path = std::env::temp_dir().join("cex_socket_export_missing_dir/test.sock");
+        let (sender, _) = broadcast::channel(16);
+        assert!(spawn(path.to_str().unwrap(), sender).is_err());
+    }
+}
@@ -0,0 +1,169 @@
+use std::error::Error;
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::thread::{self, JoinHandle};
+use rusqlite::Connection;
+use serde::Deserialize;
+use crate::sink::Sink;
+use crate::storage::create_directory;
+use tracing::error;
+
+/// The number of records buffered for the writer thread before new records are dropped.
+const QUEUE_CAPACITY: usize = 10_000;
+
+/// Stores records in the `order_book(exchange, symbol, time, raw_json)` table of a SQLite database.
+///
+/// Records are queued to a single writer thread, which serializes the inserts of all workers and
+/// commits whatever is queued in one transaction. When the queue is full, the record is dropped
+/// and the error returned.
+/// The queue is flushed when the sink is dropped.
+pub struct SqliteSink {
+    sender: Option<SyncSender<Row>>,
+    writer: Option<JoinHandle<()>>,
+}
+
+/// A record queued for insertion.
+struct Row {
+    exchange: String,
+    symbol: String,
+    time: i64,
+    raw_json: String,
+}
+
+/// The part of a record stored in its own column besides the raw JSON.
+#[derive(Deserialize)]
+struct RecordTime {
+    time: i64,
+}
+
+impl SqliteSink {
+    /// Opens or creates the database at `path` and its directory, creating the `order_book` table and its index if needed.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `rusqlite::Error` if the database cannot be opened or the schema cannot be created.
+    pub fn open(path: &str) -> rusqlite::Result<SqliteSink> {
+        if let Some(parent) = Path::new(path).parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            create_directory(&parent.to_string_lossy());
+        }
+        let connection = Connection::open(path)?;
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS order_book (
+                exchange TEXT NOT NULL,
+                symbol TEXT NOT NULL,
+                time INTEGER NOT NULL,
+                raw_json TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS order_book_exchange_symbol_time ON order_book (exchange, symbol, time);",
+        )?;
+        let (sender, receiver) = mpsc::sync_channel(QUEUE_CAPACITY);
+        let writer = thread::spawn(move || write_rows(connection, receiver));
+        Ok(SqliteSink { sender: Some(sender), writer: Some(writer) })
+    }
+}
+
+impl SqliteSink {
+    /// Builds the row of a record.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the record has no time.
+    fn row(exchange: &str, symbol: &str, record: &str) -> Result<Row, Box<dyn Error + Send + Sync>> {
+        let time = serde_json::from_str::<RecordTime>(record)
+            .map_err(|e| format!("SQLite: record without a time: {}", e))?
+            .time;
+        Ok(Row { exchange: exchange.to_string(), symbol: symbol.to_string(), time, raw_json: record.to_string() })
+    }
+}
+
+impl Sink for SqliteSink {
+    fn publish(&self, exchange: &str, symbol: &str, record: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let row = SqliteSink::row(exchange, symbol, record)?;
+        match self.sender.as_ref().map(|sender| sender.try_send(row)) {
+            Some(Ok(())) => Ok(()),
+            Some(Err(TrySendError::Full(_))) => Err("SQLite: queue is full".into()),
+            Some(Err(TrySendError::Disconnected(_))) | None => Err("SQLite: writer stopped".into()),
+        }
+    }
+
+    /// Queues a replayed record, waiting for room in a full queue instead of dropping the record.
+    fn replay(&self, exchange: &str, symbol: &str, record: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let row = SqliteSink::row(exchange, symbol, record)?;
+        match self.sender.as_ref().map(|sender| sender.send(row)) {
+            Some(Ok(())) => Ok(()),
+            Some(Err(_)) | None => Err("SQLite: writer stopped".into()),
+        }
+    }
+}
+
+impl Drop for SqliteSink {
+    fn drop(&mut self) {
+        // Closing the queue lets the writer insert what is left and exit
+        self.sender.take();
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+/// Inserts queued rows until the queue is closed, committing each batch of queued rows together.
+fn write_rows(mut connection: Connection, receiver: Receiver<Row>) {
+    while let Ok(first) = receiver.recv() {
+        let batch: Vec<Row> = std::iter::once(first).chain(receiver.try_iter()).collect();
+        if let Err(e) = insert_rows(&mut connection, &batch) {
+            error!("Failed to insert {} records into SQLite: {}", batch.len(), e);
+        }
+    }
+}
+
+/// Inserts `rows` into the `order_book` table in one transaction.
+fn insert_rows(connection: &mut Connection, rows: &[Row]) -> rusqlite::Result<()> {
+    let transaction = connection.transaction()?;
+    {
+        let mut insert = transaction.prepare_cached(
+            "INSERT INTO order_book (exchange, symbol, time, raw_json) VALUES (?1, ?2, ?3, ?4)",
+        )?;
+        for row in rows {
+            insert.execute((&row.exchange, &row.symbol, row.time, &row.raw_json))?;
+        }
+    }
+    transaction.commit()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_records_are_inserted_and_flushed_on_drop() {
+        let path = std::env::temp_dir().join("cex_sqlite_sink_test.db");
+        let _ = std::fs::remove_file(&path);
+        let path_str = path.to_str().unwrap();
+
+        let sink = SqliteSink::open(path_str).unwrap();
+        sink.publish("BINANCE", "BTC_USDT", r#"{"time": 5, "response": {"bids": []}}"#).unwrap();
+        sink.publish("BITKUB", "BTC_THB", r#"{"time": 6, "response": {"bids": []}}"#).unwrap();
+        assert!(sink.publish("BINANCE", "BTC_USDT", "not a record").is_err());
+        drop(sink);
+
+        let connection = Connection::open(path_str).unwrap();
+        let rows: Vec<(String, String, i64, String)> = connection
+            .prepare("SELECT exchange, symbol, time, raw_json FROM order_book ORDER BY time").unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))).unwrap()
+            .collect::<rusqlite::Result<_>>().unwrap();
+        assert_eq!(rows, vec![
+            ("BINANCE".to_string(), "BTC_USDT".to_string(), 5, r#"{"time": 5, "response": {"bids": []}}"#.to_string()),
+            ("BITKUB".to_string(), "BTC_THB".to_string(), 6, r#"{"time": 6, "response": {"bids": []}}"#.to_string()),
+        ]);
+        let index: String = connection
+            .query_row("SELECT name FROM sqlite_master WHERE type = 'index' AND tbl_name = 'order_book'", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(index, "order_book_exchange_symbol_time");
+
+        // Reopening keeps the existing rows
+        drop(SqliteSink::open(path_str).unwrap());
+        let count: i64 = connection.query_row("SELECT COUNT(*) FROM order_book", [], |row| row.get(0)).unwrap();
+        assert_eq!(count, 2);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,1268 @@
+use base64::Engine;
+use chrono::{Offset, TimeZone};
+use chrono_tz::Tz;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use std::error::Error;
+use std::fs::{self, create_dir_all, File, OpenOptions};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use crate::config::{Config, RecordCompression, SnapshotCollision, TimestampUnit};
+use tracing::{debug, error, info, warn};
+
+/// The zstd compression level used for compressed record lines.
+const ZSTD_LEVEL: i32 = 3;
+
+/// Creates a directory if it does not exist.
+///
+/// A failure is logged rather than returned: the data files create their directory again
+/// before opening a file, so writes are retried once the directory can be created.
+///
+/// # Arguments
+///
+/// * `path` - A string slice that holds the path of the directory to create.
+pub fn create_directory(path: &str) {
+    match create_dir_all(path) {
+        Ok(()) => debug!("Directory {} created or already exists", path),
+        Err(e) => error!("Cannot create dir {}: {}", path, e),
+    }
+}
+
+/// Checks that files can be written in `dir`, creating it if needed.
+///
+/// A probe file is created, written and removed again, so a read-only mount or a directory
+/// without write permission is detected before any worker starts.
+///
+/// # Errors
+///
+/// Returns an `io::Error` if the directory cannot be created or the probe file cannot be written or removed.
+pub fn check_writable(dir: &str) -> io::Result<()> {
+    create_dir_all(dir)?;
+    let probe = Path::new(dir).join(format!(".write_check-{}", std::process::id()));
+    let written = File::create(&probe).and_then(|mut file| file.write_all(b"ok").and_then(|_| file.sync_all()));
+    let removed = fs::remove_file(&probe);
+    written.and(removed)
+}
+
+/// Appends order book records to hourly files in a ticker's data directory.
+///
+/// The file for the current hour is kept open between writes and is replaced by a new
+/// `<hour_timestamp>.json` file when a record belongs to a later hour. The extension can be changed,
+/// e.g. to `csv`, and the period can be changed from an hour, e.g. to a day. With a record or size limit
+/// set, a full file is also replaced within the hour by the next `<hour_timestamp>-<n>.json` part.
+/// The names can be customized with a filename pattern, see `set_filename_pattern`.
+/// Writes are flushed after every record unless a write buffer is set, see `set_write_buffer`.
+pub struct HourlyFile {
+    dir: String,
+    fsync_on_rotation: bool,
+    compression: RecordCompression,
+    header: Option<String>,
+    timezone: Option<Tz>,
+    /// Whether the files are nested in a directory per local date, see `set_date_dirs`.
+    date_dirs: bool,
+    max_records: Option<u64>,
+    max_bytes: Option<u64>,
+    gzip_on_rotation: bool,
+    extension: &'static str,
+    /// The file name without extension, with `{exchange}` and `{symbol}` already expanded.
+    stem_pattern: Option<String>,
+    /// The length of the period covered by each file, in seconds.
+    period_secs: i64,
+    /// The unit of the timestamps passed to `write`.
+    timestamp_unit: TimestampUnit,
+    current: Option<(i64, BufWriter<File>)>,
+    /// The path of the current file.
+    current_path: Option<String>,
+    /// The part number of the current file within its hour, the number of records it holds and its size.
+    part: u32,
+    records: u64,
+    bytes: u64,
+    /// The number of buffered records that triggers a flush, `1` to flush every record.
+    flush_records: u64,
+    /// The age of the oldest buffered record that triggers a flush on the next write.
+    flush_interval: Option<Duration>,
+    /// The number of records written since the last flush and the time of the first of them.
+    unflushed: u64,
+    unflushed_since: Option<Instant>,
+}
+
+impl HourlyFile {
+    /// Creates a new `HourlyFile` writing into `dir`.
+    ///
+    /// # Arguments
+    ///
+    /// * `dir` - The directory the hourly files are created in.
+    /// * `fsync_on_rotation` - Whether to call `File::sync_all()` on a file when it is closed.
+    /// * `compression` - The compression applied to each written record.
+    pub fn new(dir: &str, fsync_on_rotation: bool, compression: RecordCompression) -> HourlyFile {
+        HourlyFile {
+            dir: dir.to_string(),
+            fsync_on_rotation,
+            compression,
+            header: None,
+            timezone: None,
+            date_dirs: false,
+            max_records: None,
+            max_bytes: None,
+            gzip_on_rotation: false,
+            extension: "json",
+            stem_pattern: None,
+            period_secs: 3600,
+            timestamp_unit: TimestampUnit::Seconds,
+            current: None,
+            current_path: None,
+            part: 0,
+            records: 0,
+            bytes: 0,
+            flush_records: 1,
+            flush_interval: None,
+            unflushed: 0,
+            unflushed_since: None,
+        }
+    }
+
+    /// Creates a new `HourlyFile` writing into `dir` with the file settings of `config`: fsync,
+    /// compression, rotation period, timestamp unit, filename pattern, timezone, date directories,
+    /// record and size limits, gzip on rotation and write buffer.
+    ///
+    /// The header and extension are left to the caller, as they depend on what the files hold.
+    ///
+    /// # Arguments
+    ///
+    /// * `dir` - The directory the hourly files are created in.
+    /// * `config` - The loaded configuration.
+    /// * `exchange` - The exchange name substituted in the filename pattern.
+    /// * `symbol` - The ticker symbol substituted in the filename pattern.
+    pub fn from_config(dir: &str, config: &Config, exchange: &str, symbol: &str) -> HourlyFile {
+        let mut file = HourlyFile::new(dir, config.fsync_on_rotation, config.compression);
+        file.set_period_secs(config.rotation_secs);
+        file.set_timestamp_unit(config.timestamp_unit);
+        file.set_filename_pattern(&config.filename_pattern, exchange, symbol);
+        if let Some(timezone) = config.timezone {
+            file.set_timezone(timezone);
+        }
+        if config.date_dirs {
+            file.set_date_dirs();
+        }
+        if let Some(max_records) = config.max_records_per_file {
+            file.set_max_records(max_records);
+        }
+        if let Some(max_bytes) = config.max_file_bytes {
+            file.set_max_bytes(max_bytes);
+        }
+        if config.gzip_on_rotation {
+            file.set_gzip_on_rotation();
+        }
+        if let Some(buffer) = &config.write_buffer {
+            file.set_write_buffer(buffer.flush_records, Duration::from_millis(buffer.flush_interval_ms));
+        }
+        file
+    }
+
+    /// Sets a metadata record written as the first line of every newly created file.
+    ///
+    /// The header is always stored uncompressed so consumers can read it directly.
+    /// It is not written to a file that already has content, e.g. after a restart within the hour.
+    pub fn set_header(&mut self, header: String) {
+        self.header = Some(header);
+    }
+
+    /// Sets the timezone whose hours delimit the files, instead of UTC.
+    ///
+    /// Files are then named after the local start of their hour with its UTC offset,
+    /// e.g. `2024-01-16T00+0700.json`, which keeps the repeated hour of a DST change unambiguous.
+    pub fn set_timezone(&mut self, timezone: Tz) {
+        self.timezone = Some(timezone);
+    }
+
+    /// Nests the files in a directory per date and names them after their local hour, e.g.
+    /// `2024-01-15/13.json`, in the configured timezone or UTC.
+    ///
+    /// Periods that are not whole hours add minutes or seconds to the name, e.g. `2024-01-15/1345.json`.
+    /// The repeated hour of a DST change shares one file. Call this before `set_gzip_on_rotation`, so
+    /// interrupted compressions in the date directories are recovered too.
+    pub fn set_date_dirs(&mut self) {
+        self.date_dirs = true;
+    }
+
+    /// Limits each file to `max_records` records, in addition to the hourly rotation.
+    ///
+    /// The hour always takes precedence: a new hour starts a new file even if the current one is not full.
+    /// The file header does not count as a record.
+    pub fn set_max_records(&mut self, max_records: u64) {
+        self.max_records = Some(max_records);
+    }
+
+    /// Limits each file to `max_bytes` bytes, in addition to the hourly rotation.
+    ///
+    /// A record that would take the file past the limit starts the next part instead, so records
+    /// never span two files. A single record larger than the limit still gets a file of its own.
+    /// The hour takes precedence as with `set_max_records`, and the header counts towards the size.
+    pub fn set_max_bytes(&mut self, max_bytes: u64) {
+        self.max_bytes = Some(max_bytes);
+    }
+
+    /// Sets the period in seconds covered by each file, an hour by default.
+    ///
+    /// Files are still named after the start of their period, e.g. the unix timestamp of midnight UTC
+    /// for a daily period. With a timezone, periods are aligned to local time instead.
+    pub fn set_period_secs(&mut self, period_secs: u64) {
+        self.period_secs = period_secs.max(1) as i64;
+    }
+
+    /// Sets the unit of the timestamps passed to `write`, seconds by default.
+    ///
+    /// Files are named after the start of their period in seconds whatever the unit.
+    pub fn set_timestamp_unit(&mut self, timestamp_unit: TimestampUnit) {
+        self.timestamp_unit = timestamp_unit;
+    }
+
+    /// Sets the extension of the created files, `json` by default.
+    pub fn set_extension(&mut self, extension: &'static str) {
+        self.extension = extension;
+    }
+
+    /// Names the files after `pattern` instead of `{hour_ts}.json`, expanding its tokens on each rotation.
+    ///
+    /// The `.json` suffix of the pattern is replaced by the configured extension, and parts of a full
+    /// file get their `-<n>` suffix before it. The pattern is expected to be checked by `Config::validate`.
+    ///
+    /// # Arguments
+    ///
+    /// * `pattern` - The pattern, e.g. `"{exchange}_{symbol}_{date}.json"`.
+    /// * `exchange` - The exchange name substituted for `{exchange}`.
+    /// * `symbol` - The ticker symbol substituted for `{symbol}`.
+    pub fn set_filename_pattern(&mut self, pattern: &str, exchange: &str, symbol: &str) {
+        let stem = pattern.strip_suffix(".json").unwrap_or(pattern);
+        self.stem_pattern = Some(stem.replace("{exchange}", exchange).replace("{symbol}", symbol));
+    }
+
+    /// Buffers written records, flushing them once `flush_records` are pending or the oldest of them
+    /// is older than `flush_interval` when the next record is written.
+    ///
+    /// The buffer is always flushed when the file is rotated or closed, so no record is lost on shutdown.
+    pub fn set_write_buffer(&mut self, flush_records: u64, flush_interval: Duration) {
+        self.flush_records = flush_records.max(1);
+        self.flush_interval = Some(flush_interval);
+    }
+
+    /// Gzips each file in the background once it is rotated away from, replacing it by `<file>.json.gz`.
+    ///
+    /// Compressions interrupted by a crash are finished first, see `recover_gzip`.
+    pub fn set_gzip_on_rotation(&mut self) {
+        self.gzip_on_rotation = true;
+        if let Err(e) = recover_gzip(Path::new(&self.dir)) {
+            warn!("Cannot recover interrupted compressions in {}: {}", self.dir, e);
+        }
+        if self.date_dirs {
+            for date_dir in date_dirs(Path::new(&self.dir)) {
+                if let Err(e) = recover_gzip(&date_dir) {
+                    warn!("Cannot recover interrupted compressions in {}: {}", date_dir.display(), e);
+                }
+            }
+        }
+    }
+
+    /// Appends a record to the file of the hour containing `timestamp`, rotating files if needed.
+    ///
+    /// # Arguments
+    ///
+    /// * `timestamp` - The unix timestamp of the record, in seconds unless set otherwise.
+    /// * `data` - The record to write, without a trailing newline.
+    pub fn write(&mut self, timestamp: i64, data: &str) -> io::Result<()> {
+        self.write_rows(timestamp, &[data])
+    }
+
+    /// Appends the lines of one record, e.g. the CSV rows of a book, to the file of the hour
+    /// containing `timestamp`, rotating files if needed.
+    ///
+    /// The lines count as one record towards `set_max_records` and are sized together for
+    /// `set_max_bytes`, so they are never split across two files. Each line is compressed on its own.
+    ///
+    /// # Arguments
+    ///
+    /// * `timestamp` - The unix timestamp of the record, in seconds unless set otherwise.
+    /// * `rows` - The lines of the record, without trailing newlines.
+    pub fn write_rows<S: AsRef<str>>(&mut self, timestamp: i64, rows: &[S]) -> io::Result<()> {
+        if rows.is_empty() {
+            return Ok(());
+        }
+        let seconds = timestamp.div_euclid(self.timestamp_unit.per_second());
+        let (hour_timestamp, mut file_stem) = period_file_stem(seconds, self.period_secs, self.timezone);
+        let date_dir = match self.date_dirs {
+            true => {
+                let (date, hour) = date_dir_file_stem(hour_timestamp, self.period_secs, self.timezone);
+                file_stem = hour;
+                Some(date)
+            }
+            false => None,
+        };
+        if let Some(pattern) = &self.stem_pattern {
+            file_stem = expand_file_stem(pattern, hour_timestamp, &file_stem, self.timezone);
+        }
+        if let Some(date) = date_dir {
+            file_stem = format!("{}/{}", date, file_stem);
+        }
+        let lines = rows.iter()
+            .map(|row| match self.compression {
+                RecordCompression::None => Ok(row.as_ref().to_string()),
+                RecordCompression::Zstd => encode_line(row.as_ref()),
+            })
+            .collect::<io::Result<Vec<String>>>()?;
+        let line_bytes = lines.iter().map(|line| line.len() as u64 + 1).sum();
+        let full = self.is_full(self.records, self.bytes, line_bytes);
+        let rotation = match &self.current {
+            Some((current_hour, _)) if hour_timestamp > *current_hour => Some(0),
+            Some(_) if full => Some(self.part + 1),
+            Some(_) => None,
+            None => Some(0),
+        };
+
+        if let Some(part) = rotation {
+            let previous = self.current_path.take();
+            self.close()?;
+            if let (true, Some(previous)) = (self.gzip_on_rotation, previous) {
+                thread::spawn(move || {
+                    if let Err(e) = gzip_file(Path::new(&previous)) {
+                        error!("Cannot gzip {}: {}", previous, e);
+                    }
+                });
+            }
+            self.open(hour_timestamp, &file_stem, part, line_bytes)?;
+        }
+
+        if let Some((_, file)) = &mut self.current {
+            // A failed write drops the file, so the next record reopens it rather than rotating away from it
+            if let Err(e) = lines.iter().try_for_each(|line| writeln!(file, "{}", line)) {
+                self.current = None;
+                self.current_path = None;
+                return Err(e);
+            }
+            self.records += 1;
+            self.bytes += line_bytes;
+            self.unflushed += 1;
+            let since = *self.unflushed_since.get_or_insert_with(Instant::now);
+            let expired = self.flush_interval.is_some_and(|interval| since.elapsed() >= interval);
+            if self.unflushed >= self.flush_records || expired {
+                self.flush()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns whether a file holding `records` records in `bytes` bytes has no room for a record of `line_bytes`.
+    fn is_full(&self, records: u64, bytes: u64, line_bytes: u64) -> bool {
+        self.max_records.is_some_and(|max_records| records >= max_records)
+            || self.max_bytes.is_some_and(|max_bytes| records > 0 && bytes + line_bytes > max_bytes)
+    }
+
+    /// Opens the first part of the hour from `part` on that still has room for a record of `line_bytes`.
+    ///
+    /// Parts left full by a previous run are skipped, so no file exceeds the record or size limit after a restart.
+    /// A record left half-written by a killed run is removed before appending, see `truncate_partial_line`.
+    fn open(&mut self, hour_timestamp: i64, file_stem: &str, mut part: u32, line_bytes: u64) -> io::Result<()> {
+        loop {
+            let file_path = match part {
+                0 => format!("{}/{}.{}", self.dir, file_stem, self.extension),
+                part => format!("{}/{}-{}.{}", self.dir, file_stem, part, self.extension),
+            };
+            // A compressed part was completed by an earlier run and is never reopened
+            if Path::new(&format!("{}.gz", file_path)).exists() {
+                part += 1;
+                continue;
+            }
+            if truncate_partial_line(Path::new(&file_path))? {
+                warn!("Removed a partially written record at the end of {}", file_path);
+            }
+            let records = match self.max_records.or(self.max_bytes) {
+                Some(_) => count_records(&file_path, self.header.as_deref(), self.extension == "csv")?,
+                None => 0,
+            };
+            let bytes = match fs::metadata(&file_path) {
+                Ok(metadata) => metadata.len(),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => 0,
+                Err(e) => return Err(e),
+            };
+            if self.is_full(records, bytes, line_bytes) {
+                part += 1;
+                continue;
+            }
+
+            create_dir_all(Path::new(&file_path).parent().unwrap_or(Path::new(&self.dir)))?;
+            let mut file = BufWriter::new(OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&file_path)?);
+            let mut bytes = bytes;
+            if let Some(header) = &self.header {
+                if bytes == 0 {
+                    writeln!(file, "{}", header)?;
+                    bytes = header.len() as u64 + 1;
+                }
+            }
+            info!("Opened data file {}", file_path);
+            self.current = Some((hour_timestamp, file));
+            self.current_path = Some(file_path);
+            self.part = part;
+            self.records = records;
+            self.bytes = bytes;
+            return Ok(());
+        }
+    }
+
+    /// Writes the buffered records of the current file to it.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.unflushed = 0;
+        self.unflushed_since = None;
+        match &mut self.current {
+            Some((_, file)) => file.flush(),
+            None => Ok(()),
+        }
+    }
+
+    /// Closes the current file after flushing its buffered records, syncing it to disk first if
+    /// `fsync_on_rotation` is enabled.
+    pub fn close(&mut self) -> io::Result<()> {
+        self.flush()?;
+        if let Some((_, file)) = self.current.take() {
+            if self.fsync_on_rotation {
+                file.get_ref().sync_all()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Writes each order book record to its own `<millisecond timestamp>.json` file in a ticker's data directory.
+///
+/// Two records in the same millisecond, or a restart into a directory that already holds a file
+/// of that name, collide; `collision` decides whether the new record is suffixed, skipped or overwrites.
+pub struct SnapshotFiles {
+    dir: String,
+    compression: RecordCompression,
+    collision: SnapshotCollision,
+}
+
+impl SnapshotFiles {
+    /// Creates a new `SnapshotFiles` writing into `dir`.
+    ///
+    /// # Arguments
+    ///
+    /// * `dir` - The directory the snapshot files are created in.
+    /// * `compression` - The compression applied to each written record.
+    /// * `collision` - How a record is written when its file name is already taken.
+    pub fn new(dir: &str, compression: RecordCompression, collision: SnapshotCollision) -> SnapshotFiles {
+        SnapshotFiles { dir: dir.to_string(), compression, collision }
+    }
+
+    /// Writes a record to the file named after `timestamp_millis`.
+    ///
+    /// # Arguments
+    ///
+    /// * `timestamp_millis` - The unix timestamp of the record in milliseconds.
+    /// * `data` - The record to write, without a trailing newline.
+    ///
+    /// # Returns
+    ///
+    /// The path of the written file, or `None` if the record was skipped because of a collision.
+    pub fn write(&self, timestamp_millis: i64, data: &str) -> io::Result<Option<PathBuf>> {
+        let line = match self.compression {
+            RecordCompression::None => data.to_string(),
+            RecordCompression::Zstd => encode_line(data)?,
+        };
+        create_dir_all(&self.dir)?;
+        let path = PathBuf::from(&self.dir).join(format!("{}.json", timestamp_millis));
+        let mut options = OpenOptions::new();
+        options.write(true);
+        match self.collision {
+            SnapshotCollision::Overwrite => {
+                options.create(true).truncate(true);
+            }
+            SnapshotCollision::Skip | SnapshotCollision::Suffix => {
+                options.create_new(true);
+            }
+        }
+
+        let mut counter = 0;
+        let mut path = path;
+        loop {
+            match options.open(&path) {
+                Ok(mut file) => {
+                    writeln!(file, "{}", line)?;
+                    return Ok(Some(path));
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => match self.collision {
+                    SnapshotCollision::Skip => {
+                        warn!("Snapshot file {} already exists, skipping record", path.display());
+                        return Ok(None);
+                    }
+                    _ => {
+                        counter += 1;
+                        path = PathBuf::from(&self.dir).join(format!("{}-{}.json", timestamp_millis, counter));
+                    }
+                },
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// Compresses a completed data file to `<file>.gz` and removes the original.
+///
+/// The archive is written to a `.gz.tmp` file and renamed into place once it is synced, so a
+/// `.gz` file is always complete. The original is only removed afterwards.
+///
+/// # Errors
+///
+/// Returns an `io::Error` if the file cannot be read, compressed or removed.
+pub fn gzip_file(path: &Path) -> io::Result<()> {
+    let gz_path = PathBuf::from(format!("{}.gz", path.display()));
+    let tmp_path = PathBuf::from(format!("{}.gz.tmp", path.display()));
+    let mut encoder = GzEncoder::new(File::create(&tmp_path)?, Compression::default());
+    io::copy(&mut File::open(path)?, &mut encoder)?;
+    encoder.finish()?.sync_all()?;
+    fs::rename(&tmp_path, &gz_path)?;
+    fs::remove_file(path)
+}
+
+/// Finishes the compressions in `dir` that a crash interrupted.
+///
+/// A leftover `.gz.tmp` file is incomplete and is removed, keeping its original to be compressed
+/// again on a later rotation. An original next to its `.gz` file was already archived completely, so
+/// only the original is removed. This way each hour is held by exactly one file.
+///
+/// # Errors
+///
+/// Returns an `io::Error` if the directory cannot be read or a leftover cannot be removed.
+pub fn recover_gzip(dir: &Path) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let name = path.to_string_lossy().to_string();
+        let partial = name.ends_with(".json.gz.tmp") || name.ends_with(".csv.gz.tmp");
+        let data_file = name.ends_with(".json") || name.ends_with(".csv");
+        let archived = data_file && Path::new(&format!("{}.gz", name)).exists();
+        if partial || archived {
+            fs::remove_file(&path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Truncates a file after its last newline, removing a record left half-written by a killed process.
+///
+/// The file is scanned back from its end for the last `\n`, so only the tail of a large file is read.
+/// A file without any newline is emptied. A missing or empty file, or one ending in a newline, is kept as is.
+///
+/// # Returns
+///
+/// Whether the file was truncated.
+///
+/// # Errors
+///
+/// Returns an `io::Error` if the file exists but cannot be read or truncated.
+pub fn truncate_partial_line(path: &Path) -> io::Result<bool> {
+    const CHUNK: u64 = 4096;
+    let mut file = match OpenOptions::new().read(true).write(true).open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e),
+    };
+    let len = file.metadata()?.len();
+    let mut end = len;
+    let mut buffer = vec![0; CHUNK as usize];
+    while end > 0 {
+        let start = end.saturating_sub(CHUNK);
+        let chunk = &mut buffer[..(end - start) as usize];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(chunk)?;
+        if let Some(newline) = chunk.iter().rposition(|byte| *byte == b'\n') {
+            end = start + newline as u64 + 1;
+            break;
+        }
+        end = start;
+    }
+    if end == len {
+        return Ok(false);
+    }
+    file.set_len(end)?;
+    Ok(true)
+}
+
+/// Counts the records in an existing data file, excluding its header; a missing file has none.
+///
+/// With `csv`, consecutive rows of the same time, i.e. the rows of one book, count as one record.
+fn count_records(path: &str, header: Option<&str>, csv: bool) -> io::Result<u64> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+    let mut records = 0;
+    let mut last_time = None;
+    for line in contents.lines().filter(|line| !line.starts_with(r#"{"meta""#) && Some(*line) != header) {
+        if csv {
+            let time = line.split(',').next();
+            if time == last_time {
+                continue;
+            }
+            last_time = time;
+        }
+        records += 1;
+    }
+    Ok(records)
+}
+
+/// Returns the start of the period of `period_secs` containing `timestamp` and the name of its file, without extension.
+///
+/// Without a timezone periods are aligned to UTC and named by their unix timestamp, e.g. `1705334400`.
+/// With a timezone they are aligned to local time and named by their local start time and UTC offset,
+/// with minutes or seconds added when the period is not a whole number of hours.
+fn period_file_stem(timestamp: i64, period_secs: i64, timezone: Option<Tz>) -> (i64, String) {
+    match timezone.and_then(|tz| tz.timestamp_opt(timestamp, 0).single()) {
+        Some(local) => {
+            let offset = local.offset().fix().local_minus_utc() as i64;
+            let period_timestamp = (timestamp + offset).div_euclid(period_secs) * period_secs - offset;
+            let start = local.timezone().timestamp_opt(period_timestamp, 0).unwrap();
+            let format = match period_secs {
+                p if p % 3600 == 0 => "%Y-%m-%dT%H%z",
+                p if p % 60 == 0 => "%Y-%m-%dT%H%M%z",
+                _ => "%Y-%m-%dT%H%M%S%z",
+            };
+            (period_timestamp, start.format(format).to_string())
+        }
+        None => {
+            let period_timestamp = timestamp.div_euclid(period_secs) * period_secs;
+            (period_timestamp, period_timestamp.to_string())
+        }
+    }
+}
+
+/// Returns the local date directory and file name, without extension, of the period starting at
+/// `period_timestamp`, e.g. `("2024-01-15", "13")`, in `timezone` or UTC without one.
+///
+/// Minutes or seconds are added to the name when the period is not a whole number of hours.
+fn date_dir_file_stem(period_timestamp: i64, period_secs: i64, timezone: Option<Tz>) -> (String, String) {
+    let format = match period_secs {
+        p if p % 3600 == 0 => "%H",
+        p if p % 60 == 0 => "%H%M",
+        _ => "%H%M%S",
+    };
+    match timezone.and_then(|tz| tz.timestamp_opt(period_timestamp, 0).single()) {
+        Some(local) => (local.format("%Y-%m-%d").to_string(), local.format(format).to_string()),
+        None => {
+            let start = chrono::Utc.timestamp_opt(period_timestamp, 0).unwrap();
+            (start.format("%Y-%m-%d").to_string(), start.format(format).to_string())
+        }
+    }
+}
+
+/// Returns the subdirectories of `dir` named after a date, e.g. `2024-01-15`; a missing `dir` has none.
+pub(crate) fn date_dirs(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    entries.filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_dir())
+        .filter(|path| path.file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| chrono::NaiveDate::parse_from_str(name, "%Y-%m-%d").is_ok()))
+        .collect()
+}
+
+/// Expands the `{hour_ts}` and `{date}` tokens of a file name pattern for the period starting at `period_timestamp`.
+///
+/// `{hour_ts}` becomes `period_stem`, the default name of the file, and `{date}` the local date the period
+/// starts on in `timezone`, or in UTC without one.
+fn expand_file_stem(pattern: &str, period_timestamp: i64, period_stem: &str, timezone: Option<Tz>) -> String {
+    let date = match timezone.and_then(|tz| tz.timestamp_opt(period_timestamp, 0).single()) {
+        Some(local) => local.format("%Y-%m-%d").to_string(),
+        None => chrono::Utc.timestamp_opt(period_timestamp, 0).unwrap().format("%Y-%m-%d").to_string(),
+    };
+    pattern.replace("{hour_ts}", period_stem).replace("{date}", &date)
+}
+
+/// Compresses a record into an independently decodable `{"zstd": "<base64>"}` line.
+///
+/// # Errors
+///
+/// Returns an `io::Error` if compression fails.
+pub fn encode_line(data: &str) -> io::Result<String> {
+    let compressed = zstd::encode_all(data.as_bytes(), ZSTD_LEVEL)?;
+    Ok(format!(r#"{{"zstd": "{}"}}"#, BASE64.encode(compressed)))
+}
+
+/// Restores the record stored in a data file line.
+///
+/// Lines written with `"compression": "zstd"` are decompressed; any other line is returned unchanged,
+/// so files of either kind can be decoded the same way.
+///
+/// # Errors
+///
+/// Returns an error if a compressed line is not valid base64 or zstd data.
+pub fn decode_line(line: &str) -> Result<String, Box<dyn Error>> {
+    let encoded = match serde_json::from_str::<serde_json::Value>(line) {
+        Ok(value) => match value.get("zstd").and_then(|v| v.as_str()) {
+            Some(encoded) => encoded.to_string(),
+            None => return Ok(line.to_string()),
+        },
+        Err(_) => return Ok(line.to_string()),
+    };
+    let decompressed = zstd::decode_all(BASE64.decode(encoded)?.as_slice())?;
+    Ok(String::from_utf8(decompressed)?)
+}
+
+/// The number of sequence numbers reserved by each write of a `.seq` file.
+const SEQUENCE_BLOCK: u64 = 1000;
+
+/// A per-ticker record sequence number persisted in a small state file.
+///
+/// Numbers are reserved in blocks of `SEQUENCE_BLOCK`: the end of the current block is stored
+/// in `.seq` in the ticker's data directory, so the file is only replaced once per block rather
+/// than once per record. `close` stores the last issued number, so numbering continues without a
+/// gap after a clean restart. After a crash it continues after the reserved block, so a number is
+/// never issued twice.
+pub struct SequenceFile {
+    path: PathBuf,
+    last: u64,
+    /// The last number of the reserved block, as stored in the state file.
+    reserved: u64,
+}
+
+impl SequenceFile {
+    /// Loads the last issued sequence number from `dir`, starting from zero if there is none.
+    ///
+    /// # Arguments
+    ///
+    /// * `dir` - The ticker's data directory holding the state file.
+    pub fn load(dir: &str) -> SequenceFile {
+        let path = PathBuf::from(dir).join(".seq");
+        let last = fs::read_to_string(&path)
+            .ok()
+            .and_then(|contents| contents.trim().parse().ok())
+            .unwrap_or(0);
+        SequenceFile { path, last, reserved: last }
+    }
+
+    /// Issues the next sequence number, reserving the next block first if the current one is used up.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `io::Error` if the state file cannot be written. The number is skipped then,
+    /// and the reservation is retried for the next one.
+    pub fn next(&mut self) -> io::Result<u64> {
+        self.last += 1;
+        if self.last > self.reserved {
+            self.store(self.last + SEQUENCE_BLOCK - 1)?;
+            self.reserved = self.last + SEQUENCE_BLOCK - 1;
+        }
+        Ok(self.last)
+    }
+
+    /// Stores the last issued number, releasing the rest of the reserved block.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `io::Error` if the state file cannot be written.
+    pub fn close(&mut self) -> io::Result<()> {
+        if self.reserved > self.last {
+            self.store(self.last)?;
+            self.reserved = self.last;
+        }
+        Ok(())
+    }
+
+    /// Replaces the state file atomically with `value`.
+    fn store(&self, value: u64) -> io::Result<()> {
+        let temp_path = self.path.with_extension("tmp");
+        fs::write(&temp_path, value.to_string())?;
+        fs::rename(&temp_path, &self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        decode_line, encode_line, gzip_file, period_file_stem, recover_gzip, truncate_partial_line, HourlyFile,
+        SequenceFile, SnapshotFiles,
+    };
+    use crate::config::{Config, RecordCompression, SnapshotCollision, TimestampUnit};
+    use std::fs;
+
+    #[test]
+    fn test_write_rotates_on_new_hour() {
+        let dir = std::env::temp_dir().join("cex_storage_test_rotation");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let dir_str = dir.to_str().unwrap();
+
+        let mut file = HourlyFile::new(dir_str, true, RecordCompression::None);
+        file.write(3600, "a").unwrap();
+        file.write(7199, "b").unwrap();
+        file.write(7200, "c").unwrap();
+        file.close().unwrap();
+
+        assert_eq!(fs::read_to_string(dir.join("3600.json")).unwrap(), "a\nb\n");
+        assert_eq!(fs::read_to_string(dir.join("7200.json")).unwrap(), "c\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_write_buffer_flushes_on_count_and_close() {
+        let dir = std::env::temp_dir().join("cex_storage_test_write_buffer");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let dir_str = dir.to_str().unwrap();
+
+        let mut file = HourlyFile::new(dir_str, false, RecordCompression::None);
+        file.set_write_buffer(3, std::time::Duration::from_secs(3600));
+        file.write(3600, "a").unwrap();
+        file.write(3601, "b").unwrap();
+        assert_eq!(fs::read_to_string(dir.join("3600.json")).unwrap(), "");
+        file.write(3602, "c").unwrap();
+        assert_eq!(fs::read_to_string(dir.join("3600.json")).unwrap(), "a\nb\nc\n");
+
+        // Rotation and close flush what is still buffered
+        file.write(3603, "d").unwrap();
+        file.write(7200, "e").unwrap();
+        assert_eq!(fs::read_to_string(dir.join("3600.json")).unwrap(), "a\nb\nc\nd\n");
+        file.close().unwrap();
+        assert_eq!(fs::read_to_string(dir.join("7200.json")).unwrap(), "e\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_write_buffer_flushes_after_interval() {
+        let dir = std::env::temp_dir().join("cex_storage_test_write_buffer_interval");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let dir_str = dir.to_str().unwrap();
+
+        let mut file = HourlyFile::new(dir_str, false, RecordCompression::None);
+        file.set_write_buffer(1000, std::time::Duration::ZERO);
+        file.write(3600, "a").unwrap();
+        assert_eq!(fs::read_to_string(dir.join("3600.json")).unwrap(), "a\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_check_writable() {
+        let root = std::env::temp_dir().join("cex_storage_test_writable");
+        let _ = fs::remove_dir_all(&root);
+        let dir = root.join("data");
+        super::check_writable(dir.to_str().unwrap()).unwrap();
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+
+        // A file in place of the directory cannot hold data files
+        let blocked = root.join("blocked");
+        fs::write(&blocked, "").unwrap();
+        assert!(super::check_writable(blocked.to_str().unwrap()).is_err());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_write_retries_after_failure() {
+        let root = std::env::temp_dir().join("cex_storage_test_retry");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        // A file in place of the data directory makes every open fail
+        let dir = root.join("BTC_USDT");
+        fs::write(&dir, "").unwrap();
+
+        super::create_directory(dir.to_str().unwrap());
+        let mut file = HourlyFile::new(dir.to_str().unwrap(), false, RecordCompression::None);
+        assert!(file.write(3600, "a").is_err());
+
+        fs::remove_file(&dir).unwrap();
+        file.write(3601, "b").unwrap();
+        file.close().unwrap();
+        assert_eq!(fs::read_to_string(dir.join("3600.json")).unwrap(), "b\n");
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_file_reopened_after_failure_is_not_gzipped() {
+        let dir = std::env::temp_dir().join("cex_storage_test_retry_gzip");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        // Writes to /dev/full fail as on a full disk, once the record overflows the write buffer
+        std::os::unix::fs::symlink("/dev/full", dir.join("3600.json")).unwrap();
+
+        let mut file = HourlyFile::new(dir.to_str().unwrap(), false, RecordCompression::None);
+        file.set_gzip_on_rotation();
+        assert!(file.write(3600, &"a".repeat(10_000)).is_err());
+
+        fs::remove_file(dir.join("3600.json")).unwrap();
+        fs::write(dir.join("3600.json"), "b\n").unwrap();
+        file.write(3601, "c").unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(200));
+        file.write(3602, "d").unwrap();
+        file.close().unwrap();
+
+        assert!(!dir.join("3600.json.gz").exists());
+        assert_eq!(fs::read_to_string(dir.join("3600.json")).unwrap(), "b\nc\nd\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_write_rotates_on_new_hour_in_millis() {
+        let dir = std::env::temp_dir().join("cex_storage_test_rotation_millis");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let dir_str = dir.to_str().unwrap();
+
+        let mut file = HourlyFile::new(dir_str, true, RecordCompression::None);
+        file.set_timestamp_unit(TimestampUnit::Millis);
+        file.write(3_600_000, "a").unwrap();
+        file.write(7_199_999, "b").unwrap();
+        file.write(7_200_000, "c").unwrap();
+        file.close().unwrap();
+
+        assert_eq!(fs::read_to_string(dir.join("3600.json")).unwrap(), "a\nb\n");
+        assert_eq!(fs::read_to_string(dir.join("7200.json")).unwrap(), "c\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_write_rotates_on_record_count() {
+        let dir = std::env::temp_dir().join("cex_storage_test_record_count");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let dir_str = dir.to_str().unwrap();
+
+        let mut file = HourlyFile::new(dir_str, false, RecordCompression::None);
+        file.set_header(r#"{"meta": {}}"#.to_string());
+        file.set_max_records(2);
+        for (timestamp, record) in [(3600, "a"), (3601, "b"), (3602, "c"), (7200, "d")] {
+            file.write(timestamp, record).unwrap();
+        }
+        file.close().unwrap();
+
+        // A restart within the hour continues in the part that still has room
+        let mut restarted = HourlyFile::new(dir_str, false, RecordCompression::None);
+        restarted.set_max_records(2);
+        restarted.write(3603, "e").unwrap();
+        restarted.write(3604, "f").unwrap();
+        restarted.close().unwrap();
+
+        assert_eq!(fs::read_to_string(dir.join("3600.json")).unwrap(), "{\"meta\": {}}\na\nb\n");
+        assert_eq!(fs::read_to_string(dir.join("3600-1.json")).unwrap(), "{\"meta\": {}}\nc\ne\n");
+        assert_eq!(fs::read_to_string(dir.join("3600-2.json")).unwrap(), "f\n");
+        assert_eq!(fs::read_to_string(dir.join("7200.json")).unwrap(), "{\"meta\": {}}\nd\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_write_rotates_on_file_size() {
+        let dir = std::env::temp_dir().join("cex_storage_test_file_size");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let dir_str = dir.to_str().unwrap();
+
+        let mut file = HourlyFile::new(dir_str, false, RecordCompression::None);
+        file.set_header("{\"meta\": {}}".to_string());
+        file.set_max_bytes(20);
+        for (timestamp, record) in [(3600, "aaa"), (3601, "bbb"), (3602, "cccccccccccccccccccccccc"), (3603, "d")] {
+            file.write(timestamp, record).unwrap();
+        }
+        file.close().unwrap();
+
+        // A restart within the hour continues in the part that still has room
+        let mut restarted = HourlyFile::new(dir_str, false, RecordCompression::None);
+        restarted.set_max_bytes(20);
+        restarted.write(3604, "eeeeeeeeeeeeeeeeee").unwrap();
+        restarted.close().unwrap();
+
+        assert_eq!(fs::read_to_string(dir.join("3600.json")).unwrap(), "{\"meta\": {}}\naaa\n");
+        assert_eq!(fs::read_to_string(dir.join("3600-1.json")).unwrap(), "{\"meta\": {}}\nbbb\n");
+        // A record larger than the limit gets a file of its own
+        assert_eq!(fs::read_to_string(dir.join("3600-2.json")).unwrap(), "{\"meta\": {}}\ncccccccccccccccccccccccc\n");
+        assert_eq!(fs::read_to_string(dir.join("3600-3.json")).unwrap(), "{\"meta\": {}}\nd\n");
+        assert_eq!(fs::read_to_string(dir.join("3600-4.json")).unwrap(), "eeeeeeeeeeeeeeeeee\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_from_config_applies_file_settings() {
+        let dir = std::env::temp_dir().join("cex_storage_test_from_config");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let config = Config { date_dirs: true, max_records_per_file: Some(1), ..Config::default() };
+        let mut file = HourlyFile::from_config(dir.to_str().unwrap(), &config, "BINANCE", "BTC_USDT");
+        file.write(0, "a").unwrap();
+        file.write(1, "b").unwrap();
+        file.close().unwrap();
+
+        assert_eq!(fs::read_to_string(dir.join("1970-01-01/00.json")).unwrap(), "a\n");
+        assert_eq!(fs::read_to_string(dir.join("1970-01-01/00-1.json")).unwrap(), "b\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_write_rows_keeps_rows_of_a_record_together() {
+        let dir = std::env::temp_dir().join("cex_storage_test_write_rows");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let dir_str = dir.to_str().unwrap();
+
+        let mut file = HourlyFile::new(dir_str, false, RecordCompression::None);
+        file.set_extension("csv");
+        file.set_header("time,side".to_string());
+        file.set_max_records(2);
+        file.set_max_bytes(40);
+        file.write_rows(3600, &["3600,bid", "3600,ask"]).unwrap();
+        file.write_rows(3601, &["3601,bid", "3601,ask"]).unwrap();
+        file.write_rows(3602, &["3602,bid"]).unwrap();
+        file.close().unwrap();
+
+        // After a restart, the two rows of the first file count as one record, leaving room for one more
+        let mut restarted = HourlyFile::new(dir_str, false, RecordCompression::None);
+        restarted.set_extension("csv");
+        restarted.set_header("time,side".to_string());
+        restarted.set_max_records(2);
+        restarted.write_rows(3603, &["3603,bid"]).unwrap();
+        restarted.write_rows(3604, &["3604,bid"]).unwrap();
+        restarted.close().unwrap();
+
+        // The second book does not fit in 40 bytes next to the first, so it starts the next part whole
+        assert_eq!(fs::read_to_string(dir.join("3600.csv")).unwrap(), "time,side\n3600,bid\n3600,ask\n3603,bid\n");
+        assert_eq!(fs::read_to_string(dir.join("3600-1.csv")).unwrap(), "time,side\n3601,bid\n3601,ask\n3602,bid\n");
+        assert_eq!(fs::read_to_string(dir.join("3600-2.csv")).unwrap(), "time,side\n3604,bid\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_truncate_partial_line() {
+        let dir = std::env::temp_dir().join("cex_storage_test_partial_line");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("3600.json");
+
+        fs::write(&path, "{\"time\": 3600}\n{\"time\": 3601, \"resp").unwrap();
+        assert!(truncate_partial_line(&path).unwrap());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{\"time\": 3600}\n");
+        assert!(!truncate_partial_line(&path).unwrap());
+
+        // The newline may lie further back than one chunk, or be missing
+        fs::write(&path, format!("a\n{}", "b".repeat(10_000))).unwrap();
+        assert!(truncate_partial_line(&path).unwrap());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "a\n");
+        fs::write(&path, "partial").unwrap();
+        assert!(truncate_partial_line(&path).unwrap());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "");
+        assert!(!truncate_partial_line(&dir.join("missing.json")).unwrap());
+
+        // A restart appends after the last complete record
+        fs::write(&path, "a\nb-half").unwrap();
+        let mut file = HourlyFile::new(dir.to_str().unwrap(), false, RecordCompression::None);
+        file.write(3602, "c").unwrap();
+        file.close().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "a\nc\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_gzip_file_and_recovery() {
+        use std::io::Read;
+        let dir = std::env::temp_dir().join("cex_storage_test_gzip");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        fs::write(dir.join("3600.json"), "a\nb\n").unwrap();
+        gzip_file(&dir.join("3600.json")).unwrap();
+        assert!(!dir.join("3600.json").exists());
+        let mut decoded = String::new();
+        flate2::read::GzDecoder::new(fs::File::open(dir.join("3600.json.gz")).unwrap())
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, "a\nb\n");
+
+        // A crash after the rename leaves the original; one before it leaves a partial archive
+        fs::write(dir.join("3600.json"), "a\nb\n").unwrap();
+        fs::write(dir.join("7200.json"), "c\n").unwrap();
+        fs::write(dir.join("7200.json.gz.tmp"), "partial").unwrap();
+        recover_gzip(&dir).unwrap();
+        assert!(!dir.join("3600.json").exists());
+        assert!(dir.join("3600.json.gz").exists());
+        assert_eq!(fs::read_to_string(dir.join("7200.json")).unwrap(), "c\n");
+        assert!(!dir.join("7200.json.gz.tmp").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_period_file_stem_in_timezone_crosses_day() {
+        // 2024-01-15T16:59:59Z is 23:59:59 in Bangkok (UTC+7); one second later is the next local day
+        let tz: chrono_tz::Tz = "Asia/Bangkok".parse().unwrap();
+        assert_eq!(period_file_stem(1_705_337_999, 3600, Some(tz)), (1_705_334_400, "2024-01-15T23+0700".to_string()));
+        assert_eq!(period_file_stem(1_705_338_000, 3600, Some(tz)), (1_705_338_000, "2024-01-16T00+0700".to_string()));
+
+        // A half-hour offset moves the rotation boundary off the UTC hour
+        let tz: chrono_tz::Tz = "Asia/Kolkata".parse().unwrap();
+        assert_eq!(period_file_stem(1_705_336_200, 3600, Some(tz)).0, 1_705_336_200);
+        assert_eq!(period_file_stem(1_705_336_200, 3600, None), (1_705_334_400, "1705334400".to_string()));
+    }
+
+    #[test]
+    fn test_date_dirs() {
+        let dir = std::env::temp_dir().join("cex_storage_test_date_dirs");
+        let _ = fs::remove_dir_all(&dir);
+        let dir_str = dir.to_str().unwrap();
+
+        // 2024-01-15T16:30Z is 23:30 in Bangkok, and daily files roll at local midnight, 17:00Z
+        let tz: chrono_tz::Tz = "Asia/Bangkok".parse().unwrap();
+        let mut file = HourlyFile::new(dir_str, false, RecordCompression::None);
+        file.set_timezone(tz);
+        file.set_date_dirs();
+        for (timestamp, record) in [(1_705_336_200, "a"), (1_705_338_000, "b")] {
+            file.write(timestamp, record).unwrap();
+        }
+        file.close().unwrap();
+        let mut daily = HourlyFile::new(dir_str, false, RecordCompression::None);
+        daily.set_period_secs(86_400);
+        daily.set_timezone(tz);
+        daily.set_date_dirs();
+        daily.set_filename_pattern("{symbol}_{hour_ts}.json", "BINANCE", "BTC_USDT");
+        for (timestamp, record) in [(1_705_337_999, "c"), (1_705_338_000, "d")] {
+            daily.write(timestamp, record).unwrap();
+        }
+        daily.close().unwrap();
+
+        assert_eq!(fs::read_to_string(dir.join("2024-01-15/23.json")).unwrap(), "a\n");
+        assert_eq!(fs::read_to_string(dir.join("2024-01-16/00.json")).unwrap(), "b\n");
+        assert_eq!(fs::read_to_string(dir.join("2024-01-15/BTC_USDT_00.json")).unwrap(), "c\n");
+        assert_eq!(fs::read_to_string(dir.join("2024-01-16/BTC_USDT_00.json")).unwrap(), "d\n");
+
+        // Without a timezone the dates and hours are UTC, with minutes for sub-hour periods
+        let mut utc = HourlyFile::new(dir_str, false, RecordCompression::None);
+        utc.set_period_secs(900);
+        utc.set_date_dirs();
+        utc.write(1_705_336_200, "e").unwrap();
+        utc.close().unwrap();
+        assert_eq!(fs::read_to_string(dir.join("2024-01-15/1630.json")).unwrap(), "e\n");
+
+        // Interrupted compressions in the date directories are recovered
+        fs::write(dir.join("2024-01-15/22.json.gz.tmp"), "partial").unwrap();
+        let mut gzipped = HourlyFile::new(dir_str, false, RecordCompression::None);
+        gzipped.set_date_dirs();
+        gzipped.set_gzip_on_rotation();
+        assert!(!dir.join("2024-01-15/22.json.gz.tmp").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_filename_pattern() {
+        let dir = std::env::temp_dir().join("cex_storage_test_filename_pattern");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut file = HourlyFile::new(dir.to_str().unwrap(), false, RecordCompression::None);
+        file.set_filename_pattern("{exchange}_{symbol}_{date}_{hour_ts}.json", "BINANCE", "BTC_USDT");
+        file.set_max_records(1);
+        file.write(1_705_337_999, "a").unwrap();
+        file.write(1_705_337_999, "b").unwrap();
+        file.close().unwrap();
+        assert_eq!(fs::read_to_string(dir.join("BINANCE_BTC_USDT_2024-01-15_1705334400.json")).unwrap(), "a\n");
+        assert_eq!(fs::read_to_string(dir.join("BINANCE_BTC_USDT_2024-01-15_1705334400-1.json")).unwrap(), "b\n");
+
+        // The date follows the timezone, and the extension follows the output format
+        let tz: chrono_tz::Tz = "Asia/Bangkok".parse().unwrap();
+        let mut file = HourlyFile::new(dir.to_str().unwrap(), false, RecordCompression::None);
+        file.set_filename_pattern("{date}.json", "BINANCE", "BTC_USDT");
+        file.set_timezone(tz);
+        file.set_extension("csv");
+        file.write(1_705_338_000, "c").unwrap();
+        file.close().unwrap();
+        assert_eq!(fs::read_to_string(dir.join("2024-01-16.csv")).unwrap(), "c\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_period_file_stem_daily_and_custom() {
+        assert_eq!(period_file_stem(1_705_337_999, 86_400, None), (1_705_276_800, "1705276800".to_string()));
+        let tz: chrono_tz::Tz = "Asia/Bangkok".parse().unwrap();
+        assert_eq!(period_file_stem(1_705_337_999, 86_400, Some(tz)), (1_705_251_600, "2024-01-15T00+0700".to_string()));
+        assert_eq!(period_file_stem(1_705_337_999, 900, None).0, 1_705_337_100);
+        assert_eq!(period_file_stem(1_705_337_999, 900, Some(tz)), (1_705_337_100, "2024-01-15T2345+0700".to_string()));
+    }
+
+    #[test]
+    fn test_header_written_once_per_file() {
+        let dir = std::env::temp_dir().join("cex_storage_test_header");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let dir_str = dir.to_str().unwrap();
+
+        let mut file = HourlyFile::new(dir_str, false, RecordCompression::None);
+        file.set_header("h".to_string());
+        file.write(3600, "a").unwrap();
+        file.close().unwrap();
+        file.write(3600, "b").unwrap();
+        file.write(7200, "c").unwrap();
+        file.close().unwrap();
+
+        assert_eq!(fs::read_to_string(dir.join("3600.json")).unwrap(), "h\na\nb\n");
+        assert_eq!(fs::read_to_string(dir.join("7200.json")).unwrap(), "h\nc\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_snapshot_collisions_in_same_millisecond() {
+        let dir = std::env::temp_dir().join("cex_storage_test_snapshot_collision");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let dir_str = dir.to_str().unwrap();
+
+        let suffix = SnapshotFiles::new(dir_str, RecordCompression::None, SnapshotCollision::Suffix);
+        assert_eq!(suffix.write(1000, "a").unwrap(), Some(dir.join("1000.json")));
+        assert_eq!(suffix.write(1000, "b").unwrap(), Some(dir.join("1000-1.json")));
+        assert_eq!(suffix.write(1000, "c").unwrap(), Some(dir.join("1000-2.json")));
+        assert_eq!(fs::read_to_string(dir.join("1000.json")).unwrap(), "a\n");
+        assert_eq!(fs::read_to_string(dir.join("1000-1.json")).unwrap(), "b\n");
+        assert_eq!(fs::read_to_string(dir.join("1000-2.json")).unwrap(), "c\n");
+
+        let skip = SnapshotFiles::new(dir_str, RecordCompression::None, SnapshotCollision::Skip);
+        assert_eq!(skip.write(1000, "d").unwrap(), None);
+        assert_eq!(fs::read_to_string(dir.join("1000.json")).unwrap(), "a\n");
+
+        let overwrite = SnapshotFiles::new(dir_str, RecordCompression::None, SnapshotCollision::Overwrite);
+        assert_eq!(overwrite.write(1000, "e").unwrap(), Some(dir.join("1000.json")));
+        assert_eq!(fs::read_to_string(dir.join("1000.json")).unwrap(), "e\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_zstd_lines_round_trip() {
+        let record = r#"{"time": 5, "response": {"bids": []}}"#;
+        let line = encode_line(record).unwrap();
+        assert!(line.starts_with(r#"{"zstd": ""#));
+        assert_eq!(decode_line(&line).unwrap(), record);
+        assert_eq!(decode_line(record).unwrap(), record);
+        assert!(decode_line(r#"{"zstd": "not base64!"}"#).is_err());
+    }
+
+    #[test]
+    fn test_sequence_survives_reload() {
+        let dir = std::env::temp_dir().join("cex_storage_test_sequence");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let dir_str = dir.to_str().unwrap();
+
+        let mut sequence = SequenceFile::load(dir_str);
+        assert_eq!(sequence.next().unwrap(), 1);
+        assert_eq!(sequence.next().unwrap(), 2);
+        // Only the reservation of the first block was written
+        assert_eq!(fs::read_to_string(dir.join(".seq")).unwrap(), "1000");
+        sequence.close().unwrap();
+
+        let mut reloaded = SequenceFile::load(dir_str);
+        assert_eq!(reloaded.next().unwrap(), 3);
+
+        // Without a close, e.g. after a crash, numbering continues after the reserved block
+        let mut recovered = SequenceFile::load(dir_str);
+        assert_eq!(recovered.next().unwrap(), 1003);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
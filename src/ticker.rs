@@ -1,8 +1,11 @@
+use std::fmt;
+
 /// Represents a trading pair in a cryptocurrency exchange.
 ///
 /// A `Ticker` consists of a base currency and a quote currency.
 /// For example, in the trading pair "BTC_USDT", BTC is the base currency,
 /// and USDT is the quote currency.
+#[derive(Debug, Clone)]
 pub struct Ticker {
     pub base: String,
     pub quote: String,
@@ -14,40 +17,44 @@ impl Ticker {
     /// The symbol should be in the format "BASE_QUOTE", where BASE is the base currency
     /// and QUOTE is the quote currency. For example, "BTC_USDT".
     ///
-    /// Returns `Some(Ticker)` if the symbol is valid, or `None` if the symbol format is invalid.
+    /// Returns `Some(Ticker)` if the symbol is valid, or `None` if the symbol format is invalid:
+    /// the symbol must hold exactly one underscore with a non-empty currency on each side, so
+    /// `"_USDT"`, `"BTC_"` and `"BTC_USD_T"` are rejected.
     ///
     /// # Examples
     ///
     /// ```
+    /// # use cex_orderbook_collector_rs::Ticker;
     /// let ticker = Ticker::new("BTC_USDT").unwrap();
     /// assert_eq!(ticker.base, "BTC");
     /// assert_eq!(ticker.quote, "USDT");
     /// ```
     pub fn new(symbol: &str) -> Option<Self> {
-        if let Some(index) = symbol.find('_') {
-            let base = &symbol[..index];
-            let quote = &symbol[index+1..];
-            Some(Ticker {
-                base: base.to_string(),
-                quote: quote.to_string()
-            })
-        } else {
-            None
+        let (base, quote) = symbol.split_once('_')?;
+        if base.is_empty() || quote.is_empty() || quote.contains('_') {
+            return None;
         }
+        Some(Ticker {
+            base: base.to_string(),
+            quote: quote.to_string()
+        })
     }
+}
 
-    /// Returns a string representation of the `Ticker`.
+impl fmt::Display for Ticker {
+    /// Formats the `Ticker` as a string.
     ///
     /// The format of the string is "BASE_QUOTE".
     ///
     /// # Examples
     ///
     /// ```
+    /// # use cex_orderbook_collector_rs::Ticker;
     /// let ticker = Ticker::new("BTC_USDT").unwrap();
     /// assert_eq!(ticker.to_string(), "BTC_USDT");
     /// ```
-    pub fn to_string(&self) -> String {
-        format!("{}_{}", self.base, self.quote)
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}_{}", self.base, self.quote)
     }
 }
 
@@ -70,6 +77,15 @@ mod tests {
         assert!(Ticker::new(symbol).is_none());
     }
 
+    #[test]
+    fn test_ticker_new_rejects_empty_or_extra_parts() {
+        assert!(Ticker::new("_USDT").is_none());
+        assert!(Ticker::new("BTC_").is_none());
+        assert!(Ticker::new("_").is_none());
+        assert!(Ticker::new("BTC").is_none());
+        assert!(Ticker::new("BTC_USD_T").is_none());
+    }
+
     #[test]
     fn test_ticker_to_string() {
         let ticker = Ticker {
@@ -0,0 +1,1548 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use std::sync::atomic::{AtomicBool, Ordering};
+use futures_util::StreamExt;
+use tokio::sync::broadcast;
+use tokio_tungstenite::tungstenite::Message;
+use tokio::time::Duration;
+use crate::cex_api::{CexApi, MalformedResponse, RateLimitBan};
+use crate::clock::Clock;
+use crate::config::{Config, FetchRetryConfig};
+use crate::diff_book::{LocalBook, UpdateResult};
+use crate::events::{Event, Events};
+use crate::metrics::Metrics;
+use crate::order_book::{OrderBook, Snapshot};
+use crate::rate_limit::RateLimiter;
+use crate::recorder::{header_record, wrap_response, LatestBooks, Recorder};
+use crate::schedule::{AdaptiveInterval, Schedule};
+use crate::sink::Sink;
+use crate::storage::{create_directory, HourlyFile};
+use crate::ticker::Ticker;
+use tracing::{debug, error, info, warn};
+
+/// The state shared between the collector and a running worker.
+#[derive(Clone)]
+pub struct WorkerContext {
+    /// The exchange API the worker fetches from.
+    pub api: Arc<dyn CexApi>,
+    /// Indicates whether the worker should continue running.
+    pub alive: Arc<AtomicBool>,
+    /// The `Config` the worker was started with.
+    pub config: Arc<Config>,
+    /// The channel on which normalized snapshots are published.
+    pub snapshots: broadcast::Sender<Arc<Snapshot>>,
+    /// The handle for reporting significant events.
+    pub events: Events,
+    /// The latest normalized book of each ticker, shared with the collector.
+    pub latest_books: LatestBooks,
+    /// The sinks every order book record is published to.
+    pub sinks: Vec<Arc<dyn Sink>>,
+    /// The rate limit cooldowns shared by all workers.
+    pub cooldowns: Cooldowns,
+    /// The rate limiter shared by all workers of the exchange.
+    pub rate_limiter: Arc<RateLimiter>,
+    /// The clock that schedules fetches and stamps records.
+    pub clock: Arc<dyn Clock>,
+    /// The pause flags of the collected symbols, shared with the collector.
+    pub pauses: Pauses,
+    /// The collection metrics, shared with the collector.
+    pub metrics: Metrics,
+    /// The time of the last record written for each collected symbol, shared with the collector.
+    pub last_writes: LastWrites,
+}
+
+/// The time until which each exchange must not be contacted after a rate limit ban.
+#[derive(Clone, Default)]
+pub struct Cooldowns {
+    until: Arc<Mutex<HashMap<&'static str, Instant>>>,
+}
+
+impl Cooldowns {
+    /// Pauses all fetches from `exchange` for `duration`, unless a longer cooldown is already active.
+    pub fn start(&self, exchange: &'static str, duration: Duration) {
+        let until = Instant::now() + duration;
+        let mut cooldowns = self.until.lock().unwrap();
+        let entry = cooldowns.entry(exchange).or_insert(until);
+        if *entry < until {
+            *entry = until;
+        }
+    }
+
+    /// Returns the time left of the cooldown of `exchange`, if one is active.
+    pub fn remaining(&self, exchange: &str) -> Option<Duration> {
+        let cooldowns = self.until.lock().unwrap();
+        let until = cooldowns.get(exchange)?;
+        until.checked_duration_since(Instant::now()).filter(|remaining| !remaining.is_zero())
+    }
+}
+
+/// The pause flag of each symbol, set by the collector and checked by the workers collecting the symbol.
+///
+/// A paused worker keeps running on its schedule but skips its fetches, and records nothing.
+#[derive(Clone, Default)]
+pub struct Pauses {
+    flags: Arc<Mutex<HashMap<String, Arc<AtomicBool>>>>,
+}
+
+impl Pauses {
+    /// Returns the pause flag of `symbol`, creating an unset one if needed.
+    pub fn flag(&self, symbol: &str) -> Arc<AtomicBool> {
+        let mut flags = self.flags.lock().unwrap();
+        flags.entry(symbol.to_string()).or_insert_with(|| Arc::new(AtomicBool::new(false))).clone()
+    }
+
+    /// Pauses or resumes every worker collecting `symbol`.
+    pub fn set(&self, symbol: &str, paused: bool) {
+        self.flag(symbol).store(paused, Ordering::SeqCst);
+    }
+
+    /// Returns whether `symbol` is paused.
+    pub fn is_paused(&self, symbol: &str) -> bool {
+        self.flags.lock().unwrap().get(symbol).is_some_and(|flag| flag.load(Ordering::SeqCst))
+    }
+
+    /// Returns the paused symbols.
+    pub fn paused(&self) -> Vec<String> {
+        let flags = self.flags.lock().unwrap();
+        flags.iter().filter(|(_, flag)| flag.load(Ordering::SeqCst)).map(|(symbol, _)| symbol.clone()).collect()
+    }
+}
+
+/// The time of the last order book record written for each symbol on each exchange, in unix milliseconds.
+///
+/// Set by the recorders on every newly fetched book, and read by the collector's stall watchdog.
+#[derive(Clone, Default)]
+pub struct LastWrites {
+    times: Arc<Mutex<HashMap<(String, String), i64>>>,
+}
+
+impl LastWrites {
+    /// Notes that a record of `symbol` on `exchange` was written at `millis`.
+    pub fn record(&self, exchange: &str, symbol: &str, millis: i64) {
+        self.times.lock().unwrap().insert((exchange.to_string(), symbol.to_string()), millis);
+    }
+
+    /// Returns the time of the last record of `symbol` on `exchange`, if one was written.
+    pub fn get(&self, exchange: &str, symbol: &str) -> Option<i64> {
+        self.times.lock().unwrap().get(&(exchange.to_string(), symbol.to_string())).copied()
+    }
+}
+
+impl WorkerContext {
+    /// Returns whether the worker should continue running.
+    fn is_alive(&self) -> bool {
+        self.alive.load(Ordering::SeqCst)
+    }
+
+    /// Waits for the exchange's rate limiter to allow an order book request of `depth` levels.
+    async fn throttle(&self, depth: u32) {
+        self.rate_limiter.acquire(self.api.request_weight(depth)).await;
+    }
+}
+
+/// The worker function for collecting order book data.
+///
+/// # Arguments
+///
+/// * `ticker` - A `Ticker` object representing the asset pair.
+/// * `schedule` - The `Schedule` determining when each snapshot is fetched.
+/// * `ctx` - The `WorkerContext` shared with the collector.
+pub async fn run(ticker: Ticker, schedule: Schedule, ctx: WorkerContext) {
+    let api = ctx.api.clone();
+    let mut timer = FetchTimer::new(&schedule);
+    let mut intended_fetch_millis = match timer.wait(&ctx).await {
+        Some(intended) => intended,
+        None => {
+            info!("No upcoming fetch time for {}", ticker);
+            return;
+        }
+    };
+
+    ctx.events.emit(Event::WorkerStarted { exchange: api.name().to_string(), symbol: ticker.to_string() });
+    let mut recorder = Recorder::new(ticker.clone(), &ctx, &schedule);
+    let mut in_maintenance = false;
+    // Adaptive polling only replaces fixed intervals; cron schedules are kept as configured
+    let mut adaptive = match (&ctx.config.adaptive_interval, &schedule) {
+        (Some(config), Schedule::Interval(_)) => Some(AdaptiveInterval::new(config)),
+        _ => None,
+    };
+
+    while ctx.is_alive() {
+        if wait_out_cooldown(&ctx).await {
+            intended_fetch_millis = ctx.clock.now_millis();
+            continue;
+        }
+        // A paused worker stays aligned to its schedule, so it resumes on the next regular fetch
+        if recorder.is_paused() {
+            match timer.wait(&ctx).await {
+                Some(intended) => intended_fetch_millis = intended,
+                None => break,
+            }
+            continue;
+        }
+        check_skew(&ticker.to_string(), intended_fetch_millis, &ctx);
+
+        let mut mid_price = None;
+        let fetch = with_retry(&ctx.config.fetch_retry, &ctx.alive, || fetch_order_book(&ctx, &ticker));
+        let fetch_error = match with_deadline(ctx.config.tick_deadline.single_ms, fetch).await {
+            Some(Ok(fetched)) => {
+                leave_maintenance(&ctx, &mut in_maintenance);
+                recorder.record_fetched(fetched);
+                mid_price = recorder.last_book().and_then(|book| book.mid_price());
+                None
+            }
+            Some(Err(error)) => {
+                recorder.count_fetch_error();
+                fetch_failure(&ctx, error.as_ref())
+            }
+            None => {
+                warn!(exchange = api.name(), ticker = %ticker, "Fetching order book exceeded the tick deadline");
+                recorder.count_fetch_error();
+                recorder.record_timeout();
+                None
+            }
+        };
+        if let Some(error) = fetch_error {
+            if !back_off_if_maintenance(&ctx, &mut in_maintenance).await {
+                warn!(exchange = api.name(), ticker = %ticker, "Error fetching order book: {}", error);
+                recorder.record_stale();
+            }
+        }
+        if let Some(adaptive) = &mut adaptive {
+            let delay = adaptive.next_delay_millis(mid_price);
+            intended_fetch_millis = ctx.clock.now_millis() + delay as i64;
+            sleep_while_alive(Duration::from_millis(delay), &ctx.alive).await;
+            continue;
+        }
+        match timer.wait(&ctx).await {
+            Some(intended) => intended_fetch_millis = intended,
+            None => {
+                info!("No upcoming fetch time for {}", ticker);
+                break;
+            }
+        }
+    }
+    recorder.close();
+    ctx.events.emit(Event::WorkerStopped { exchange: api.name().to_string(), symbol: ticker.to_string() });
+    info!("Worker for {} is stopped", ticker.base);
+}
+
+/// The worker function for collecting order book data of several tickers with batched requests.
+///
+/// Each batched response is split by the API into one response per ticker, which is then
+/// recorded exactly like a response of the single-ticker worker. A ticker missing from the
+/// response is stale-filled on its own, while a response with more or fewer entries than tickers
+/// fails the whole batch.
+///
+/// # Arguments
+///
+/// * `tickers` - The `Ticker` objects fetched together.
+/// * `schedule` - The `Schedule` determining when each batch is fetched.
+/// * `ctx` - The `WorkerContext` shared with the collector.
+pub async fn run_batch(tickers: Vec<Ticker>, schedule: Schedule, ctx: WorkerContext) {
+    let api = ctx.api.clone();
+    let label = tickers.iter().map(|t| t.to_string()).collect::<Vec<_>>().join("+");
+    let mut timer = FetchTimer::new(&schedule);
+    let mut intended_fetch_millis = match timer.wait(&ctx).await {
+        Some(intended) => intended,
+        None => return,
+    };
+
+    for ticker in &tickers {
+        ctx.events.emit(Event::WorkerStarted { exchange: api.name().to_string(), symbol: ticker.to_string() });
+    }
+    let mut recorders: Vec<Recorder> = tickers.iter()
+        .map(|ticker| Recorder::new(ticker.clone(), &ctx, &schedule))
+        .collect();
+
+    let mut in_maintenance = false;
+
+    while ctx.is_alive() {
+        if wait_out_cooldown(&ctx).await {
+            intended_fetch_millis = ctx.clock.now_millis();
+            continue;
+        }
+        // The batch is fetched while any of its tickers is collected; paused ones record nothing
+        if recorders.iter().all(Recorder::is_paused) {
+            match timer.wait(&ctx).await {
+                Some(intended) => intended_fetch_millis = intended,
+                None => break,
+            }
+            continue;
+        }
+        check_skew(&label, intended_fetch_millis, &ctx);
+
+        let fetch = with_retry(&ctx.config.fetch_retry, &ctx.alive, || async {
+            ctx.throttle(ctx.config.depth).await;
+            let started = Instant::now();
+            let responses = api.get_order_books(&tickers, ctx.config.depth).await;
+            let latency_ms = started.elapsed().as_millis() as u64;
+            let responses = responses
+                .and_then(|responses| match responses.len() == tickers.len() {
+                    true => Ok(responses),
+                    false => Err(format!(
+                        "Batched order book response has {} entries for {} tickers", responses.len(), tickers.len()
+                    ).into()),
+                })
+                .and_then(|responses| responses.into_iter()
+                    .map(|response| response.map(MalformedResponse::check).transpose().map_err(Into::into))
+                    .collect::<Result<Vec<_>, Box<dyn Error>>>());
+            match responses {
+                Ok(responses) => Ok((responses, latency_ms)),
+                Err(error) => {
+                    warn!(exchange = api.name(), ticker = %label, latency_ms, "Order book request failed after {} ms", latency_ms);
+                    Err(error)
+                }
+            }
+        });
+        let fetch_error = match with_deadline(ctx.config.tick_deadline.batch_ms, fetch).await {
+            Some(Ok((responses, latency_ms))) => {
+                leave_maintenance(&ctx, &mut in_maintenance);
+                for (recorder, response) in recorders.iter_mut().zip(responses) {
+                    let checked = response.ok_or_else(|| "Missing ticker in batched order book response".to_string())
+                        .and_then(|response_text| {
+                            check_book_depth(&ctx, recorder.ticker(), ctx.config.depth, &response_text)
+                                .map_err(|error| error.to_string())
+                                .map(|book| FetchedBook { response_text, book, latency_ms })
+                        });
+                    match checked {
+                        Ok(fetched) => recorder.record_fetched(fetched),
+                        Err(error) => {
+                            warn!(exchange = api.name(), ticker = %recorder.ticker(), "{}", error);
+                            recorder.count_fetch_error();
+                            recorder.record_stale();
+                        }
+                    }
+                }
+                None
+            }
+            Some(Err(error)) => {
+                recorders.iter().for_each(Recorder::count_fetch_error);
+                fetch_failure(&ctx, error.as_ref())
+            }
+            None => {
+                warn!(exchange = api.name(), ticker = %label, "Fetching order books exceeded the tick deadline");
+                for recorder in recorders.iter_mut() {
+                    recorder.count_fetch_error();
+                    recorder.record_timeout();
+                }
+                None
+            }
+        };
+        if let Some(error) = fetch_error {
+            if !back_off_if_maintenance(&ctx, &mut in_maintenance).await {
+                warn!(exchange = api.name(), ticker = %label, "Error fetching order books: {}", error);
+                for recorder in recorders.iter_mut() {
+                    recorder.record_stale();
+                }
+            }
+        }
+        match timer.wait(&ctx).await {
+            Some(intended) => intended_fetch_millis = intended,
+            None => break,
+        }
+    }
+    for recorder in recorders.iter_mut() {
+        recorder.close();
+        ctx.events.emit(Event::WorkerStopped { exchange: api.name().to_string(), symbol: recorder.ticker().to_string() });
+    }
+    info!("Worker for batch {} is stopped", label);
+}
+
+/// The outcome of fetching one ticker in a tick of `run_aligned`, holding only `Send` data
+/// so that the fetches of all tickers can be awaited together.
+enum TickFetch {
+    /// The fetched response.
+    Fetched(FetchedBook),
+    /// The error to handle as a regular failure, or `None` for a rate limit ban.
+    Failed(Option<String>),
+    /// The fetch was cancelled at the tick deadline.
+    TimedOut,
+    /// The ticker is paused and was not fetched.
+    Skipped,
+}
+
+/// The worker function fetching the order books of several tickers concurrently on one shared tick.
+///
+/// On every tick, one request per ticker is sent at once and all of them are awaited together; the
+/// responses are then recorded with the time of the tick, so the snapshots of all tickers are aligned
+/// to the same timestamp. Each request still waits for the exchange's rate limiter and is retried and
+/// cancelled at the single-ticker tick deadline on its own.
+///
+/// # Arguments
+///
+/// * `tickers` - The `Ticker` objects fetched on the same tick.
+/// * `schedule` - The `Schedule` determining when each tick fires.
+/// * `ctx` - The `WorkerContext` shared with the collector.
+pub async fn run_aligned(tickers: Vec<Ticker>, schedule: Schedule, ctx: WorkerContext) {
+    let label = tickers.iter().map(|t| t.to_string()).collect::<Vec<_>>().join("+");
+    let books = tickers.into_iter().map(|ticker| (ticker, None)).collect();
+    run_shared_tick(&label, books, schedule, ctx).await;
+    info!("Worker for aligned tickers {} is stopped", label);
+}
+
+/// The worker function fetching a ticker's order book at several depths on one shared tick.
+///
+/// The main depth, `ctx.config.depth`, is recorded like a single-depth ticker's book. Each of
+/// `extra_depths` is requested at the same time, recorded with the same tick time and written to
+/// its own directory, see `Recorder::for_depth`. Every request goes through the exchange's rate
+/// limiter with the weight of its depth.
+///
+/// # Arguments
+///
+/// * `ticker` - A `Ticker` object representing the asset pair.
+/// * `extra_depths` - The depths fetched next to the main depth.
+/// * `schedule` - The `Schedule` determining when each tick fires.
+/// * `ctx` - The `WorkerContext` shared with the collector.
+pub async fn run_depths(ticker: Ticker, extra_depths: Vec<u32>, schedule: Schedule, ctx: WorkerContext) {
+    let books = std::iter::once((ticker.clone(), None))
+        .chain(extra_depths.into_iter().map(|depth| (ticker.clone(), Some(depth))))
+        .collect();
+    run_shared_tick(&ticker.to_string(), books, schedule, ctx).await;
+    info!("Worker for {} is stopped", ticker.base);
+}
+
+/// Fetches and records several order books concurrently on one shared tick until the worker is stopped.
+///
+/// # Arguments
+///
+/// * `label` - The name of the group in log messages.
+/// * `books` - The tickers to fetch, each with the extra depth it is fetched at, or `None` for its main depth.
+/// * `schedule` - The `Schedule` determining when each tick fires.
+/// * `ctx` - The `WorkerContext` shared with the collector.
+async fn run_shared_tick(label: &str, books: Vec<(Ticker, Option<u32>)>, schedule: Schedule, ctx: WorkerContext) {
+    let api = ctx.api.clone();
+    let mut timer = FetchTimer::new(&schedule);
+    let mut intended_fetch_millis = match timer.wait(&ctx).await {
+        Some(intended) => intended,
+        None => return,
+    };
+
+    for (ticker, _) in books.iter().filter(|(_, depth)| depth.is_none()) {
+        ctx.events.emit(Event::WorkerStarted { exchange: api.name().to_string(), symbol: ticker.to_string() });
+    }
+    let mut recorders: Vec<Recorder> = books.into_iter()
+        .map(|(ticker, depth)| match depth {
+            Some(depth) => Recorder::for_depth(ticker, &ctx, &schedule, depth),
+            None => Recorder::new(ticker, &ctx, &schedule),
+        })
+        .collect();
+
+    let mut in_maintenance = false;
+
+    while ctx.is_alive() {
+        if wait_out_cooldown(&ctx).await {
+            intended_fetch_millis = ctx.clock.now_millis();
+            continue;
+        }
+        check_skew(label, intended_fetch_millis, &ctx);
+
+        let tick_timestamp = ctx.config.timestamp_unit.timestamp(ctx.clock.now_millis());
+        let fetches = recorders.iter().map(|recorder| {
+            let (ctx, ticker, depth, paused) = (&ctx, recorder.ticker(), recorder.depth(), recorder.is_paused());
+            async move {
+                if paused {
+                    return TickFetch::Skipped;
+                }
+                let fetch = with_retry(&ctx.config.fetch_retry, &ctx.alive, || fetch_order_book_at(ctx, ticker, depth));
+                match with_deadline(ctx.config.tick_deadline.single_ms, fetch).await {
+                    Some(Ok(fetched)) => TickFetch::Fetched(fetched),
+                    Some(Err(error)) => TickFetch::Failed(fetch_failure(ctx, error.as_ref())),
+                    None => TickFetch::TimedOut,
+                }
+            }
+        });
+        let outcomes = futures_util::future::join_all(fetches).await;
+
+        let any_fetched = outcomes.iter().any(|outcome| matches!(outcome, TickFetch::Fetched(..)));
+        let any_failed = outcomes.iter().any(|outcome| matches!(outcome, TickFetch::Failed(Some(_))));
+        if any_fetched {
+            leave_maintenance(&ctx, &mut in_maintenance);
+        }
+        // A tick where every request failed may be a maintenance window rather than per-ticker failures
+        let in_backoff = !any_fetched && any_failed && back_off_if_maintenance(&ctx, &mut in_maintenance).await;
+        for (recorder, outcome) in recorders.iter_mut().zip(outcomes) {
+            if matches!(outcome, TickFetch::Failed(_) | TickFetch::TimedOut) {
+                recorder.count_fetch_error();
+            }
+            match outcome {
+                TickFetch::Fetched(fetched) => recorder.record_fetched_at(tick_timestamp, fetched),
+                TickFetch::Failed(Some(error)) if !in_backoff => {
+                    warn!(exchange = api.name(), ticker = %recorder.ticker(), "Error fetching order book: {}", error);
+                    recorder.record_stale();
+                }
+                TickFetch::TimedOut => {
+                    warn!(exchange = api.name(), ticker = %recorder.ticker(), "Fetching order book exceeded the tick deadline");
+                    recorder.record_timeout();
+                }
+                TickFetch::Failed(_) | TickFetch::Skipped => {}
+            }
+        }
+        match timer.wait(&ctx).await {
+            Some(intended) => intended_fetch_millis = intended,
+            None => break,
+        }
+    }
+    for recorder in recorders.iter_mut() {
+        recorder.close();
+        if recorder.extra_depth().is_none() {
+            ctx.events.emit(Event::WorkerStopped { exchange: api.name().to_string(), symbol: recorder.ticker().to_string() });
+        }
+    }
+}
+
+/// Fetches and records a single order book of a ticker, e.g. for `--once`.
+///
+/// The fetch goes through the same rate limiter, retries and tick deadline as the collection loop.
+///
+/// # Arguments
+///
+/// * `ticker` - A `Ticker` object representing the asset pair.
+/// * `ctx` - The `WorkerContext` shared with the collector.
+///
+/// # Returns
+///
+/// `true` if the order book was fetched and recorded.
+pub async fn run_once(ticker: Ticker, ctx: WorkerContext) -> bool {
+    let schedule = Schedule::Interval(ctx.api.get_order_book_interval());
+    let mut recorder = Recorder::new(ticker.clone(), &ctx, &schedule);
+    let fetch = with_retry(&ctx.config.fetch_retry, &ctx.alive, || fetch_order_book(&ctx, &ticker));
+    let fetched = match with_deadline(ctx.config.tick_deadline.single_ms, fetch).await {
+        Some(Ok(fetched)) => {
+            recorder.record_fetched(fetched);
+            true
+        }
+        Some(Err(error)) => {
+            error!(exchange = ctx.api.name(), ticker = %ticker, "Error fetching order book: {}", error);
+            false
+        }
+        None => {
+            error!(exchange = ctx.api.name(), ticker = %ticker, "Fetching order book exceeded the tick deadline");
+            false
+        }
+    };
+    recorder.close();
+    fetched
+}
+
+/// The worker function for collecting mark and index prices.
+///
+/// Records are written into the `mark` subdirectory of the ticker's data directory,
+/// using the same hourly files and record format as the order book worker. Fetches are skipped while the ticker is paused.
+///
+/// # Arguments
+///
+/// * `ticker` - A `Ticker` object representing the asset pair.
+/// * `interval` - The interval in seconds between mark price fetches.
+/// * `ctx` - The `WorkerContext` shared with the collector.
+pub async fn run_mark_price(ticker: Ticker, interval: u64, ctx: WorkerContext) {
+    let api = ctx.api.clone();
+    let schedule = Schedule::Interval(interval);
+    let mut timer = FetchTimer::new(&schedule);
+    if timer.wait(&ctx).await.is_none() {
+        return;
+    }
+
+    let dir = format!("{}/mark", ctx.config.ticker_dir(api.name(), &ticker.to_string()));
+    create_directory(dir.as_str());
+    let mut file = HourlyFile::from_config(&dir, &ctx.config, api.name(), &ticker.to_string());
+    if ctx.config.file_header {
+        file.set_header(header_record(api.name(), &ticker.to_string(), None, schedule.describe()));
+    }
+
+    let paused = ctx.pauses.flag(&ticker.to_string());
+    while ctx.is_alive() {
+        if !paused.load(Ordering::SeqCst) {
+            ctx.rate_limiter.acquire(1).await;
+            match api.get_mark_price(&ticker).await {
+                Ok(response_text) => {
+                    let timestamp = ctx.config.timestamp_unit.timestamp(ctx.clock.now_millis());
+                    let json_data = wrap_response(timestamp, response_text.trim_end_matches('\n'));
+                    if let Err(e) = file.write(timestamp, &json_data) {
+                        error!(exchange = api.name(), ticker = %ticker, "Cannot write mark price: {}", e);
+                    }
+                }
+                Err(error) => {
+                    warn!(exchange = api.name(), ticker = %ticker, "Error fetching mark price: {:?}", error);
+                }
+            }
+        }
+        if timer.wait(&ctx).await.is_none() {
+            break;
+        }
+    }
+    if let Err(e) = file.close() {
+        error!("Cannot close mark price file of {}: {}", ticker, e);
+    }
+    info!("Mark price worker for {} is stopped", ticker.base);
+}
+
+/// The longest delay between two reconnection attempts of a stream.
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+
+/// The worker function for streaming liquidation events.
+///
+/// Events are written into the `liquidations` subdirectory of the ticker's data directory,
+/// one record per received message. The stream is reconnected with an exponential backoff
+/// whenever it fails or is closed by the exchange. While the ticker is paused, events are dropped.
+///
+/// # Arguments
+///
+/// * `ticker` - A `Ticker` object representing the contract's trading pair.
+/// * `ctx` - The `WorkerContext` shared with the collector.
+pub async fn run_liquidations(ticker: Ticker, ctx: WorkerContext) {
+    let api = ctx.api.clone();
+    let url = match api.liquidation_stream_url(&ticker) {
+        Some(url) => url,
+        None => {
+            warn!("{} does not provide liquidation events for {}", api.name(), ticker);
+            return;
+        }
+    };
+
+    let dir = format!("{}/liquidations", ctx.config.ticker_dir(api.name(), &ticker.to_string()));
+    create_directory(dir.as_str());
+    let mut file = HourlyFile::from_config(&dir, &ctx.config, api.name(), &ticker.to_string());
+    if ctx.config.file_header {
+        file.set_header(header_record(api.name(), &ticker.to_string(), None, serde_json::Value::Null));
+    }
+
+    let paused = ctx.pauses.flag(&ticker.to_string());
+    let mut reconnect_delay = Duration::from_secs(1);
+    while ctx.is_alive() {
+        match tokio_tungstenite::connect_async(url.as_str()).await {
+            Ok((mut stream, _)) => {
+                info!("Streaming liquidations of {}", ticker);
+                reconnect_delay = Duration::from_secs(1);
+                // Wake up regularly so a stopped worker does not wait for the next event
+                while ctx.is_alive() {
+                    match tokio::time::timeout(Duration::from_secs(1), stream.next()).await {
+                        Err(_) => continue,
+                        Ok(Some(Ok(Message::Text(_)))) if paused.load(Ordering::SeqCst) => {}
+                        Ok(Some(Ok(Message::Text(text)))) => {
+                            let timestamp = ctx.config.timestamp_unit.timestamp(ctx.clock.now_millis());
+                            if let Err(e) = file.write(timestamp, &wrap_response(timestamp, text.as_str())) {
+                                error!("Cannot write liquidation of {}: {}", ticker, e);
+                            }
+                        }
+                        Ok(Some(Ok(_))) => {}
+                        Ok(Some(Err(error))) => {
+                            warn!("Liquidation stream of {} failed: {}", ticker, error);
+                            break;
+                        }
+                        Ok(None) => {
+                            warn!("Liquidation stream of {} closed", ticker);
+                            break;
+                        }
+                    }
+                }
+            }
+            Err(error) => warn!("Cannot connect liquidation stream of {}: {}", ticker, error),
+        }
+        sleep_while_alive(reconnect_delay, &ctx.alive).await;
+        reconnect_delay = (reconnect_delay * 2).min(MAX_RECONNECT_DELAY);
+    }
+    if let Err(e) = file.close() {
+        error!("Cannot close liquidation file of {}: {}", ticker, e);
+    }
+    info!("Liquidation worker for {} is stopped", ticker.base);
+}
+
+/// How long a dropped depth stream may take to reconnect before the worker falls back to polling.
+const STREAM_RECONNECT_WINDOW: Duration = Duration::from_secs(5);
+
+/// How long a worker that fell back to polling keeps polling before it tries the depth stream again.
+const STREAM_RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The worker function for collecting order book data from the exchange's depth stream.
+///
+/// Every text message of the stream is recorded like a polled response. When the stream drops
+/// and cannot be reconnected within `STREAM_RECONNECT_WINDOW`, the worker polls on its schedule
+/// for `STREAM_RETRY_INTERVAL` before trying the stream again, so collection never stops.
+///
+/// # Arguments
+///
+/// * `ticker` - A `Ticker` object representing the asset pair.
+/// * `schedule` - The `Schedule` used while falling back to polling.
+/// * `ctx` - The `WorkerContext` shared with the collector.
+pub async fn run_stream(ticker: Ticker, schedule: Schedule, ctx: WorkerContext) {
+    let api = ctx.api.clone();
+    let url = match api.depth_stream_url(&ticker, ctx.config.depth) {
+        Some(url) => url,
+        None => {
+            info!("{} does not provide a depth stream for {}, polling instead", api.name(), ticker);
+            return run(ticker, schedule, ctx).await;
+        }
+    };
+
+    ctx.events.emit(Event::WorkerStarted { exchange: api.name().to_string(), symbol: ticker.to_string() });
+    let mut recorder = Recorder::new(ticker.clone(), &ctx, &schedule);
+    while ctx.is_alive() {
+        match connect_within(&url, STREAM_RECONNECT_WINDOW, &ctx).await {
+            Some(mut stream) => {
+                info!("Streaming order book of {}", ticker);
+                // Wake up regularly so a stopped worker does not wait for the next message
+                while ctx.is_alive() {
+                    match tokio::time::timeout(Duration::from_secs(1), stream.next()).await {
+                        Err(_) => continue,
+                        Ok(Some(Ok(Message::Text(text)))) => recorder.record(text.as_str(), None),
+                        Ok(Some(Ok(_))) => {}
+                        Ok(Some(Err(error))) => {
+                            warn!("Depth stream of {} failed: {}", ticker, error);
+                            break;
+                        }
+                        Ok(None) => {
+                            warn!("Depth stream of {} closed", ticker);
+                            break;
+                        }
+                    }
+                }
+            }
+            None if ctx.is_alive() => {
+                warn!("Cannot connect depth stream of {}, falling back to polling", ticker);
+                poll_until(&ticker, &schedule, &ctx, &mut recorder, Instant::now() + STREAM_RETRY_INTERVAL).await;
+            }
+            None => {}
+        }
+    }
+    recorder.close();
+    ctx.events.emit(Event::WorkerStopped { exchange: api.name().to_string(), symbol: ticker.to_string() });
+    info!("Stream worker for {} is stopped", ticker.base);
+}
+
+/// The WebSocket stream type returned by `tokio_tungstenite::connect_async`.
+type WebSocket = tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>;
+
+/// Tries to connect to a WebSocket until `window` has passed.
+///
+/// # Returns
+///
+/// The connected stream, or `None` if no attempt succeeded in time or the worker was stopped.
+async fn connect_within(url: &str, window: Duration, ctx: &WorkerContext) -> Option<WebSocket> {
+    let deadline = Instant::now() + window;
+    while ctx.is_alive() {
+        let remaining = deadline.checked_duration_since(Instant::now())?;
+        match tokio::time::timeout(remaining, tokio_tungstenite::connect_async(url)).await {
+            Ok(Ok((stream, _))) => return Some(stream),
+            Ok(Err(error)) => warn!("Cannot connect to {}: {}", url, error),
+            Err(_) => return None,
+        }
+        sleep_while_alive(Duration::from_secs(1).min(remaining), &ctx.alive).await;
+    }
+    None
+}
+
+/// Polls the order book on the worker's schedule until `until`, as the fallback of a stream worker.
+async fn poll_until(ticker: &Ticker, schedule: &Schedule, ctx: &WorkerContext, recorder: &mut Recorder, until: Instant) {
+    let mut timer = FetchTimer::new(schedule);
+    while ctx.is_alive() && Instant::now() < until {
+        if wait_out_cooldown(ctx).await {
+            continue;
+        }
+        if recorder.is_paused() {
+            if timer.wait(ctx).await.is_none() {
+                break;
+            }
+            continue;
+        }
+        let fetch = with_retry(&ctx.config.fetch_retry, &ctx.alive, || fetch_order_book(ctx, ticker));
+        match with_deadline(ctx.config.tick_deadline.single_ms, fetch).await {
+            Some(Ok(fetched)) => recorder.record_fetched(fetched),
+            Some(Err(error)) => {
+                recorder.count_fetch_error();
+                if let Some(error) = fetch_failure(ctx, error.as_ref()) {
+                    warn!(exchange = ctx.api.name(), ticker = %ticker, "Error fetching order book: {}", error);
+                    recorder.record_stale();
+                }
+            }
+            None => {
+                warn!(exchange = ctx.api.name(), ticker = %ticker, "Fetching order book exceeded the tick deadline");
+                recorder.count_fetch_error();
+                recorder.record_timeout();
+            }
+        }
+        if timer.wait(ctx).await.is_none() {
+            break;
+        }
+    }
+}
+
+/// The number of levels per side of the REST snapshot a diff-maintained book starts from.
+const DIFF_SNAPSHOT_DEPTH: u32 = 1000;
+
+/// The worker function maintaining a ticker's order book from the exchange's diff depth stream.
+///
+/// The stream is opened first and buffered while a REST snapshot is fetched; the book is then
+/// built from the snapshot and the buffered events and kept up to date by every later event.
+/// The maintained book is recorded after each applied event. If the update ids show a gap, or the
+/// stream fails, the connection is dropped and the book is rebuilt from a new snapshot.
+///
+/// # Arguments
+///
+/// * `ticker` - A `Ticker` object representing the asset pair.
+/// * `schedule` - The ticker's `Schedule`, only reported in file headers.
+/// * `ctx` - The `WorkerContext` shared with the collector.
+pub async fn run_diff_book(ticker: Ticker, schedule: Schedule, ctx: WorkerContext) {
+    let api = ctx.api.clone();
+    let url = match api.diff_stream_url(&ticker) {
+        Some(url) => url,
+        None => {
+            warn!("{} does not provide a diff depth stream for {}", api.name(), ticker);
+            return;
+        }
+    };
+
+    ctx.events.emit(Event::WorkerStarted { exchange: api.name().to_string(), symbol: ticker.to_string() });
+    let mut recorder = Recorder::new(ticker.clone(), &ctx, &schedule);
+    let mut reconnect_delay = Duration::from_secs(1);
+    while ctx.is_alive() {
+        if wait_out_cooldown(&ctx).await {
+            continue;
+        }
+        let mut stream = match tokio_tungstenite::connect_async(url.as_str()).await {
+            Ok((stream, _)) => stream,
+            Err(error) => {
+                warn!("Cannot connect diff depth stream of {}: {}", ticker, error);
+                sleep_while_alive(reconnect_delay, &ctx.alive).await;
+                reconnect_delay = (reconnect_delay * 2).min(MAX_RECONNECT_DELAY);
+                continue;
+            }
+        };
+
+        // Buffer the stream while the snapshot is fetched, so no event between the two is lost
+        let mut buffered = Vec::new();
+        // The snapshot is scoped so that its error, which is not `Send`, is dropped before the next await
+        let local = {
+            let snapshot = async {
+                ctx.throttle(DIFF_SNAPSHOT_DEPTH).await;
+                api.get_order_book(&ticker, DIFF_SNAPSHOT_DEPTH).await
+            };
+            tokio::pin!(snapshot);
+            let snapshot = loop {
+                tokio::select! {
+                    result = &mut snapshot => break Some(result),
+                    message = stream.next() => match message {
+                        Some(Ok(Message::Text(text))) => match api.parse_depth_update(text.as_str()) {
+                            Ok(update) => buffered.push(update),
+                            Err(error) => warn!("Invalid depth update of {}: {:?}", ticker, error),
+                        },
+                        Some(Ok(_)) => {}
+                        Some(Err(error)) => {
+                            warn!("Diff depth stream of {} failed: {}", ticker, error);
+                            break None;
+                        }
+                        None => break None,
+                    },
+                }
+            };
+            match snapshot {
+                Some(Ok(text)) => match (api.parse_order_book(&text), api.parse_snapshot_update_id(&text)) {
+                    (Ok(book), Ok(last_update_id)) => LocalBook::from_snapshot(book, last_update_id, &buffered),
+                    (Err(error), _) | (_, Err(error)) => {
+                        warn!("Invalid order book snapshot of {}: {:?}", ticker, error);
+                        None
+                    }
+                },
+                Some(Err(error)) => {
+                    if let Some(error) = fetch_failure(&ctx, error.as_ref()) {
+                        warn!(exchange = api.name(), ticker = %ticker, "Error fetching order book snapshot: {}", error);
+                    }
+                    None
+                }
+                None => None,
+            }
+        };
+        let mut local = match local {
+            Some(local) => local,
+            None => {
+                info!("Could not sync the order book of {}, retrying", ticker);
+                sleep_while_alive(reconnect_delay, &ctx.alive).await;
+                reconnect_delay = (reconnect_delay * 2).min(MAX_RECONNECT_DELAY);
+                continue;
+            }
+        };
+        info!("Synced the order book of {} at update {}", ticker, local.last_update_id());
+        reconnect_delay = Duration::from_secs(1);
+        recorder.record_book(local.book());
+
+        // Wake up regularly so a stopped worker does not wait for the next event
+        while ctx.is_alive() {
+            match tokio::time::timeout(Duration::from_secs(1), stream.next()).await {
+                Err(_) => continue,
+                Ok(Some(Ok(Message::Text(text)))) => {
+                    let update = match api.parse_depth_update(text.as_str()) {
+                        Ok(update) => update,
+                        Err(error) => {
+                            warn!("Invalid depth update of {}: {:?}", ticker, error);
+                            continue;
+                        }
+                    };
+                    match local.apply(&update) {
+                        UpdateResult::Applied => recorder.record_book(local.book()),
+                        UpdateResult::Ignored => {}
+                        UpdateResult::Gap => {
+                            warn!(
+                                "Gap in the diff depth stream of {}: update {} followed update {}, resyncing",
+                                ticker, update.first_update_id, local.last_update_id()
+                            );
+                            break;
+                        }
+                    }
+                }
+                Ok(Some(Ok(_))) => {}
+                Ok(Some(Err(error))) => {
+                    warn!("Diff depth stream of {} failed: {}", ticker, error);
+                    break;
+                }
+                Ok(None) => {
+                    warn!("Diff depth stream of {} closed", ticker);
+                    break;
+                }
+            }
+        }
+    }
+    recorder.close();
+    ctx.events.emit(Event::WorkerStopped { exchange: api.name().to_string(), symbol: ticker.to_string() });
+    info!("Diff book worker for {} is stopped", ticker.base);
+}
+
+/// A fetched order book response, with the book parsed from it when it was checked.
+pub struct FetchedBook {
+    /// The raw response, checked to be valid JSON.
+    pub response_text: String,
+    /// The book parsed from the response, or `None` if it cannot be parsed.
+    pub book: Option<OrderBook>,
+    /// The time the exchange took to serve the response, in milliseconds.
+    pub latency_ms: u64,
+}
+
+/// Fetches one order book attempt at the worker's configured depth, see `fetch_order_book_at`.
+async fn fetch_order_book(ctx: &WorkerContext, ticker: &Ticker) -> Result<FetchedBook, Box<dyn Error>> {
+    fetch_order_book_at(ctx, ticker, ctx.config.depth).await
+}
+
+/// Fetches one order book attempt at `depth` once the rate limiter allows it, timing the request.
+///
+/// A failed attempt is logged with its latency, as it is not recorded. An empty book counts as a
+/// failed attempt, see `check_book_depth`.
+///
+/// # Returns
+///
+/// The raw response with its parsed book and the time the exchange took to serve it.
+async fn fetch_order_book_at(ctx: &WorkerContext, ticker: &Ticker, depth: u32) -> Result<FetchedBook, Box<dyn Error>> {
+    ctx.throttle(depth).await;
+    let started = Instant::now();
+    let response = ctx.api.get_order_book(ticker, depth).await;
+    let latency_ms = started.elapsed().as_millis() as u64;
+    let checked = response.and_then(|response_text| {
+        let response_text = MalformedResponse::check(response_text)?;
+        let book = check_book_depth(ctx, ticker, depth, &response_text)?;
+        Ok(FetchedBook { response_text, book, latency_ms })
+    });
+    match checked {
+        Ok(fetched) => Ok(fetched),
+        Err(error) => {
+            warn!(exchange = ctx.api.name(), ticker = %ticker, latency_ms, "Order book request failed after {} ms", latency_ms);
+            Err(error)
+        }
+    }
+}
+
+/// Parses a fetched book and compares its levels to the requested `depth`.
+///
+/// A short book is valid, e.g. on a thin market, and is only logged at debug level. A book with
+/// no level on either side is an error, so it is retried and handled like a failed fetch. A response
+/// that cannot be parsed passes, as the recorder stores and reports it.
+///
+/// # Returns
+///
+/// The parsed book, handed to the recorder so the response is parsed only once, or `None` if the
+/// response cannot be parsed.
+///
+/// # Errors
+///
+/// Returns an error if both sides of the book are empty.
+fn check_book_depth(ctx: &WorkerContext, ticker: &Ticker, depth: u32, response_text: &str) -> Result<Option<OrderBook>, Box<dyn Error>> {
+    let Ok(book) = ctx.api.parse_order_book(response_text) else {
+        return Ok(None);
+    };
+    if book.is_empty() {
+        return Err(format!("Empty order book of {} on {}", ticker, ctx.api.name()).into());
+    }
+    if book.is_short(depth) {
+        debug!(
+            exchange = ctx.api.name(), ticker = %ticker,
+            "Requested {} levels, got {} bids and {} asks", depth, book.bids.len(), book.asks.len()
+        );
+    }
+    Ok(Some(book))
+}
+
+/// Handles a fetch error, starting the exchange-wide cooldown if it is a rate limit ban.
+///
+/// # Returns
+///
+/// The error message to handle as a regular failure, or `None` for a ban.
+fn fetch_failure(ctx: &WorkerContext, error: &(dyn Error + 'static)) -> Option<String> {
+    match error.downcast_ref::<RateLimitBan>() {
+        Some(ban) => {
+            let cooldown = ban.retry_after.unwrap_or(Duration::from_secs(ctx.config.ban_cooldown_secs));
+            if ctx.cooldowns.remaining(ctx.api.name()).is_none() {
+                error!(
+                    "!!! {} banned this IP for exceeding its rate limits; pausing all its workers for {} s !!!",
+                    ctx.api.name(), cooldown.as_secs()
+                );
+                ctx.events.emit(Event::RateLimitBan {
+                    exchange: ctx.api.name().to_string(),
+                    cooldown_secs: cooldown.as_secs(),
+                });
+            }
+            ctx.cooldowns.start(ctx.api.name(), cooldown);
+            None
+        }
+        None => Some(format!("{:?}", error)),
+    }
+}
+
+/// Sleeps until the exchange's rate limit cooldown is over, if one is active.
+///
+/// # Returns
+///
+/// `true` if the worker waited and should re-check whether it is still alive before fetching.
+async fn wait_out_cooldown(ctx: &WorkerContext) -> bool {
+    match ctx.cooldowns.remaining(ctx.api.name()) {
+        Some(remaining) => {
+            sleep_while_alive(remaining, &ctx.alive).await;
+            true
+        }
+        None => false,
+    }
+}
+
+/// Checks whether a failed fetch was caused by exchange maintenance and, if so, backs off.
+///
+/// The first detection logs that the exchange is in maintenance; subsequent failures during
+/// the same window only back off, so the log is not flooded at full cadence.
+///
+/// # Returns
+///
+/// `true` if the exchange is in maintenance and the worker has backed off.
+async fn back_off_if_maintenance(ctx: &WorkerContext, in_maintenance: &mut bool) -> bool {
+    if !ctx.api.is_in_maintenance().await {
+        return false;
+    }
+    if !*in_maintenance {
+        warn!(
+            "Exchange {} in maintenance, backing off to every {} s",
+            ctx.api.name(), ctx.config.maintenance_interval_secs
+        );
+        ctx.events.emit(Event::MaintenanceStarted { exchange: ctx.api.name().to_string() });
+        *in_maintenance = true;
+    }
+    sleep_while_alive(Duration::from_secs(ctx.config.maintenance_interval_secs), &ctx.alive).await;
+    true
+}
+
+/// Logs the end of a maintenance window after a successful fetch.
+fn leave_maintenance(ctx: &WorkerContext, in_maintenance: &mut bool) {
+    if *in_maintenance {
+        info!("Exchange {} recovered from maintenance, resuming normal cadence", ctx.api.name());
+        ctx.events.emit(Event::MaintenanceEnded { exchange: ctx.api.name().to_string() });
+        *in_maintenance = false;
+    }
+}
+
+/// Runs `fetch` until it succeeds, retrying failures with exponential backoff.
+///
+/// A failed attempt is logged and retried after `base_delay_ms`, doubled before each further retry,
+/// up to `max_retries` times. Rate limit bans are not retried, and the backoff ends early once
+/// `alive` is cleared so a shutdown is not delayed.
+///
+/// # Returns
+///
+/// The first successful result, or the error of the last attempt.
+async fn with_retry<T, F, Fut>(retry: &FetchRetryConfig, alive: &AtomicBool, mut fetch: F) -> Result<T, Box<dyn Error>>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, Box<dyn Error>>>,
+{
+    let mut attempt = 0;
+    loop {
+        // The error is not `Send`, so it must not be held across the backoff
+        let delay = {
+            let error = match fetch().await {
+                Ok(result) => return Ok(result),
+                Err(error) => error,
+            };
+            let banned = error.downcast_ref::<RateLimitBan>().is_some();
+            if attempt >= retry.max_retries || banned || !alive.load(Ordering::SeqCst) {
+                return Err(error);
+            }
+            let delay = retry.base_delay_ms.saturating_mul(1 << attempt.min(16));
+            attempt += 1;
+            warn!("Fetch failed ({}), retry {}/{} in {} ms", error, attempt, retry.max_retries, delay);
+            delay
+        };
+        sleep_while_alive(Duration::from_millis(delay), alive).await;
+    }
+}
+
+/// Awaits `fetch`, cancelling it once `deadline_ms` milliseconds have passed if a deadline is set.
+///
+/// # Returns
+///
+/// The output of `fetch`, or `None` if it was cancelled at the deadline.
+async fn with_deadline<T>(deadline_ms: Option<u64>, fetch: impl Future<Output = T>) -> Option<T> {
+    match deadline_ms {
+        Some(deadline_ms) => tokio::time::timeout(Duration::from_millis(deadline_ms), fetch).await.ok(),
+        None => Some(fetch.await),
+    }
+}
+
+/// Sleeps for `duration`, returning early once `alive` is cleared.
+///
+/// `alive` is checked every 100 ms, and the sleep ends at a fixed deadline so the checks do not
+/// make it run late.
+async fn sleep_while_alive(duration: Duration, alive: &AtomicBool) {
+    let step = Duration::from_millis(100);
+    let deadline = tokio::time::Instant::now() + duration;
+    while alive.load(Ordering::SeqCst) {
+        let now = tokio::time::Instant::now();
+        if now >= deadline {
+            break;
+        }
+        tokio::time::sleep_until(deadline.min(now + step)).await;
+    }
+}
+
+/// Logs a warning when a fetch started later than its intended time by more than the configured threshold.
+fn check_skew(label: &str, intended_fetch_millis: i64, ctx: &WorkerContext) {
+    if let Some(max_skew_ms) = ctx.config.max_skew_ms {
+        let skew = ctx.clock.now_millis() - intended_fetch_millis;
+        if skew > max_skew_ms as i64 {
+            warn!(
+                "Fetch for {} on {} started {} ms late (threshold {} ms); the machine may not keep up with the configured frequency",
+                label, ctx.api.name(), skew, max_skew_ms
+            );
+        }
+    }
+}
+
+/// The fetch times of a worker loop, anchored to the wall-clock boundaries of its schedule.
+///
+/// Each fetch time follows from the previous one instead of from when the last fetch returned,
+/// so variable fetch durations neither shift nor repeat ticks; the ticks a slow fetch ran past
+/// are skipped.
+struct FetchTimer<'a> {
+    schedule: &'a Schedule,
+    /// The previous fetch time in unix milliseconds.
+    last_millis: Option<i64>,
+}
+
+impl<'a> FetchTimer<'a> {
+    fn new(schedule: &'a Schedule) -> FetchTimer<'a> {
+        FetchTimer { schedule, last_millis: None }
+    }
+
+    /// Sleeps until the next fetch time, as told by the worker's clock, or until the worker is asked
+    /// to stop, so a long interval or cron gap does not outlast the stop grace period.
+    ///
+    /// # Returns
+    ///
+    /// The intended fetch time in unix milliseconds, or `None` if the schedule has no upcoming fetch time.
+    async fn wait(&mut self, ctx: &WorkerContext) -> Option<i64> {
+        let (intended, delay) = self.next(ctx.clock.as_ref())?;
+        if delay > 0 {
+            sleep_while_alive(Duration::from_millis(delay), &ctx.alive).await;
+        }
+        Some(intended)
+    }
+
+    /// Returns the next fetch time in unix milliseconds and the delay until then, and moves past it.
+    fn next(&mut self, clock: &dyn Clock) -> Option<(i64, u64)> {
+        let now = clock.now_millis();
+        let intended = self.schedule.next_fetch_millis(now, self.last_millis)?;
+        self.last_millis = Some(intended);
+        Some((intended, (intended - now).max(0) as u64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+
+    /// An exchange that is never contacted, for tests of the recording logic.
+    struct StubApi;
+
+    #[async_trait::async_trait]
+    impl CexApi for StubApi {
+        fn name(&self) -> &'static str {
+            "STUB"
+        }
+
+        async fn get_order_book(&self, _symbol: &Ticker, _depth: u32) -> Result<String, Box<dyn Error>> {
+            Err("stub".into())
+        }
+
+        fn parse_order_book(&self, _raw: &str) -> Result<OrderBook, Box<dyn Error>> {
+            Err("stub".into())
+        }
+
+        fn get_order_book_interval(&self) -> u64 {
+            1
+        }
+    }
+
+    /// An exchange answering batched requests from a script, one batch per call, that stops the
+    /// worker once the script is used up. Each call advances the clock to the next tick.
+    struct BatchApi {
+        batches: Mutex<Vec<Vec<Option<String>>>>,
+        clock: Arc<MockClock>,
+        alive: Arc<AtomicBool>,
+    }
+
+    #[async_trait::async_trait]
+    impl CexApi for BatchApi {
+        fn name(&self) -> &'static str {
+            "STUB"
+        }
+
+        async fn get_order_book(&self, _symbol: &Ticker, _depth: u32) -> Result<String, Box<dyn Error>> {
+            Err("stub".into())
+        }
+
+        fn max_batch_size(&self) -> usize {
+            10
+        }
+
+        async fn get_order_books(&self, _symbols: &[Ticker], _depth: u32) -> Result<Vec<Option<String>>, Box<dyn Error>> {
+            self.clock.advance(1000);
+            let mut batches = self.batches.lock().unwrap();
+            let batch = batches.remove(0);
+            if batches.is_empty() {
+                self.alive.store(false, Ordering::SeqCst);
+            }
+            Ok(batch)
+        }
+
+        fn parse_order_book(&self, _raw: &str) -> Result<OrderBook, Box<dyn Error>> {
+            Err("stub".into())
+        }
+
+        fn get_order_book_interval(&self) -> u64 {
+            1
+        }
+    }
+
+    fn mock_context(clock: Arc<MockClock>, output_dir: &std::path::Path) -> WorkerContext {
+        WorkerContext {
+            api: Arc::new(StubApi),
+            alive: Arc::new(AtomicBool::new(true)),
+            config: Arc::new(Config { output_dir: output_dir.to_str().unwrap().to_string(), ..Config::default() }),
+            snapshots: broadcast::channel(1).0,
+            events: Events::default(),
+            latest_books: LatestBooks::default(),
+            sinks: Vec::new(),
+            cooldowns: Cooldowns::default(),
+            rate_limiter: Arc::new(RateLimiter::new(0)),
+            clock,
+            pauses: Pauses::default(),
+            last_writes: LastWrites::default(),
+            metrics: Metrics::default(),
+        }
+    }
+
+    #[test]
+    fn test_files_rotate_at_mock_clock_hour() {
+        let output_dir = std::env::temp_dir().join("cex_worker_test_mock_clock");
+        let _ = std::fs::remove_dir_all(&output_dir);
+        let clock = Arc::new(MockClock::new(3_599_000));
+        let ctx = mock_context(clock.clone(), &output_dir);
+        let mut recorder = Recorder::new(Ticker::new("BTC_USDT").unwrap(), &ctx, &Schedule::Interval(1));
+
+        recorder.record(r#"{"n":1}"#, None);
+        clock.advance(999);
+        recorder.record(r#"{"n":2}"#, None);
+        clock.advance(1);
+        recorder.record(r#"{"n":3}"#, None);
+        recorder.close();
+
+        let dir = output_dir.join("STUB/BTC_USDT");
+        let records = |name: &str| -> Vec<String> {
+            std::fs::read_to_string(dir.join(name)).unwrap().lines().map(String::from).collect()
+        };
+        assert_eq!(records("0.json"), vec![
+            r#"{"time": 3599, "v": 1, "response": {"n":1}}"#,
+            r#"{"time": 3599, "v": 1, "response": {"n":2}}"#,
+        ]);
+        assert_eq!(records("3600.json"), vec![r#"{"time": 3600, "v": 1, "response": {"n":3}}"#]);
+        std::fs::remove_dir_all(&output_dir).unwrap();
+    }
+
+    #[test]
+    fn test_paused_recorder_writes_nothing() {
+        let output_dir = std::env::temp_dir().join("cex_worker_test_pause");
+        let _ = std::fs::remove_dir_all(&output_dir);
+        let clock = Arc::new(MockClock::new(0));
+        let ctx = mock_context(clock.clone(), &output_dir);
+        let mut recorder = Recorder::new(Ticker::new("BTC_USDT").unwrap(), &ctx, &Schedule::Interval(1));
+
+        ctx.pauses.set("BTC_USDT", true);
+        assert!(recorder.is_paused());
+        recorder.record(r#"{"n":1}"#, None);
+        recorder.record_timeout();
+        ctx.pauses.set("BTC_USDT", false);
+        clock.advance(1_000);
+        recorder.record(r#"{"n":2}"#, None);
+        recorder.close();
+
+        let contents = std::fs::read_to_string(output_dir.join("STUB/BTC_USDT/0.json")).unwrap();
+        assert_eq!(contents, "{\"time\": 1, \"v\": 1, \"response\": {\"n\":2}}\n");
+        std::fs::remove_dir_all(&output_dir).unwrap();
+    }
+
+    #[test]
+    fn test_recorder_counts_metrics() {
+        let output_dir = std::env::temp_dir().join("cex_worker_test_metrics");
+        let _ = std::fs::remove_dir_all(&output_dir);
+        let ctx = mock_context(Arc::new(MockClock::new(0)), &output_dir);
+        let mut recorder = Recorder::new(Ticker::new("BTC_USDT").unwrap(), &ctx, &Schedule::Interval(1));
+
+        recorder.record(r#"{"n":1}"#, None);
+        recorder.record(r#"{"n":2}"#, None);
+        recorder.count_fetch_error();
+        recorder.close();
+
+        let rendered = ctx.metrics.render();
+        assert!(rendered.contains("snapshots_written_total{exchange=\"STUB\",symbol=\"BTC_USDT\"} 2"), "{}", rendered);
+        assert!(rendered.contains("fetch_errors_total{exchange=\"STUB\",symbol=\"BTC_USDT\"} 1"), "{}", rendered);
+        std::fs::remove_dir_all(&output_dir).unwrap();
+    }
+
+    #[test]
+    fn test_fetched_book_is_not_parsed_again() {
+        let output_dir = std::env::temp_dir().join("cex_worker_test_fetched_book");
+        let _ = std::fs::remove_dir_all(&output_dir);
+        let ctx = mock_context(Arc::new(MockClock::new(0)), &output_dir);
+        let ctx = WorkerContext { config: Arc::new(Config { store_depth: Some(1), ..(*ctx.config).clone() }), ..ctx };
+        let mut recorder = Recorder::new(Ticker::new("BTC_USDT").unwrap(), &ctx, &Schedule::Interval(1));
+
+        // The stub cannot parse any response, so the stored book is the one fetched with it
+        let book = OrderBook { bids: vec![(1.0, 2.0)], asks: vec![(3.0, 4.0)], ..Default::default() };
+        recorder.record_fetched(FetchedBook { response_text: "{}".to_string(), book: Some(book), latency_ms: 5 });
+        recorder.close();
+
+        let contents = std::fs::read_to_string(output_dir.join("STUB/BTC_USDT/0.json")).unwrap();
+        assert!(contents.contains(r#""book": {"bids":[[1.0,2.0]],"asks":[[3.0,4.0]]"#), "{}", contents);
+        std::fs::remove_dir_all(&output_dir).unwrap();
+    }
+
+    #[test]
+    fn test_failed_writes_are_not_counted() {
+        let output_dir = std::env::temp_dir().join("cex_worker_test_failed_writes");
+        let _ = std::fs::remove_dir_all(&output_dir);
+        let ctx = mock_context(Arc::new(MockClock::new(0)), &output_dir);
+        // The data file cannot be opened with a directory in its place
+        std::fs::create_dir_all(output_dir.join("STUB/BTC_USDT/0.json")).unwrap();
+        let mut recorder = Recorder::new(Ticker::new("BTC_USDT").unwrap(), &ctx, &Schedule::Interval(1));
+
+        recorder.record(r#"{"n":1}"#, None);
+        recorder.close();
+
+        assert_eq!(ctx.last_writes.get("STUB", "BTC_USDT"), None);
+        assert!(!ctx.metrics.render().contains("snapshots_written_total{"));
+        std::fs::remove_dir_all(&output_dir).unwrap();
+    }
+
+    struct FailingSink;
+
+    impl Sink for FailingSink {
+        fn publish(&self, _exchange: &str, _symbol: &str, _record: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+            Err("queue is full".into())
+        }
+    }
+
+    #[test]
+    fn test_records_no_sink_took_are_not_counted() {
+        let output_dir = std::env::temp_dir().join("cex_worker_test_failed_sink");
+        let _ = std::fs::remove_dir_all(&output_dir);
+        let ctx = mock_context(Arc::new(MockClock::new(0)), &output_dir);
+        let ctx = WorkerContext {
+            config: Arc::new(Config { write_files: false, ..(*ctx.config).clone() }),
+            sinks: vec![Arc::new(FailingSink)],
+            ..ctx
+        };
+        let mut recorder = Recorder::new(Ticker::new("BTC_USDT").unwrap(), &ctx, &Schedule::Interval(1));
+
+        recorder.record(r#"{"n":1}"#, None);
+        recorder.close();
+
+        assert_eq!(ctx.last_writes.get("STUB", "BTC_USDT"), None);
+        assert!(!ctx.metrics.render().contains("snapshots_written_total{"));
+        std::fs::remove_dir_all(&output_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_run_batch_splits_responses_per_ticker() {
+        let output_dir = std::env::temp_dir().join("cex_worker_test_run_batch");
+        let _ = std::fs::remove_dir_all(&output_dir);
+        let clock = Arc::new(MockClock::new(0));
+        let ctx = mock_context(clock.clone(), &output_dir);
+        let batches = vec![
+            vec![Some(r#"{"n":"a1"}"#.to_string()), Some(r#"{"n":"b1"}"#.to_string())],
+            vec![Some(r#"{"n":"a2"}"#.to_string()), None],
+            vec![Some(r#"{"n":"a3"}"#.to_string())],
+        ];
+        let api = BatchApi { batches: Mutex::new(batches), clock, alive: ctx.alive.clone() };
+        let config = Config {
+            stale_fill: true,
+            fetch_retry: FetchRetryConfig { max_retries: 0, ..FetchRetryConfig::default() },
+            ..(*ctx.config).clone()
+        };
+        let ctx = WorkerContext { api: Arc::new(api), config: Arc::new(config), ..ctx };
+        let tickers = vec![Ticker::new("BTC_USDT").unwrap(), Ticker::new("ETH_USDT").unwrap()];
+
+        run_batch(tickers, Schedule::Interval(1), ctx).await;
+
+        let records = |symbol: &str| -> Vec<(String, bool)> {
+            std::fs::read_to_string(output_dir.join(format!("STUB/{}/0.json", symbol))).unwrap().lines()
+                .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+                .map(|record| (record["response"]["n"].as_str().unwrap().to_string(), record["stale"] == true))
+                .collect()
+        };
+        // The missing entry stale-fills only its ticker; a short response fails the whole batch
+        assert_eq!(records("BTC_USDT"), vec![
+            ("a1".to_string(), false), ("a2".to_string(), false), ("a2".to_string(), true),
+        ]);
+        assert_eq!(records("ETH_USDT"), vec![
+            ("b1".to_string(), false), ("b1".to_string(), true), ("b1".to_string(), true),
+        ]);
+        std::fs::remove_dir_all(&output_dir).unwrap();
+    }
+
+    #[test]
+    fn test_unwritable_sequence_keeps_recording() {
+        let output_dir = std::env::temp_dir().join("cex_worker_test_unwritable_seq");
+        let _ = std::fs::remove_dir_all(&output_dir);
+        let ctx = mock_context(Arc::new(MockClock::new(0)), &output_dir);
+        let ctx = WorkerContext { config: Arc::new(Config { sequence_numbers: true, ..(*ctx.config).clone() }), ..ctx };
+        // The state file cannot be replaced by a directory in its place
+        std::fs::create_dir_all(output_dir.join("STUB/BTC_USDT/.seq")).unwrap();
+        let mut recorder = Recorder::new(Ticker::new("BTC_USDT").unwrap(), &ctx, &Schedule::Interval(1));
+
+        recorder.record(r#"{"n":1}"#, None);
+        recorder.close();
+
+        let contents = std::fs::read_to_string(output_dir.join("STUB/BTC_USDT/0.json")).unwrap();
+        assert_eq!(contents, "{\"time\": 0, \"v\": 1, \"response\": {\"n\":1}}\n");
+        std::fs::remove_dir_all(&output_dir).unwrap();
+    }
+
+    #[cfg(feature = "binance")]
+    #[test]
+    fn test_check_book_depth_accepts_short_books() {
+        let output_dir = std::env::temp_dir().join("cex_worker_test_short_book");
+        let ctx = WorkerContext {
+            api: Arc::new(crate::binance_api::BinanceApi::default()),
+            ..mock_context(Arc::new(MockClock::new(0)), &output_dir)
+        };
+        let ticker = Ticker::new("BTC_USDT").unwrap();
+        let short = r#"{"lastUpdateId":1,"bids":[["3","1"],["2","1"],["1","1"]],"asks":[["4","1"],["5","1"],["6","1"]]}"#;
+
+        let book = check_book_depth(&ctx, &ticker, 10, short).unwrap().unwrap().with_requested_depth(10);
+        assert_eq!((book.bids.len(), book.asks.len(), book.requested_depth), (3, 3, Some(10)));
+        let error = check_book_depth(&ctx, &ticker, 10, r#"{"lastUpdateId":1,"bids":[],"asks":[]}"#).unwrap_err();
+        assert_eq!(error.to_string(), "Empty order book of BTC_USDT on BINANCE");
+        assert!(check_book_depth(&ctx, &ticker, 10, r#"{"unexpected":true}"#).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_next_fetch_aligns_to_interval() {
+        let clock = MockClock::new(1_500);
+        let schedule = Schedule::Interval(2);
+        let mut timer = FetchTimer::new(&schedule);
+        assert_eq!(timer.next(&clock), Some((2_000, 500)));
+        clock.set(4_000);
+        assert_eq!(timer.next(&clock), Some((4_000, 0)));
+        // A fetch returning within the millisecond of its tick waits for the next tick
+        assert_eq!(timer.next(&clock), Some((6_000, 2_000)));
+        clock.set(6_001);
+        assert_eq!(timer.next(&clock), Some((8_000, 1_999)));
+        // Ticks a slow fetch ran past are skipped
+        clock.set(12_500);
+        assert_eq!(timer.next(&clock), Some((14_000, 1_500)));
+    }
+
+    #[tokio::test]
+    async fn test_with_retry_backs_off_until_success() {
+        let retry = FetchRetryConfig { max_retries: 3, base_delay_ms: 10 };
+        let alive = AtomicBool::new(true);
+        let attempts = std::cell::Cell::new(0);
+        let started = Instant::now();
+        let result = with_retry(&retry, &alive, || async {
+            attempts.set(attempts.get() + 1);
+            match attempts.get() {
+                3 => Ok("book"),
+                _ => Err("connection reset".into()),
+            }
+        }).await;
+        assert_eq!(result.unwrap(), "book");
+        assert_eq!(attempts.get(), 3);
+        assert!(started.elapsed() >= Duration::from_millis(30));
+    }
+
+    #[tokio::test]
+    async fn test_with_retry_gives_up() {
+        let retry = FetchRetryConfig { max_retries: 2, base_delay_ms: 1 };
+        let alive = AtomicBool::new(true);
+        let attempts = std::cell::Cell::new(0);
+        let result: Result<(), _> = with_retry(&retry, &alive, || async {
+            attempts.set(attempts.get() + 1);
+            Err("connection reset".into())
+        }).await;
+        assert!(result.is_err());
+        assert_eq!(attempts.get(), 3);
+
+        // Neither bans nor stopped workers are retried
+        attempts.set(0);
+        let _: Result<(), _> = with_retry(&retry, &alive, || async {
+            attempts.set(attempts.get() + 1);
+            Err(Box::new(RateLimitBan { retry_after: None }) as Box<dyn Error>)
+        }).await;
+        assert_eq!(attempts.get(), 1);
+        alive.store(false, Ordering::SeqCst);
+        attempts.set(0);
+        let _: Result<(), _> = with_retry(&retry, &alive, || async {
+            attempts.set(attempts.get() + 1);
+            Err("connection reset".into())
+        }).await;
+        assert_eq!(attempts.get(), 1);
+    }
+
+    #[test]
+    fn test_cooldowns_keep_the_longest_ban() {
+        let cooldowns = Cooldowns::default();
+        assert_eq!(cooldowns.remaining("BINANCE"), None);
+
+        cooldowns.start("BINANCE", Duration::from_secs(60));
+        cooldowns.start("BINANCE", Duration::from_secs(1));
+        assert!(cooldowns.remaining("BINANCE").unwrap() > Duration::from_secs(30));
+        assert_eq!(cooldowns.remaining("BITKUB"), None);
+    }
+
+    #[tokio::test]
+    async fn test_with_deadline_cancels_slow_fetch() {
+        assert_eq!(with_deadline(None, async { 1 }).await, Some(1));
+        assert_eq!(with_deadline(Some(1000), async { 1 }).await, Some(1));
+
+        let slow = async {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            1
+        };
+        assert_eq!(with_deadline(Some(10), slow).await, None);
+    }
+}